@@ -0,0 +1,95 @@
+//! Generates the vendor and architecture tables from the files in data/
+
+use std::{collections::HashSet, env, fmt::Write, fs, path::Path};
+
+const VENDOR_DATA: &str = "data/jep106.txt";
+const ARCHITECTURE_DATA: &str = "data/marchid.txt";
+
+fn main() {
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+
+    let mut vendors = String::from("&[\n");
+    let mut seen = HashSet::new();
+    for (line, fields) in entries(VENDOR_DATA) {
+        let [bank, id, name] = fields;
+        let bank: u8 = bank
+            .parse()
+            .unwrap_or_else(|_| panic!("{VENDOR_DATA}:{line}: bad bank {bank:?}"));
+        let id =
+            parse_hex(&id).unwrap_or_else(|| panic!("{VENDOR_DATA}:{line}: bad ID code {id:?}"));
+        if bank == 0 {
+            panic!("{VENDOR_DATA}:{line}: banks are numbered from 1");
+        }
+        if id.count_ones() & 1 == 0 {
+            panic!("{VENDOR_DATA}:{line}: ID code 0x{id:02x} does not have odd parity");
+        }
+        if id & 0x7f == 0x7f {
+            panic!("{VENDOR_DATA}:{line}: 0x{id:02x} is the continuation code");
+        }
+        if !seen.insert((bank, id)) {
+            panic!("{VENDOR_DATA}:{line}: bank {bank} ID 0x{id:02x} is listed twice");
+        }
+        writeln!(vendors, "    ({name:?}, 0x{id:02x}, {bank}),").unwrap();
+    }
+    vendors.push(']');
+
+    let mut architectures = String::from("&[\n");
+    let mut seen = HashSet::new();
+    for (line, fields) in entries(ARCHITECTURE_DATA) {
+        let [id, name] = fields;
+        let id: u32 = id
+            .parse()
+            .unwrap_or_else(|_| panic!("{ARCHITECTURE_DATA}:{line}: bad marchid {id:?}"));
+        if id & (1 << 31) != 0 {
+            panic!("{ARCHITECTURE_DATA}:{line}: marchid {id} is not an open-source ID");
+        }
+        if !seen.insert(id) {
+            panic!("{ARCHITECTURE_DATA}:{line}: marchid {id} is listed twice");
+        }
+        writeln!(architectures, "    ({name:?}, {id}),").unwrap();
+    }
+    architectures.push(']');
+
+    let generated = format!(
+        "/// A list of vendor names\n\
+         /// fields: display name, ID, Bank,\n\
+         pub const VENDORS: &[(&str, u8, u8)] = {vendors};\n\n\
+         /// A list of open-source core names\n\
+         /// fields: display name, marchid,\n\
+         pub const ARCHITECTURES: &[(&str, u32)] = {architectures};\n"
+    );
+    fs::write(Path::new(&out_dir).join("tables.rs"), generated).unwrap();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={VENDOR_DATA}");
+    println!("cargo:rerun-if-changed={ARCHITECTURE_DATA}");
+}
+
+/// The non-empty, non-comment lines of a data file, with their line numbers,
+/// split into N whitespace separated fields where the last takes the rest of the line
+fn entries<const N: usize>(path: &str) -> Vec<(usize, [String; N])> {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("reading {path}: {e}"));
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let mut rest = line;
+            let fields = core::array::from_fn(|field| {
+                if field == N - 1 {
+                    return rest.to_string();
+                }
+                let (value, remainder) = rest
+                    .split_once(char::is_whitespace)
+                    .unwrap_or_else(|| panic!("{path}:{number}: expected {N} fields"));
+                rest = remainder.trim_start();
+                value.to_string()
+            });
+            (number, fields)
+        })
+        .collect()
+}
+
+fn parse_hex(value: &str) -> Option<u8> {
+    u8::from_str_radix(value.strip_prefix("0x")?, 16).ok()
+}
@@ -1,4 +1,83 @@
 #![no_std]
-/// A list of vendor names
-/// fields: display name, ID, Bank,
-pub const VENDORS: &[(&str, u8, u8)] = &[];
+//! Names for the values of the mvendorid and marchid csrs.
+//!
+//! The tables are generated by build.rs from the files in data/.
+
+include!(concat!(env!("OUT_DIR"), "/tables.rs"));
+
+/// Most significant bit of a 32-bit marchid, set for commercial
+/// implementations whose IDs are chosen by the vendor
+const COMMERCIAL_MARCHID: u32 = 1 << 31;
+
+/// A JEDEC manufacturer ID, as encoded in mvendorid
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JedecId {
+    /// the JEP106 bank, numbered from 1
+    pub bank: u32,
+    /// the final byte of the ID code, without its parity bit
+    pub offset: u8,
+}
+
+impl JedecId {
+    /// Splits mvendorid into the number of continuation codes in
+    /// bits 31:7 and the final byte of the ID in bits 6:0.
+    /// Returns None for the zero value of a non-commercial implementation.
+    ///
+    /// ```
+    /// # use gen_mvendorid::JedecId;
+    /// assert_eq!(JedecId::decode(0x489), Some(JedecId { bank: 10, offset: 0x09 }));
+    /// assert_eq!(JedecId::decode(0), None);
+    /// ```
+    pub const fn decode(mvendorid: u32) -> Option<JedecId> {
+        if mvendorid == 0 {
+            return None;
+        }
+        Some(JedecId {
+            bank: (mvendorid >> 7) + 1,
+            offset: (mvendorid & 0x7f) as u8,
+        })
+    }
+    /// Encodes the ID as it would be found in mvendorid
+    pub const fn mvendorid(&self) -> u32 {
+        (self.bank - 1) << 7 | self.offset as u32
+    }
+    /// Looks up the manufacturer in the JEP106 table
+    pub fn name(&self) -> Option<&'static str> {
+        VENDORS
+            .iter()
+            .find(|(_, id, bank)| *bank as u32 == self.bank && id & 0x7f == self.offset)
+            .map(|(name, _, _)| *name)
+    }
+}
+
+/// The name of the vendor in mvendorid, if it is known
+///
+/// ```
+/// # use gen_mvendorid::vendor_name;
+/// assert_eq!(vendor_name(0x489), Some("SiFive Inc"));
+/// assert_eq!(vendor_name(0x31e), Some("Andes Technology Corporation"));
+/// assert_eq!(vendor_name(0), None);
+/// ```
+pub fn vendor_name(mvendorid: usize) -> Option<&'static str> {
+    JedecId::decode(mvendorid as u32)?.name()
+}
+
+/// The name of the open-source core in marchid, if it is known.
+/// Commercial marchid values are specific to a vendor and are not looked up.
+///
+/// ```
+/// # use gen_mvendorid::architecture_name;
+/// assert_eq!(architecture_name(1), Some("Rocket"));
+/// assert_eq!(architecture_name(0x8000_0007), None);
+/// ```
+pub fn architecture_name(marchid: usize) -> Option<&'static str> {
+    // the most significant bit is at bit 31 of a sign-extended XLEN value
+    let marchid = marchid as u32;
+    if marchid & COMMERCIAL_MARCHID != 0 {
+        return None;
+    }
+    ARCHITECTURES
+        .iter()
+        .find(|(_, id)| *id == marchid)
+        .map(|(name, _)| *name)
+}
@@ -0,0 +1,19 @@
+//! Chooses the addresses the kernel is loaded and linked at, see src/linker/layout.lds
+
+/// offset from the physical load address to the kernel's virtual addresses,
+/// placing a kernel loaded at 0x8000_0000 at 0xffff_ffc0_8000_0000 in Sv39
+const HIGHER_HALF_OFFSET: &str = "0xffffffc000000000";
+
+/// room left below the kernel for SBI firmware, clear of
+/// what OpenSBI reserves for itself on qemu's virt machine
+const FIRMWARE_SIZE: &str = "0x200000";
+
+fn main() {
+    if std::env::var_os("CARGO_FEATURE_HIGHER_HALF").is_some() {
+        println!("cargo:rustc-link-arg-bins=--defsym=_kernel_offset={HIGHER_HALF_OFFSET}");
+    }
+    if std::env::var_os("CARGO_FEATURE_FIRMWARE").is_none() {
+        println!("cargo:rustc-link-arg-bins=--defsym=_firmware_size={FIRMWARE_SIZE}");
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -1,5 +1,39 @@
 use core::arch::global_asm;
 
-global_asm!(include_str!("assembly/boot.s"));
-global_asm!(include_str!("assembly/cpu.s"));
-global_asm!(include_str!("assembly/trap.s"));
+use crate::{
+    machine::{MACHINE_STACK_SIZE, MAX_HARTS},
+    stack::{HART_STACK_SIZE, TRAP_STACK_USABLE},
+    trap::{TrapFrame, FS_DIRTY, FS_SHIFT},
+};
+
+#[cfg(all(feature = "higher_half", not(feature = "firmware")))]
+compile_error!("higher_half turns paging on in boot.s, which needs the firmware feature");
+
+#[cfg(feature = "firmware")]
+global_asm!(
+    include_str!("assembly/boot.s"),
+    max_harts = const MAX_HARTS,
+    hart_stack_size = const HART_STACK_SIZE,
+);
+#[cfg(not(feature = "firmware"))]
+global_asm!(
+    include_str!("assembly/sbi_boot.s"),
+    max_harts = const MAX_HARTS,
+    hart_stack_size = const HART_STACK_SIZE,
+);
+global_asm!(
+    include_str!("assembly/hart.s"),
+    hart_stack_size = const HART_STACK_SIZE,
+);
+global_asm!(
+    include_str!("assembly/trap.s"),
+    frame_regs = const TrapFrame::REGS_OFFSET,
+    frame_fregs = const TrapFrame::FREGS_OFFSET,
+    frame_trap_stack = const TrapFrame::TRAP_STACK_OFFSET,
+    frame_hartid = const TrapFrame::HARTID_OFFSET,
+    frame_fcsr = const TrapFrame::FCSR_OFFSET,
+    fs_shift = const FS_SHIFT,
+    fs_dirty = const FS_DIRTY,
+    machine_stack_size = const MACHINE_STACK_SIZE,
+    supervisor_stack_size = const TRAP_STACK_USABLE,
+);
@@ -38,82 +38,137 @@ _start:
 	# setup stack pointer per layout
 	la		sp, _stack_end
 
-	# set previous MPP to Machine
-	# so mret won't pop us out of Machine privilege
+	# let supervisor mode reach devices and memory
+	la		a0, _memory_start
+	la		a1, _memory_end
+	call	kinit_pmp
+
+	# let supervisor mode read the counters
+	call	kinit_counters
+
+	# find out what this hart supports, while
+	# satp and misa are still ours to touch
+	call	kinit_features
+
+	# trap vectors, for traps from kinit on
+	call	kinit_trap_vector
+
+	# the kernel handles its own traps in supervisor mode
+	call	kinit_delegation
+
+	# set previous MPP to Supervisor
+	# so mret drops us into Supervisor privilege
 	# when we go to kinit
-	li		t0, 0b11 << 11
+	li		t0, 0b01 << 11
 	csrw	mstatus, t0
 
-	# disable interupts
-	csrw	mie, zero
+	# only other harts may interrupt machine mode
+	# until kinit, with their requests (see machine.rs)
+	li		t0, 1 << 3
+	csrw	mie, t0
 
 	# set MEPC to kinit so we will return there
 	# on mret
 	la		t1, kinit
 	csrw	mepc, t1
 
+	# kinit is given its hart id
+	csrr	a0, mhartid
+
 	# set return pointer so we come back here
 	# when kinit returns 
 	la		ra, 5f
 
-	# call kinit
-	mret
-3:
-	# set up mstatus for returning to rust
-	# set MPP to supervisor (11)
-	# set MPIE to enabled (7)
-	# set SPIE to enabled (5)
-	li		t0, (0b01 << 11) | (1 << 7) | (1 << 5)
-	csrw	mstatus, t0
+	# when linked to the higher half, turn on paging
+	# and enter kinit at its virtual address instead
+	ld		t2, _kernel_offset_value
+	bnez	t2, 6f
 
-	# set machine trap vector
-	la		t2, asm_trap_vector
-	csrw	mtvec, t2
-
-	# setup MEPC to kmain as before
-	la		t1, kinit_hart 
-	csrw	mepc, t1
-
-	# jump forward to park on return
-	#la		ra, 5f
+	# call kinit
 	mret
 
 # init other cores - address 124
 4: 
-	# give each hart a stack of 64kb
+	# harts past those machine.rs has a trap frame and
+	# stack for never show up to the kernel, and wait here
+	li		t1, {max_harts}
+	bgeu	t0, t1, 5f
+
+	# give each hart a stack of HART_STACK_SIZE (see stack.rs),
+	# the lowest page of which is a guard page
 	la		sp, _stack_end
-	li		t0, 0x10000
+	li		t0, {hart_stack_size}
 	csrr	a0, mhartid
 	mul		t0, t0, a0
 	sub		sp, sp, t0
 
-	# MPP machine mode (11)
-	# MPIE enabled
-	# SPIE enabled 
-	li		t0, 0b11 << 11 | (1 << 7) | (1 <<  5)
-	csrw	mstatus, t0
-
-	# turn on MSIE (machine software interupt enable)
-	li		t3, (1 << 3)
-	csrw	mie, t3
+	# each hart has its own PMP to set up
+	la		a0, _memory_start
+	la		a1, _memory_end
+	call	kinit_pmp
+	call	kinit_counters
+	call	kinit_trap_vector
+	call	kinit_delegation
 
-	# set exception pointer to rust hart init code
-	la		t1, kinit_hart
-	csrw	mepc, t1
+	# wait in machine mode until the kernel starts this
+	# hart through SBI, entering it at _start_hart (see hart.s)
+	csrr	a0, mhartid
+	call	kinit_park
 
-	# set up hart's trap vector
-	la		t2, asm_trap_vector
-	csrw	mtvec, t2
+# park if kinit returns
+5:
+	wfi
+	j		5b
 
-	# set return pointer so we come back here
-	# when kinit_hart returns 
-	la		ra, 5f
+# higher half trampoline, t2 holds the kernel's virtual offset
+# and we are still running at physical addresses
+6:
+	# identity map the low 4GiB with gigapages
+	# so devices and physical memory stay reachable
+	la		t3, _boot_page_table
+	li		t4, 0xcf		# valid, RWX, accessed, dirty
+	li		t5, 1 << 28		# one gigapage, in ppn position
+	li		t6, 4
+7:
+	sd		t4, (t3)
+	add		t4, t4, t5
+	addi	t3, t3, 8
+	addi	t6, t6, -1
+	bnez	t6, 7b
+
+	# map the gigapage holding the kernel at its virtual address
+	la		t3, _text_start
+	add		t4, t3, t2
+	srli	t4, t4, 30
+	andi	t4, t4, 0x1ff
+	slli	t4, t4, 3
+	la		t5, _boot_page_table
+	add		t5, t5, t4
+	srli	t3, t3, 30
+	slli	t3, t3, 28
+	ori		t3, t3, 0xcf
+	sd		t3, (t5)
+
+	# turn on Sv39 with the boot page table
+	la		t3, _boot_page_table
+	srli	t3, t3, 12
+	li		t4, 8 << 60
+	or		t3, t3, t4
+	csrw	satp, t3
+	sfence.vma
+
+	# move everything to the virtual addresses
+	add		sp, sp, t2
+	add		gp, gp, t2
+	add		ra, ra, t2
+	add		t1, t1, t2
+	csrw	mepc, t1
 
-	# call kinit_hart
+	# call kinit
 	mret
 
-# initialize non-zero cores
-# and park
-5:
-	wfi
-	j		5b
+.section .bss
+.align 12
+# temporary page table used until kinit builds the kernel's own
+_boot_page_table:
+	.skip	4096
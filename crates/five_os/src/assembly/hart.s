@@ -0,0 +1,44 @@
+# shared by both boot paths: where harts started through SBI enter the kernel
+
+.option norvc
+.section .text
+
+.global _start_hart
+_start_hart:
+	# started in supervisor mode with translation off, its hart id
+	# in a0 and the kernel's satp as the opaque argument in a1
+	csrw	sie, zero
+.option push
+.option norelax
+	la		gp, _global_pointer
+.option pop
+
+	# the hart's stack, as given in boot.s
+	la		sp, _stack_end
+	li		t0, {hart_stack_size}
+	mul		t0, t0, a0
+	sub		sp, sp, t0
+
+	# move to the kernel's virtual addresses, which are only mapped
+	# there when linked to the higher half. then the fetch after satp
+	# is written faults, and the trap lands on the virtual label below
+	ld		t2, _kernel_offset_value
+	add		gp, gp, t2
+	add		sp, sp, t2
+	la		t0, 1f
+	add		t0, t0, t2
+	csrw	stvec, t0
+	sfence.vma
+	csrw	satp, a1
+	sfence.vma
+.align 2
+1:
+	# hart id still in a0
+	call	kinit_hart
+
+.section .rodata
+.align 3
+# offset from physical to virtual kernel addresses, see layout.lds
+.global _kernel_offset_value
+_kernel_offset_value:
+	.dword	_kernel_offset
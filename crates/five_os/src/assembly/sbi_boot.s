@@ -0,0 +1,54 @@
+# entry point under SBI firmware such as OpenSBI, which keeps machine mode
+# and loads the kernel above itself, used without the firmware feature
+
+.option norvc
+.section .text.init
+
+.global _start
+_start:
+	# entered in supervisor mode with translation off, the
+	# boot hart's id in a0 and the device tree in a1
+.option push
+.option norelax
+	la		gp, _global_pointer
+.option pop
+
+	csrw	satp, zero
+	# no interrupts until the kernel sets up its traps
+	csrw	sie, zero
+
+	# keep the hart id, any hart may be the boot hart
+	mv		s0, a0
+
+	# past the harts there are stacks for, as in boot.s,
+	# there is nowhere to run the kernel from, so wait here
+	li		t0, {max_harts}
+	bgeu	s0, t0, 3f
+
+	# zero bss section
+	la		a0, _bss_start
+	la		a1, _bss_end
+	bgeu	a0, a1, 2f
+1:
+	sd		zero, (a0)
+	addi	a0, a0, 8
+	bltu	a0, a1, 1b
+2:
+	# the hart's stack, as given in boot.s
+	la		sp, _stack_end
+	li		t0, {hart_stack_size}
+	mul		t0, t0, s0
+	sub		sp, sp, t0
+
+	# find out what this hart supports, with
+	# the firmware's help where it has to
+	call	kinit_supervisor_features
+
+	# kinit is given its hart id
+	mv		a0, s0
+	call	kinit
+
+# park if kinit returns
+3:
+	wfi
+	j		3b
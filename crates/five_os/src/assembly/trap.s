@@ -6,41 +6,136 @@
 .set NUM_GP_REGS, 32
 .set NUM_FP_REGS, 32
 .set REG_SIZE, 8
-.set MAX_CPUS, 8
+
+# offsets of the TrapFrame fields and the trap stack sizes, given by
+# assembly.rs from the rust definitions so the two can't disagree
+.set FRAME_REGS, {frame_regs}
+.set FRAME_FREGS, {frame_fregs}
+.set FRAME_TRAP_STACK, {frame_trap_stack}
+.set FRAME_HARTID, {frame_hartid}
+.set FRAME_FCSR, {frame_fcsr}
+.set FS_SHIFT, {fs_shift}
+.set FS_DIRTY, {fs_dirty}
+.set MACHINE_STACK_SIZE, {machine_stack_size}
+.set SUPERVISOR_STACK_SIZE, {supervisor_stack_size}
 
 .macro save_gp i, basereg=t6
-	sd	x\i, ((\i)*REG_SIZE)(\basereg)
+	sd	x\i, (FRAME_REGS+(\i)*REG_SIZE)(\basereg)
 .endm
 .macro load_gp i, basereg=t6
-	ld	x\i, ((\i)*REG_SIZE)(\basereg)
+	ld	x\i, (FRAME_REGS+(\i)*REG_SIZE)(\basereg)
 .endm
 .macro save_fp i, basereg=t6
-	fsd	f\i, ((NUM_GP_REGS+(\i))*REG_SIZE)(\basereg)
+	fsd	f\i, (FRAME_FREGS+(\i)*REG_SIZE)(\basereg)
 .endm
 .macro load_fp i, basereg=t6
-	fld	f\i, ((NUM_GP_REGS+(\i))*REG_SIZE)(\basereg)
+	fld	f\i, (FRAME_FREGS+(\i)*REG_SIZE)(\basereg)
 .endm
 
 
-.align 4
-.global asm_trap_vector
-asm_trap_vector:
-.option push
-.option norelax # ensures the following assembly is not relaxed by the linker
+# saves the interrupted registers to the trap frame held in the scratch
+# csr, leaving the frame in t5 and the frame's trap stack in sp. A trap
+# taken while already on the trap stack has overwritten the frame of the
+# one it interrupted, so goes to rust_nested_trap instead, on the stack
+# it interrupted to leave the outer handler's frames intact. stack_size
+# stops above the trap stack's guard page, so a fault with sp in the guard
+# page, the handler overflowing, starts again from the top of the stack
+# where it can be reported, rather than faulting again on every push
+.macro save_frame scratch, cause, epc, stack_size
 	# swap csr with t6
-	csrrw	t6, mscratch, t6
+	csrrw	t6, \scratch, t6
 
-.set i, 1
-.rept 30
+.set i, 0
+.rept 31
 	save_gp %i
 	.set i, i+1
 .endr
 
 	mv t5, t6
-	csrr t6, mscratch
+	csrr t6, \scratch
 	save_gp 31, t5
 
-	csrw mscratch, t5
+	csrw \scratch, t5
+
+	# nested if trap_stack - stack_size <= sp <= trap_stack, see is_nested
+	ld		t1, FRAME_TRAP_STACK(t5)
+	bgtu	sp, t1, 2f
+	li		t0, \stack_size
+	sub		t1, t1, t0
+	bltu	sp, t1, 2f
+	mv		a0, t5
+	csrr	a1, \cause
+	csrr	a2, \epc
+	call	rust_nested_trap
+2:
+	ld		sp, FRAME_TRAP_STACK(t5)
+.endm
+
+# saves f0-f31 and fcsr to the frame in t5, only if the interrupted code
+# dirtied them (status.FS is Dirty, see saves_fp_state), leaving the status at entry in s1, which
+# the handler preserves for restore_fp_state
+.macro save_fp_state status
+	csrr	s1, \status
+	srli	t0, s1, FS_SHIFT
+	andi	t0, t0, 0b11
+	li		t1, FS_DIRTY
+	bne		t0, t1, 1f
+.set i, 0
+.rept NUM_FP_REGS
+	save_fp %i, t5
+	.set i, i+1
+.endr
+	frcsr	t0
+	sd		t0, FRAME_FCSR(t5)
+1:
+.endm
+
+# restores the state saved by save_fp_state, if any, from the frame in
+# the scratch csr, before restore_frame puts back s1. With clean set, marks
+# FS Clean, as the frame now holds a copy of the registers, see
+# restored_fp_status
+.macro restore_fp_state status, scratch, clean
+	srli	t0, s1, FS_SHIFT
+	andi	t0, t0, 0b11
+	li		t1, FS_DIRTY
+	bne		t0, t1, 1f
+	csrr	t6, \scratch
+.set i, 0
+.rept NUM_FP_REGS
+	load_fp %i
+	.set i, i+1
+.endr
+	ld		t0, FRAME_FCSR(t6)
+	fscsr	t0
+.if \clean
+	li		t0, 1 << FS_SHIFT
+	csrc	\status, t0
+.endif
+1:
+.endm
+
+# restores the registers saved by save_frame
+.macro restore_frame scratch
+	csrr	t6, \scratch
+.set	i, 1
+.rept	31
+	load_gp %i
+	.set	i, i+1
+.endr
+.endm
+
+# machine mode entry, calls a rust handler taking the same arguments as
+# rust_trap and returns to the pc it gives back, through the mret at label.
+# Floating point state is left dirty, as supervisor mode has not saved it
+.macro machine_trap_entry name, handler, label
+.align 4
+.global \name
+\name:
+.option push
+.option norelax # ensures the following assembly is not relaxed by the linker
+	save_frame mscratch, mcause, mepc, MACHINE_STACK_SIZE
+	save_fp_state mstatus
+
 # load rust_trap arguments
 	csrr	a0, mepc
 	csrr	a1, mtval
@@ -48,21 +143,53 @@ asm_trap_vector:
 	csrr	a3, mhartid
 	csrr	a4, mstatus
 	mv		a5, t5
-	ld		sp, 520(a5)
-	call rust_trap
+	call \handler
 
 # restore registers and return
 	csrw	mepc, a0
-	csrr	t6, mscratch
-.set	i, 1
-.rept	31
-	load_gp %i
-	.set	i, i+1
-.endr
+	restore_fp_state mstatus, mscratch, 0
+	restore_frame mscratch
 
 .option pop
-.global trapbreak
-trapbreak:
+.global \label
+\label:
 	mret
+.endm
+
+# supervisor mode entry, as machine_trap_entry but returning with sret,
+# and taking the hart id from the trap frame as mhartid is machine mode only
+.macro supervisor_trap_entry name, handler
+.align 4
+.global \name
+\name:
+.option push
+.option norelax
+	save_frame sscratch, scause, sepc, SUPERVISOR_STACK_SIZE
+	save_fp_state sstatus
+
+	csrr	a0, sepc
+	csrr	a1, stval
+	csrr	a2, scause
+	ld		a3, FRAME_HARTID(t5)
+	csrr	a4, sstatus
+	mv		a5, t5
+	call \handler
+
+	csrw	sepc, a0
+	restore_fp_state sstatus, sscratch, 1
+	restore_frame sscratch
+.option pop
+	sret
+.endm
+
+# the kernel's traps, which machine mode delegates to supervisor mode
+supervisor_trap_entry asm_supervisor_trap_vector, rust_supervisor_trap
+
+# traps left to machine mode, and interrupts when mtvec is in direct mode
+machine_trap_entry asm_trap_vector, rust_trap, trapbreak
+
+# the machine interrupts' own entries in the vector table, see trap.rs
+machine_trap_entry asm_machine_software_vector, rust_machine_software, machine_software_mret
+machine_trap_entry asm_machine_timer_vector, rust_machine_timer, machine_timer_mret
 
 .option pop
@@ -0,0 +1,110 @@
+//! Steps run from boot.s in machine mode, before kinit drops to supervisor mode,
+//! or from sbi_boot.s in supervisor mode when SBI firmware owns machine mode.
+
+use fiveos_riscv::cpu::{
+    counters::{self, Counter},
+    features::CpuFeatures,
+    registers::{
+        csr::{
+            medeleg::{self, Medeleg},
+            mhartid,
+            mideleg::{self, Mideleg},
+        },
+        mtvec::{self, Mtvec},
+        pmp::{PmpBuilder, PmpConfig},
+    },
+};
+
+use crate::{machine, trap};
+
+/// Features of the boot hart, probed before kinit
+static mut CPU_FEATURES: Option<CpuFeatures> = None;
+
+/// Provides the features of the boot hart.
+pub fn cpu_features() -> &'static CpuFeatures {
+    // safety: only written by kinit_features, before kinit
+    unsafe { &*core::ptr::addr_of!(CPU_FEATURES) }
+        .as_ref()
+        .expect("cpu features are probed before kinit")
+}
+
+/// Program this hart's physical memory protection so supervisor mode can
+/// reach the devices below memory and memory itself, before boot.s leaves
+/// machine mode. Called on every hart, as each has its own PMP.
+///
+/// This runs at the physical address the kernel is loaded at, before
+/// any page table exists, so it is given the physical bounds of memory.
+#[no_mangle]
+pub extern "C" fn kinit_pmp(memory_start: usize, memory_end: usize) {
+    let mut pmp = PmpBuilder::new();
+    // devices below memory, unless memory starts at 0
+    if memory_start != 0 {
+        pmp = pmp.range(0, memory_start, PmpConfig::READ_WRITE);
+    }
+    pmp = pmp.range(memory_start, memory_end, PmpConfig::READ_WRITE_EXECUTE);
+    unsafe { pmp.apply() };
+}
+
+/// Let supervisor mode read this hart's cycle, time and instret counters,
+/// so the kernel can measure itself wherever kinit runs. Called on every hart.
+#[no_mangle]
+pub extern "C" fn kinit_counters() {
+    for counter in [Counter::Cycle, Counter::Time, Counter::Instret] {
+        unsafe { counters::allow_supervisor(counter) };
+    }
+}
+
+/// Probe the boot hart's features while still in machine mode
+/// with translation off, for [`cpu_features`].
+///
+/// Like kinit_pmp this runs at physical addresses, which is fine for
+/// writing a static through a pc-relative address.
+#[no_mangle]
+pub extern "C" fn kinit_features() {
+    unsafe { CPU_FEATURES = Some(CpuFeatures::probe()) };
+}
+
+/// Probe the boot hart's features from supervisor mode, when sbi_boot.s
+/// runs the kernel under SBI firmware rather than boot.s
+#[no_mangle]
+pub extern "C" fn kinit_supervisor_features() {
+    unsafe { CPU_FEATURES = Some(CpuFeatures::probe_supervisor()) };
+}
+
+/// Point this hart's machine traps at the vector table, so each machine
+/// interrupt has its own entry, or at the single entry on harts without
+/// vectored mode, and give them a trap frame. Called on every hart.
+#[no_mangle]
+pub extern "C" fn kinit_trap_vector() {
+    unsafe {
+        machine::init_hart(mhartid::read().bits());
+        if !mtvec::set_trap_vector(Mtvec::vectored(trap::machine_vectors())) {
+            mtvec::set_trap_vector(Mtvec::direct(trap::machine_trap_entry()));
+        }
+    }
+}
+
+/// Hand this hart's traps from supervisor and user mode to the kernel,
+/// which runs in supervisor mode. Machine mode keeps its own interrupts
+/// and the ecalls from supervisor mode, see machine.rs. Called on every hart.
+#[no_mangle]
+pub extern "C" fn kinit_delegation() {
+    let exceptions = Medeleg::new()
+        .with_instruction_misaligned(true)
+        .with_instruction_access_fault(true)
+        .with_illegal_instruction(true)
+        .with_breakpoint(true)
+        .with_load_misaligned(true)
+        .with_load_access_fault(true)
+        .with_store_misaligned(true)
+        .with_store_access_fault(true)
+        .with_user_ecall(true)
+        .with_instruction_page_fault(true)
+        .with_load_page_fault(true)
+        .with_store_page_fault(true);
+    let interrupts = Mideleg::new().with_ssi(true).with_sti(true).with_sei(true);
+    unsafe {
+        medeleg::write(exceptions);
+        mideleg::write(interrupts);
+    }
+}
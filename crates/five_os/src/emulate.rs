@@ -0,0 +1,221 @@
+//! Completes instructions the hart trapped on rather than ran: misaligned
+//! loads and stores, done a byte at a time, and reads of the time csr,
+//! from the CLINT, on harts which don't implement it.
+//!
+//! Each returns the pc after the instruction, for the trap to return to,
+//! or None when the instruction is not one emulated here.
+
+use fiveos_riscv::instruction::{self, CsrOp, CsrSource, Decoded, Instruction};
+use fiveos_virtio::clint;
+
+use crate::trap::TrapFrame;
+
+/// The time csr, read by rdtime
+const TIME: usize = 0xc01;
+
+/// Reads the instruction at pc, a half at a time, as a 4 byte instruction
+/// is only 2 byte aligned with compressed instructions. Longer instructions
+/// are cut to their first 4 bytes.
+///
+/// ## Safety
+/// pc must be the address of an instruction the kernel can read
+unsafe fn fetch(pc: usize) -> u32 {
+    let low = (pc as *const u16).read_volatile();
+    if instruction::length(low) == Some(2) {
+        return low as u32;
+    }
+    let high = ((pc + 2) as *const u16).read_volatile() as u32;
+    low as u32 | high << 16
+}
+
+/// The pc after the instruction at epc, to step over ebreak, which may be
+/// the compressed c.ebreak. tval only holds the instruction's bits for
+/// illegal instructions, so it is read from epc.
+///
+/// ## Safety
+/// epc must be the address of the trapping instruction, in kernel memory.
+pub unsafe fn skip_instruction(epc: usize) -> usize {
+    let low = (epc as *const u16).read_volatile();
+    match instruction::length(low) {
+        Some(length) => epc + length,
+        None => panic!("instruction of reserved length at 0x{:08x}", epc),
+    }
+}
+
+fn set_register(frame: &mut TrapFrame, register: usize, value: usize) {
+    // x0 is always zero
+    if register != 0 {
+        frame.regs[register] = value;
+    }
+}
+
+/// Completes the misaligned load or store at epc a byte at a time, using
+/// and updating the registers saved in the frame. Atomics are left alone,
+/// as they can't be split.
+///
+/// ## Safety
+/// epc must be the address of the trapping instruction, in kernel memory.
+/// A fault on one of the bytes is taken on the trap stack, so is fatal.
+pub unsafe fn misaligned_access(frame: &mut TrapFrame, epc: usize) -> Option<usize> {
+    let Decoded {
+        instruction,
+        length,
+    } = instruction::decode(fetch(epc))?;
+    match instruction {
+        Instruction::Load {
+            width,
+            signed,
+            rd,
+            rs1,
+            offset,
+        } => {
+            let address = frame.regs[rs1].wrapping_add_signed(offset);
+            let mut value = 0;
+            // little endian, so the last byte is the most significant
+            for byte in (0..width.bytes()).rev() {
+                let byte = address.wrapping_add(byte) as *const u8;
+                value = value << 8 | byte.read_volatile() as usize;
+            }
+            if signed {
+                value = instruction::sign_extend(value, width.bytes() as u32 * 8) as usize;
+            }
+            set_register(frame, rd, value);
+        }
+        Instruction::Store {
+            width,
+            rs1,
+            rs2,
+            offset,
+        } => {
+            let address = frame.regs[rs1].wrapping_add_signed(offset);
+            let value = frame.regs[rs2];
+            for byte in 0..width.bytes() {
+                let value = (value >> (byte * 8)) as u8;
+                (address.wrapping_add(byte) as *mut u8).write_volatile(value);
+            }
+        }
+        Instruction::Csr { .. } => return None,
+    }
+    Some(epc + length)
+}
+
+/// Emulates the illegal instruction at epc, if it is one the hart may be
+/// missing. Only reads of the time csr are, as rdtime.
+///
+/// tval holds the instruction's bits, when the hart reports them there,
+/// otherwise it is read from epc.
+///
+/// ## Safety
+/// epc must be the address of the trapping instruction, in kernel memory.
+pub unsafe fn illegal_instruction(frame: &mut TrapFrame, epc: usize, tval: usize) -> Option<usize> {
+    let bits = if tval != 0 { tval as u32 } else { fetch(epc) };
+    let Decoded {
+        instruction,
+        length,
+    } = instruction::decode(bits)?;
+    match instruction {
+        // time is read only, so only reads which don't try to write it
+        Instruction::Csr {
+            op: CsrOp::Set | CsrOp::Clear,
+            rd,
+            source: CsrSource::Register(0) | CsrSource::Immediate(0),
+            csr: TIME,
+        } => set_register(frame, rd, clint::mtime() as usize),
+        _ => return None,
+    }
+    Some(epc + length)
+}
+
+#[cfg(test)]
+mod tests {
+    use fiveos_riscv::instruction::{self, Instruction};
+
+    use super::misaligned_access;
+    use crate::trap::TrapFrame;
+
+    /// Little endian, so the value of a load from the start of it depends on
+    /// the width, and its sign on whether the top byte was reached
+    const MEMORY: [u8; 8] = [0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88];
+
+    /// Runs the load or store in a frame whose registers point it at a
+    /// misaligned address in memory, returning the frame
+    fn run(bits: u32, memory: &mut [u8]) -> TrapFrame {
+        let (rs1, offset) = match instruction::decode(bits).unwrap().instruction {
+            Instruction::Load { rs1, offset, .. } => (rs1, offset),
+            Instruction::Store { rs1, offset, .. } => (rs1, offset),
+            Instruction::Csr { .. } => unreachable!(),
+        };
+        let target = &mut memory[1] as *mut u8 as usize;
+        let mut frame = TrapFrame::zero();
+        frame.regs[rs1] = target.wrapping_add_signed(-offset);
+        // rs2 of every store below
+        for register in [9, 10, 12, 31] {
+            frame.regs[register] = 0x0807_0605_0403_0201;
+        }
+        // fetched a half at a time
+        let code = [bits as u16, (bits >> 16) as u16];
+        let epc = code.as_ptr() as usize;
+        let length = instruction::decode(bits).unwrap().length;
+        assert_eq!(
+            unsafe { misaligned_access(&mut frame, epc) },
+            Some(epc + length)
+        );
+        frame
+    }
+
+    #[test]
+    fn loads() {
+        // instruction, rd, value loaded
+        let table: [(&str, u32, usize, u64); 15] = [
+            ("lb a0, -1(a1)", 0xfff5_8503, 10, 0xffff_ffff_ffff_ff81),
+            ("lh a0, 2(a1)", 0x0025_9503, 10, 0xffff_ffff_ffff_8281),
+            ("lw a0, -2048(a1)", 0x8005_a503, 10, 0xffff_ffff_8483_8281),
+            ("ld a0, 2047(a1)", 0x7ff5_b503, 10, 0x8887_8685_8483_8281),
+            ("lbu t0, 3(s0)", 0x0034_4283, 5, 0x81),
+            ("lhu t0, -3(s0)", 0xffd4_5283, 5, 0x8281),
+            ("lwu t0, 5(s0)", 0x0054_6283, 5, 0x8483_8281),
+            ("c.lw a0, 4(a1)", 0x41c8, 10, 0xffff_ffff_8483_8281),
+            ("c.lw s1, 124(a5)", 0x5fe4, 9, 0xffff_ffff_8483_8281),
+            ("c.ld a0, 8(a1)", 0x6588, 10, 0x8887_8685_8483_8281),
+            ("c.ld s1, 248(a5)", 0x7fe4, 9, 0x8887_8685_8483_8281),
+            ("c.lwsp a0, 4(sp)", 0x4512, 10, 0xffff_ffff_8483_8281),
+            ("c.lwsp t6, 252(sp)", 0x5ffe, 31, 0xffff_ffff_8483_8281),
+            ("c.ldsp a0, 8(sp)", 0x6522, 10, 0x8887_8685_8483_8281),
+            ("c.ldsp t6, 504(sp)", 0x7ffe, 31, 0x8887_8685_8483_8281),
+        ];
+        for (name, bits, rd, value) in table {
+            let mut memory = [0; 16];
+            memory[1..9].copy_from_slice(&MEMORY);
+            let frame = run(bits, &mut memory);
+            assert_eq!(frame.regs[rd], value as usize, "{}", name);
+        }
+    }
+
+    #[test]
+    fn stores() {
+        // instruction, bytes stored
+        let table: [(&str, u32, usize); 12] = [
+            ("sb a2, -1(a3)", 0xfec6_8fa3, 1),
+            ("sh a2, 6(a3)", 0x00c6_9323, 2),
+            ("sw a2, -2048(a3)", 0x80c6_a023, 4),
+            ("sd a2, 2047(a3)", 0x7ec6_bfa3, 8),
+            ("c.sw a0, 4(a1)", 0xc1c8, 4),
+            ("c.sw s1, 124(a5)", 0xdfe4, 4),
+            ("c.sd a0, 8(a1)", 0xe588, 8),
+            ("c.sd s1, 248(a5)", 0xffe4, 8),
+            ("c.swsp a0, 4(sp)", 0xc22a, 4),
+            ("c.swsp t6, 252(sp)", 0xdffe, 4),
+            ("c.sdsp a0, 8(sp)", 0xe42a, 8),
+            ("c.sdsp t6, 504(sp)", 0xfffe, 8),
+        ];
+        for (name, bits, bytes) in table {
+            let mut memory = [0; 16];
+            run(bits, &mut memory);
+            let mut expected = [0; 16];
+            for byte in 0..bytes {
+                expected[1 + byte] = byte as u8 + 1;
+            }
+            assert_eq!(memory, expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Page faults, handed by the trap handler to a resolver which may fix up
+//! the mapping the access needed, by demand paging, copy on write or
+//! growing a stack, and have the faulting instruction run again.
+
+use fiveos_riscv::cpu::registers::{mcause::Exception, satp::Satp};
+
+use crate::slot::Slot;
+
+/// The kind of access which faulted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// instruction fetch
+    Execute,
+    Load,
+    /// store or atomic memory operation
+    Store,
+}
+
+impl Access {
+    /// The access a page fault exception was raised for, None for other exceptions
+    pub fn from_exception(exception: Exception) -> Option<Access> {
+        match exception {
+            Exception::InstructionPageFault => Some(Access::Execute),
+            Exception::LoadPageFault => Some(Access::Load),
+            Exception::StoreAMOPageFault => Some(Access::Store),
+            _ => None,
+        }
+    }
+}
+
+/// Everything known about a page fault when it is taken
+#[derive(Clone, Copy, Debug)]
+pub struct PageFault {
+    pub hart: usize,
+    /// address of the faulting instruction
+    pub epc: usize,
+    /// the virtual address which faulted, from stval
+    pub address: usize,
+    pub access: Access,
+    /// the access came from user mode, rather than the kernel
+    pub user: bool,
+    /// the address space the access was made in
+    pub satp: Satp,
+}
+
+/// What became of a page fault
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// the address is now mapped, return to the faulting instruction
+    Retry,
+    /// the access was not allowed, the faulting process can't continue
+    Kill,
+    /// the resolver doesn't handle faults at this address
+    Unresolved,
+}
+
+/// Resolves a page fault, called on the trap stack of the faulting hart
+pub type Resolver = fn(&PageFault) -> Resolution;
+
+/// The resolver, once set
+static RESOLVER: Slot<Resolver> = Slot::empty();
+
+/// Sets the resolver page faults are handed to, false if one is already set
+pub fn set_resolver(resolver: Resolver) -> bool {
+    RESOLVER.set(resolver)
+}
+
+/// Hands the fault to the resolver, Unresolved if there is none
+pub fn resolve(fault: &PageFault) -> Resolution {
+    match RESOLVER.get() {
+        Some(resolver) => resolver(fault),
+        None => Resolution::Unresolved,
+    }
+}
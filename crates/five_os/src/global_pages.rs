@@ -1,122 +1,92 @@
-use core::{arch::asm, fmt::Debug};
+use core::{
+    ops::Range,
+    ptr::{addr_of, addr_of_mut},
+};
 use fiveos_allocator::page::PageAllocator;
 
-use crate::{kernel_heap::HeapInfo, layout::LinkerLayout};
-use five_os::{trap::TrapFrame, *};
-use fiveos_peripherals::{print, print_title, println};
+use crate::{kernel_heap::HeapInfo, layout::LinkerLayout, memory_manager::kernel_zalloc};
+use five_os::{
+    fault::{self, Access, PageFault, Resolution},
+    kernel_regions::{
+        register_kernel_region, seal_kernel_memory_map, KernelMemoryMap, KernelRegion, RegionKind,
+    },
+    *,
+};
+use fiveos_riscv::cpu::registers::satp::Satp;
 use fiveos_riscv::mmu::{
     page_table::{thirty_nine::Sv39, AnyPageTable, PageTable, PAGE_SIZE},
-    set_translation_table, EntryFlags, TableTypes,
-};
-use fiveos_virtio::{
-    clint::{CLINT_BASE_ADDRESS, CLINT_END_ADDRESS},
-    plic::{PLIC_BASE_ADDRESS, PLIC_END_ADDRESS},
-    uart::{UART_BASE_ADDRESS, UART_END_ADDRESS},
+    set_translation_table, EntryFlags, PhysAddr, TableTypes, VirtAddr,
 };
 
 /// MMU page table for kernel
 static mut KMEM_PAGE_TABLE: AnyPageTable = AnyPageTable::Off;
 
-pub struct KernelMemoryMap([(&'static str, usize, usize, EntryFlags); 16]);
-
-impl Debug for KernelMemoryMap {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        print_title!(f, "Kernel Space Identity Map");
-
-        for (msg, start, end, flags) in self.0 {
-            if msg.len() > 0 {
-                println!(f, "{}: 0x{:x}-0x{:x} {:?}", msg, start, end, flags);
-            }
-        }
-        Ok(())
-    }
+/// Provides the kernel's page table, for inspection.
+///
+/// ## Safety
+/// Only valid after init_global_pages, and while the table is not being modified.
+pub unsafe fn kernel_page_table() -> &'static AnyPageTable {
+    &KMEM_PAGE_TABLE
 }
 
+/// Registers the regions the kernel image and its early allocations occupy,
+/// maps every region in the kernel memory map into a new kernel page table,
+/// then turns on translation with it.
+///
+/// Device regions should be registered with register_kernel_region beforehand,
+/// and the trap frames given their trap stacks.
+///
+/// ## Safety
+/// Accesses static mut, expected to only run once
+/// during kinit while other harts are parked
 pub unsafe fn init_global_pages(
     layout: &LinkerLayout,
     page_allocator: &mut PageAllocator<PAGE_SIZE>,
-    trap_stack: usize,
+    boot_hart: usize,
     kernel_heap_info: HeapInfo,
-) -> KernelMemoryMap {
+) -> &'static KernelMemoryMap {
     /////////////////////////////////////////////////////////////////////////////////////////
     // Hard-coded info about kernel's memory use
     /////////////////////////////////////////////////////////////////////////////////////////
-    let mut kernel_memory_map: [(&str, usize, usize, EntryFlags); 16] = [
-        // dynamic entry for kernel page table
-        ("", 0, 0, EntryFlags::READ),
-        // dynamic entry for further kernel pages
-        ("", 0, 0, EntryFlags::READ),
-        (
-            "Allocation Bitmap",
-            layout.heap_start,
-            layout.heap_start + (layout.heap_size / PAGE_SIZE),
-            EntryFlags::READ_EXECUTE,
-        ),
-        (
+    let kernel_image = [
+        KernelRegion::new(
             "Kernel Code Section",
-            layout.text_start,
-            layout.text_end,
+            layout.text_start..layout.text_end,
             EntryFlags::READ_EXECUTE,
+            RegionKind::Code,
         ),
-        (
+        KernelRegion::new(
             "Readonly Data Section",
-            layout.rodata_start,
-            layout.rodata_end,
-            EntryFlags::READ_EXECUTE,
+            layout.rodata_start..layout.rodata_end,
+            EntryFlags::READ,
+            RegionKind::Code,
         ),
-        (
+        KernelRegion::new(
             "Data Section",
-            layout.data_start,
-            layout.data_end,
+            layout.data_start..layout.data_end,
             EntryFlags::READ_WRITE,
+            RegionKind::Data,
         ),
-        (
+        KernelRegion::new(
             "BSS section",
-            layout.bss_start,
-            layout.bss_end,
+            layout.bss_start..layout.bss_end,
             EntryFlags::READ_WRITE,
+            RegionKind::Data,
         ),
-        (
+        KernelRegion::new(
             "Kernel Stack",
-            layout.stack_start,
-            layout.stack_end,
+            layout.stack_start..layout.stack_end,
             EntryFlags::READ_WRITE,
+            RegionKind::Data,
         ),
-        (
-            "Hardware UART",
-            UART_BASE_ADDRESS,
-            UART_END_ADDRESS,
-            EntryFlags::READ_WRITE,
-        ),
-        (
-            "Hardware CLINT, MSIP",
-            CLINT_BASE_ADDRESS,
-            CLINT_END_ADDRESS,
-            EntryFlags::READ_WRITE,
-        ),
-        (
-            "Hardware PLIC",
-            PLIC_BASE_ADDRESS,
-            PLIC_END_ADDRESS,
-            EntryFlags::READ_WRITE,
-        ),
-        (
-            "Hardware ????",
-            0x0c20_0000,
-            0x0c20_8000,
-            EntryFlags::READ_WRITE,
-        ),
-        (
-            "Trap stack",
-            trap_stack,
-            trap_stack + PAGE_SIZE,
-            EntryFlags::READ_WRITE,
-        ),
-        // unused entries
-        ("", 0, 0, EntryFlags::READ),
-        ("", 0, 0, EntryFlags::READ),
-        ("", 0, 0, EntryFlags::READ),
     ];
+    for region in kernel_image {
+        register(region);
+    }
+
+    if !boot::cpu_features().paging.supports(TableTypes::Sv39) {
+        panic!("address translation not supported on this processor.");
+    }
 
     /////////////////////////////////////////////////////////////////////////////////////////
     // initialize kernel root page table
@@ -126,53 +96,149 @@ pub unsafe fn init_global_pages(
     KMEM_PAGE_TABLE = AnyPageTable::Sv39(PageTable::in_place(kpt, Sv39));
     let kpt = &KMEM_PAGE_TABLE;
 
-    /////////////////////////////////////////////////////////////////////////////////////////
-    // setup SATP
-    /////////////////////////////////////////////////////////////////////////////////////////
     let satp_val = 8 << 60 | (kpta >> 12);
-    if !set_translation_table(TableTypes::Sv39, kpta) {
-        panic!("address translation not supported on this processor.");
-    }
 
     /////////////////////////////////////////////////////////////////////////////////////////
     // add kernel dynamic memory info to memory map
     /////////////////////////////////////////////////////////////////////////////////////////
-    {
-        kernel_memory_map[0] = ("Kernel Root Page Table", kpta, kpta, EntryFlags::READ_WRITE);
+    if layout.kernel_offset != 0 {
+        // linked to the higher half, so the physical memory the page
+        // allocator hands out is not covered by the kernel's sections
+        register(KernelRegion::new(
+            "Physical Memory",
+            layout.physical(layout.heap_start)..layout.physical(layout.memory_end - 1) + 1,
+            EntryFlags::READ_WRITE,
+            RegionKind::Dynamic,
+        ));
+    } else {
         let HeapInfo { start, end, .. } = kernel_heap_info;
-        kernel_memory_map[1] = ("Kernel Dynamic Memory", start, end, EntryFlags::READ_WRITE);
+        let bitmap_start = layout.physical(layout.heap_start);
+        let dynamic = [
+            KernelRegion::new(
+                "Allocation Bitmap",
+                bitmap_start..bitmap_start + (layout.heap_size / PAGE_SIZE),
+                EntryFlags::READ_WRITE,
+                RegionKind::Dynamic,
+            ),
+            KernelRegion::new(
+                "Kernel Root Page Table",
+                kpta..kpta + PAGE_SIZE,
+                EntryFlags::READ_WRITE,
+                RegionKind::Dynamic,
+            ),
+            KernelRegion::new(
+                "Kernel Dynamic Memory",
+                start..end,
+                EntryFlags::READ_WRITE,
+                RegionKind::Dynamic,
+            ),
+        ];
+        for region in dynamic {
+            register(region);
+        }
+        for frame in (*addr_of!(trap::GLOBAL_TRAPFRAMES)).iter() {
+            if frame.trap_stack.is_null() {
+                continue;
+            }
+            let guard = stack::trap_stack_guard(frame.trap_stack as usize);
+            register(KernelRegion::new(
+                "Trap stack",
+                guard.end..frame.trap_stack as usize,
+                EntryFlags::READ_WRITE,
+                RegionKind::Dynamic,
+            ));
+        }
     }
+    // everything the kernel maps is registered by now, later regions would not be mapped
+    let kernel_memory_map = seal_kernel_memory_map();
     let mut kernel_zalloc =
         |count: usize| -> Option<*mut u8> { page_allocator.zalloc(count).map(|p| p as *mut u8) };
     {
-        let global_trapframe_address = {
-            let frame: &mut TrapFrame = &mut trap::GLOBAL_TRAPFRAMES[0];
-            frame.satp = satp_val;
-            frame.trap_stack = (trap_stack + PAGE_SIZE) as *mut _;
-            let global_trapframe_address = frame as *mut TrapFrame as usize;
+        {
+            // the trap frames themselves are mapped with the kernel's dynamic memory
+            for frame in (*addr_of_mut!(trap::GLOBAL_TRAPFRAMES)).iter_mut() {
+                frame.satp = satp_val;
+            }
 
-            // store for use in trap handler
-            asm!("csrw mscratch, {}", in(reg) global_trapframe_address);
-            asm!("csrw sscratch, {}", in(reg) global_trapframe_address);
-            global_trapframe_address
-        };
+            // for use in trap handler, which runs in supervisor mode
+            // with the translation turned on below
+            trap::install_trap_frame(boot_hart);
+        }
 
         match kpt {
             AnyPageTable::Sv39(kpt) => {
-                kpt.identity_map(
-                    global_trapframe_address,
-                    global_trapframe_address + PAGE_SIZE,
-                    EntryFlags::READ_WRITE,
-                    &mut kernel_zalloc,
-                );
-                for (msg, start, end, flags) in kernel_memory_map {
-                    if msg.len() > 0 {
-                        kpt.identity_map(start, end, flags, &mut kernel_zalloc);
-                    }
+                for region in kernel_memory_map.regions() {
+                    let Range { start, end } = region.range;
+                    kpt.map(
+                        VirtAddr::new(start),
+                        PhysAddr::new(layout.physical(start)),
+                        end - start,
+                        region.flags,
+                        &mut kernel_zalloc,
+                    );
+                }
+
+                // leave the bottom page of every stack unmapped, so overflowing
+                // it faults instead of running into whatever is below
+                let hart_stack_guards = (0..stack::hart_stack_count(layout))
+                    .map(|hart| stack::hart_stack_guard(layout, hart));
+                let trap_stack_guards = (*addr_of!(trap::GLOBAL_TRAPFRAMES))
+                    .iter()
+                    .filter(|frame| !frame.trap_stack.is_null())
+                    .map(|frame| stack::trap_stack_guard(frame.trap_stack as usize));
+                for guard in hart_stack_guards.chain(trap_stack_guards) {
+                    kpt.unmap(
+                        VirtAddr::new(guard.start),
+                        guard.end - guard.start,
+                        &mut kernel_zalloc,
+                    );
                 }
             }
             _ => todo!(),
         }
     }
-    KernelMemoryMap(kernel_memory_map)
+
+    /////////////////////////////////////////////////////////////////////////////////////////
+    // setup SATP, once the table covers the code we are running
+    /////////////////////////////////////////////////////////////////////////////////////////
+    if !set_translation_table(TableTypes::Sv39, kpta) {
+        panic!("address translation not supported on this processor.");
+    }
+    fault::set_resolver(resolve_page_fault);
+    kernel_memory_map
+}
+
+/// Resolves page faults in the kernel's address space, the only one yet,
+/// by completing writes to copy-on-write pages
+fn resolve_page_fault(fault: &PageFault) -> Resolution {
+    // safety: the page table and trap frames are only changed during kinit
+    let (kpt, frames) = unsafe {
+        (
+            &*addr_of!(KMEM_PAGE_TABLE),
+            &*addr_of!(trap::GLOBAL_TRAPFRAMES),
+        )
+    };
+    let kernel_space = frames
+        .get(fault.hart)
+        .is_some_and(|frame| Satp::from_bits(frame.satp) == fault.satp);
+    match kpt {
+        AnyPageTable::Sv39(kpt) if kernel_space && fault.access == Access::Store => {
+            // safety: not yet in general, as two harts could fault at once, or one
+            // while kinit allocates. The kernel's table has no copy-on-write pages
+            // yet, so until something calls clone_cow neither can happen
+            let mut zalloc = |count| unsafe { kernel_zalloc(count) };
+            match kpt.resolve_cow_fault(VirtAddr::new(fault.address), &mut zalloc) {
+                true => Resolution::Retry,
+                false => Resolution::Unresolved,
+            }
+        }
+        _ => Resolution::Unresolved,
+    }
+}
+
+/// Adds one of the kernel's own regions to the memory map, which must not fail
+unsafe fn register(region: KernelRegion) {
+    if let Err(error) = register_kernel_region(region) {
+        panic!("{}", error);
+    }
 }
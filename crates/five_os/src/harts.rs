@@ -0,0 +1,49 @@
+//! The harts the kernel runs on, found at boot by asking the SBI firmware
+//! for the status of each hart id, which it only reports for harts that exist.
+
+use core::fmt::Debug;
+
+use fiveos_riscv::sbi::hsm;
+
+/// A set of hart ids, a bit per id
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct HartSet(usize);
+
+impl HartSet {
+    pub const fn contains(&self, hart: usize) -> bool {
+        hart < usize::BITS as usize && self.0 & (1 << hart) != 0
+    }
+    pub fn insert(&mut self, hart: usize) {
+        assert!(hart < usize::BITS as usize, "hart id {} out of range", hart);
+        self.0 |= 1 << hart;
+    }
+    pub fn iter(&self) -> impl Iterator<Item = usize> {
+        let set = *self;
+        (0..usize::BITS as usize).filter(move |&hart| set.contains(hart))
+    }
+    pub const fn count(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+    /// One past the highest hart id, the length of a table indexed by hart id
+    pub const fn end(&self) -> usize {
+        (usize::BITS - self.0.leading_zeros()) as usize
+    }
+}
+
+impl Debug for HartSet {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// Finds the harts with ids below the limit, e.g. the number of kernel stacks.
+/// Ids the firmware doesn't know return an error, rather than a state.
+pub fn discover(limit: usize) -> HartSet {
+    let mut harts = HartSet::default();
+    for hart in 0..limit.min(usize::BITS as usize) {
+        if hsm::hart_get_status(hart).is_ok() {
+            harts.insert(hart);
+        }
+    }
+    harts
+}
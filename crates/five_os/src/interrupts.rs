@@ -0,0 +1,165 @@
+//! Handlers for the interrupts the kernel takes, registered by the drivers
+//! of the devices raising them, so the trap handler needs no knowledge of
+//! the board or which PLIC source each device is wired to.
+
+use core::fmt::Display;
+
+use fiveos_virtio::plic::PLIC;
+
+use crate::slot::Slot;
+
+/// Handles an interrupt taken on the given hart
+pub type Handler = fn(hart: usize);
+
+/// PLIC sources a handler can be registered for, those the PLIC driver can enable
+pub const EXTERNAL_SOURCES: usize = 32;
+
+/// The interrupts a handler can be registered for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterruptSource {
+    /// A PLIC source, claimed and completed around the handler by the trap handler
+    External(u32),
+    /// The supervisor timer. The handler must set the next timer, which
+    /// clears the interrupt, or it will be taken again straight away
+    Timer,
+    /// A supervisor software interrupt, cleared before the handler runs
+    Software,
+}
+
+/// Reasons a handler could not be registered
+#[derive(Debug)]
+pub enum RegisterError {
+    /// there is no such PLIC source
+    OutOfRange(InterruptSource),
+    /// only one handler is kept per source
+    AlreadyRegistered(InterruptSource),
+}
+
+impl Display for RegisterError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RegisterError::OutOfRange(source) => {
+                write!(f, "no handler can be registered for {:?}", source)
+            }
+            RegisterError::AlreadyRegistered(source) => {
+                write!(f, "a handler is already registered for {:?}", source)
+            }
+        }
+    }
+}
+
+static EXTERNAL: [Slot<Handler>; EXTERNAL_SOURCES] = [const { Slot::empty() }; EXTERNAL_SOURCES];
+static TIMER: Slot<Handler> = Slot::empty();
+static SOFTWARE: Slot<Handler> = Slot::empty();
+
+fn slot(source: InterruptSource) -> Option<&'static Slot<Handler>> {
+    match source {
+        // source 0 means no interrupt to the PLIC
+        InterruptSource::External(0) => None,
+        InterruptSource::External(number) => EXTERNAL.get(number as usize),
+        InterruptSource::Timer => Some(&TIMER),
+        InterruptSource::Software => Some(&SOFTWARE),
+    }
+}
+
+/// Registers the handler for an interrupt, which only takes effect once
+/// the interrupt is enabled, see [`register_external`] for PLIC sources.
+pub fn register(source: InterruptSource, handler: Handler) -> Result<(), RegisterError> {
+    let slot = slot(source).ok_or(RegisterError::OutOfRange(source))?;
+    if !slot.set(handler) {
+        return Err(RegisterError::AlreadyRegistered(source));
+    }
+    Ok(())
+}
+
+/// Registers the handler for a PLIC source, then enables the source with
+/// the given priority, in range 1..8
+pub fn register_external(source: u8, priority: u8, handler: Handler) -> Result<(), RegisterError> {
+    register(InterruptSource::External(source as u32), handler)?;
+    PLIC.set_priority(source, priority);
+    PLIC.enable_interrupt(source);
+    Ok(())
+}
+
+/// Runs the handler registered for the interrupt, false if there is none
+pub fn dispatch(source: InterruptSource, hart: usize) -> bool {
+    match slot(source).and_then(Slot::get) {
+        Some(handler) => {
+            handler(hart);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        ptr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::{dispatch, register, slot, InterruptSource, RegisterError, EXTERNAL_SOURCES};
+
+    /// the hart the last handler ran on, each test using its own sources
+    static HART: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+    fn handler(hart: usize) {
+        HART.store(hart, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn slots() {
+        let last = EXTERNAL_SOURCES as u32 - 1;
+        for source in [
+            InterruptSource::External(0),
+            InterruptSource::External(last + 1),
+            InterruptSource::External(u32::MAX),
+        ] {
+            assert!(slot(source).is_none());
+        }
+        let first = slot(InterruptSource::External(1)).unwrap();
+        let last = slot(InterruptSource::External(last)).unwrap();
+        let timer = slot(InterruptSource::Timer).unwrap();
+        let software = slot(InterruptSource::Software).unwrap();
+        assert!(!ptr::eq(first, last));
+        assert!(!ptr::eq(timer, software) && !ptr::eq(timer, first));
+    }
+
+    #[test]
+    fn register_out_of_range() {
+        for source in [
+            InterruptSource::External(0),
+            InterruptSource::External(EXTERNAL_SOURCES as u32),
+        ] {
+            assert!(matches!(
+                register(source, handler),
+                Err(RegisterError::OutOfRange(out)) if out == source
+            ));
+        }
+    }
+
+    #[test]
+    fn register_once_then_dispatch() {
+        let source = InterruptSource::External(5);
+        assert!(register(source, handler).is_ok());
+        assert!(matches!(
+            register(source, handler),
+            Err(RegisterError::AlreadyRegistered(again)) if again == source
+        ));
+        assert!(dispatch(source, 3));
+        assert_eq!(HART.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn dispatch_without_handler() {
+        for source in [
+            InterruptSource::External(0),
+            InterruptSource::External(6),
+            InterruptSource::External(EXTERNAL_SOURCES as u32),
+            InterruptSource::Software,
+        ] {
+            assert!(!dispatch(source, 0));
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use alloc::vec::Vec;
+use core::{
+    fmt::{Debug, Display},
+    ops::Range,
+    ptr::{addr_of, addr_of_mut},
+};
+
+use fiveos_peripherals::{print, print_title, println};
+use fiveos_riscv::mmu::{page_table::PAGE_SIZE, EntryFlags};
+
+/// Regions of memory the kernel maps into its own page table
+static mut KERNEL_MEMORY_MAP: KernelMemoryMap = KernelMemoryMap::new();
+
+/// What a region of kernel memory holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    /// part of the kernel image, as laid out by the linker
+    Code,
+    /// statically sized kernel data, such as the stacks
+    Data,
+    /// memory mapped registers of a hardware device
+    Device,
+    /// memory handed out by the page allocator at runtime
+    Dynamic,
+}
+
+/// A named range of addresses the kernel maps into its page table
+#[derive(Clone, Debug)]
+pub struct KernelRegion {
+    pub name: &'static str,
+    pub range: Range<usize>,
+    pub flags: EntryFlags,
+    pub kind: RegionKind,
+}
+
+impl KernelRegion {
+    pub const fn new(
+        name: &'static str,
+        range: Range<usize>,
+        flags: EntryFlags,
+        kind: RegionKind,
+    ) -> KernelRegion {
+        KernelRegion {
+            name,
+            range,
+            flags,
+            kind,
+        }
+    }
+    /// the range extended outwards to whole pages, as it will be mapped
+    fn pages(&self) -> Range<usize> {
+        let start = self.range.start & !(PAGE_SIZE - 1);
+        let end = (self.range.end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        start..end
+    }
+}
+
+/// Reasons a region could not be added to the memory map
+#[derive(Debug)]
+pub enum RegionError {
+    /// the region does not contain any addresses
+    Empty(&'static str),
+    /// the region would be both writable and executable
+    WriteExecute(&'static str),
+    /// the region shares memory, or a page with different permissions, with
+    /// a region registered earlier
+    Overlap {
+        region: &'static str,
+        existing: &'static str,
+    },
+    /// the kernel page table has already been built from the map
+    Sealed(&'static str),
+}
+
+impl Display for RegionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RegionError::Empty(name) => write!(f, "kernel region {} is empty", name),
+            RegionError::WriteExecute(name) => {
+                write!(f, "kernel region {} is both writable and executable", name)
+            }
+            RegionError::Overlap { region, existing } => {
+                write!(f, "kernel region {} overlaps {}", region, existing)
+            }
+            RegionError::Sealed(name) => {
+                write!(
+                    f,
+                    "kernel region {} registered after paging was enabled",
+                    name
+                )
+            }
+        }
+    }
+}
+
+/// Every region of memory the kernel maps for itself, kept sorted by address.
+pub struct KernelMemoryMap {
+    regions: Vec<KernelRegion>,
+    sealed: bool,
+}
+
+impl KernelMemoryMap {
+    pub const fn new() -> KernelMemoryMap {
+        KernelMemoryMap {
+            regions: Vec::new(),
+            sealed: false,
+        }
+    }
+    /// Add a region to the map.
+    ///
+    /// Regions may not be writable and executable at once, and may not overlap
+    /// any region already in the map. Since memory is mapped a page at a time,
+    /// regions may only share a page if they are given the same flags.
+    /// Nothing may be added once the map is sealed.
+    pub fn register(&mut self, region: KernelRegion) -> Result<(), RegionError> {
+        if self.sealed {
+            return Err(RegionError::Sealed(region.name));
+        }
+        if region.range.is_empty() {
+            return Err(RegionError::Empty(region.name));
+        }
+        if region.flags.is_writable() && region.flags.is_executable() {
+            return Err(RegionError::WriteExecute(region.name));
+        }
+        let pages = region.pages();
+        for existing in self.regions.iter() {
+            let existing_pages = existing.pages();
+            let shares_page = pages.start < existing_pages.end && existing_pages.start < pages.end;
+            let shares_memory =
+                region.range.start < existing.range.end && existing.range.start < region.range.end;
+            if shares_memory || (shares_page && region.flags.as_u16() != existing.flags.as_u16()) {
+                return Err(RegionError::Overlap {
+                    region: region.name,
+                    existing: existing.name,
+                });
+            }
+        }
+        let index = self
+            .regions
+            .partition_point(|existing| existing.range.start < region.range.start);
+        self.regions.insert(index, region);
+        Ok(())
+    }
+    /// Refuse any further regions, once the page table has been built from the map.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+    pub fn regions(&self) -> &[KernelRegion] {
+        &self.regions
+    }
+}
+
+impl Default for KernelMemoryMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for KernelMemoryMap {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        print_title!(f, "Kernel Memory Map");
+
+        for region in self.regions.iter() {
+            println!(
+                f,
+                "{}: 0x{:x}-0x{:x} {:?} {:?}",
+                region.name,
+                region.range.start,
+                region.range.end,
+                region.flags,
+                region.kind
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Add a region to the kernel's memory map, to be mapped by init_global_pages.
+/// Fails once init_global_pages has built the kernel page table.
+///
+/// ## Safety
+/// Accesses static mut, expected to only run during kinit before paging is enabled,
+/// while other harts are parked.
+pub unsafe fn register_kernel_region(region: KernelRegion) -> Result<(), RegionError> {
+    (*addr_of_mut!(KERNEL_MEMORY_MAP)).register(region)
+}
+
+/// Stops registering regions, so register_kernel_region returns an error
+/// from now on, and provides the finished memory map to be mapped.
+///
+/// ## Safety
+/// Accesses static mut, expected to only run once, in init_global_pages
+/// while other harts are parked.
+pub unsafe fn seal_kernel_memory_map() -> &'static KernelMemoryMap {
+    (*addr_of_mut!(KERNEL_MEMORY_MAP)).seal();
+    &*addr_of!(KERNEL_MEMORY_MAP)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(name: &'static str, range: Range<usize>, flags: EntryFlags) -> KernelRegion {
+        KernelRegion::new(name, range, flags, RegionKind::Data)
+    }
+
+    #[test]
+    fn sorted_by_address() {
+        let mut map = KernelMemoryMap::new();
+        map.register(region("b", 0x3000..0x4000, EntryFlags::READ_WRITE))
+            .unwrap();
+        map.register(region("a", 0x1000..0x2000, EntryFlags::READ))
+            .unwrap();
+        let names: Vec<_> = map.regions().iter().map(|region| region.name).collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn overlap() {
+        let mut map = KernelMemoryMap::new();
+        map.register(region("a", 0x1000..0x3000, EntryFlags::READ_WRITE))
+            .unwrap();
+        let error = map
+            .register(region("b", 0x2fff..0x4000, EntryFlags::READ_WRITE))
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            RegionError::Overlap {
+                region: "b",
+                existing: "a"
+            }
+        ));
+        // touching is fine
+        map.register(region("c", 0x3000..0x4000, EntryFlags::READ_WRITE))
+            .unwrap();
+    }
+
+    #[test]
+    fn shared_page() {
+        let mut map = KernelMemoryMap::new();
+        map.register(region("a", 0x1000..0x1800, EntryFlags::READ_WRITE))
+            .unwrap();
+        // the same page with the same flags maps the same way
+        map.register(region("b", 0x1800..0x1900, EntryFlags::READ_WRITE))
+            .unwrap();
+        let error = map
+            .register(region("c", 0x1900..0x2100, EntryFlags::READ))
+            .unwrap_err();
+        assert!(matches!(error, RegionError::Overlap { region: "c", .. }));
+    }
+
+    #[test]
+    fn write_execute() {
+        let mut map = KernelMemoryMap::new();
+        let error = map
+            .register(region(
+                "a",
+                0x1000..0x2000,
+                EntryFlags::READ_WRITE.with_executable(true),
+            ))
+            .unwrap_err();
+        assert!(matches!(error, RegionError::WriteExecute("a")));
+        let error = map
+            .register(region(
+                "b",
+                0x1000..0x2000,
+                EntryFlags::new()
+                    .with_valid(true)
+                    .with_writable(true)
+                    .with_executable(true),
+            ))
+            .unwrap_err();
+        assert!(matches!(error, RegionError::WriteExecute("b")));
+        assert!(map.regions().is_empty());
+    }
+
+    #[test]
+    fn empty() {
+        let mut map = KernelMemoryMap::new();
+        let error = map
+            .register(region("a", 0x1000..0x1000, EntryFlags::READ))
+            .unwrap_err();
+        assert!(matches!(error, RegionError::Empty("a")));
+    }
+
+    #[test]
+    fn sealed() {
+        let mut map = KernelMemoryMap::new();
+        map.register(region("a", 0x1000..0x2000, EntryFlags::READ))
+            .unwrap();
+        map.seal();
+        let error = map
+            .register(region("b", 0x3000..0x4000, EntryFlags::READ))
+            .unwrap_err();
+        assert!(matches!(error, RegionError::Sealed("b")));
+        assert_eq!(map.regions().len(), 1);
+    }
+}
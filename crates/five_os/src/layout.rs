@@ -20,6 +20,7 @@ extern "C" {
     static _heap_size: usize;
     static _memory_end: usize;
     static asm_trap_vector: usize;
+    static _kernel_offset_value: usize;
 }
 
 /// Allows access to the global addresses PROVIDE'd in the linker map. The main reason for this structure
@@ -27,6 +28,9 @@ extern "C" {
 /// addresses at compile-time so we get them at runtime instead. We could do the binding-to-address transformation
 /// as .data in the assembly output, but this way allows safe rust access to the values without a bunch of
 /// extern "c" accesses throughout the codebase.
+///
+/// All addresses are the virtual addresses the kernel was linked at, which are
+/// kernel_offset above the physical addresses it is loaded at (see layout.lds).
 pub struct LinkerLayout {
     pub text_start: usize,
     pub trap_start: usize,
@@ -45,6 +49,7 @@ pub struct LinkerLayout {
     pub heap_size: usize,
     pub memory_end: usize,
     pub trap_vector: usize,
+    pub kernel_offset: usize,
 }
 
 impl LinkerLayout {
@@ -73,6 +78,7 @@ impl LinkerLayout {
                 heap_size: &_heap_size as *const _ as usize,
                 memory_end: &_memory_end as *const _ as usize,
                 trap_vector: &asm_trap_vector as *const _ as usize,
+                kernel_offset: _kernel_offset_value,
             }
         }
     }
@@ -80,53 +86,69 @@ impl LinkerLayout {
     pub fn get() -> LinkerLayout {
         LinkerLayout::new()
     }
+    /// Converts a virtual address inside the kernel's memory to the physical
+    /// address it is loaded at, other addresses are returned unchanged.
+    pub fn physical(&self, address: usize) -> usize {
+        if (self.memory_start..self.memory_end).contains(&address) {
+            address - self.kernel_offset
+        } else {
+            address
+        }
+    }
 }
 
 impl Debug for LinkerLayout {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         print_title!(f, "Static Layout Sanity Check");
+        println!(f, "kernel offset:\t{:x}", self.kernel_offset);
         println!(
             f,
-            "text:\t{:x} - {:x}\t{}-bytes",
+            "text:\t{:x} - {:x}\t@{:x}\t{}-bytes",
             self.text_start,
             self.text_end,
+            self.physical(self.text_start),
             self.text_end - self.text_start
         );
         println!(f, " trap:\t{:x} - {:x}??", self.trap_start, self.text_end);
         println!(f, "global:\t{:x}", self.global_pointer);
         println!(
             f,
-            "rodata:\t{:x} - {:x}\t{}-bytes",
+            "rodata:\t{:x} - {:x}\t@{:x}\t{}-bytes",
             self.rodata_start,
             self.rodata_end,
+            self.physical(self.rodata_start),
             self.rodata_end - self.rodata_start
         );
         println!(
             f,
-            "data:\t{:x} - {:x}\t{}-bytes",
+            "data:\t{:x} - {:x}\t@{:x}\t{}-bytes",
             self.data_start,
             self.data_end,
+            self.physical(self.data_start),
             self.data_end - self.data_start
         );
         println!(
             f,
-            "bss:\t{:x} - {:x}\t{}-bytes",
+            "bss:\t{:x} - {:x}\t@{:x}\t{}-bytes",
             self.bss_start,
             self.bss_end,
+            self.physical(self.bss_start),
             self.bss_end - self.bss_start
         );
         println!(
             f,
-            " stack:\t{:x} - {:x}\t{}-bytes",
+            " stack:\t{:x} - {:x}\t@{:x}\t{}-bytes",
             self.stack_start,
             self.stack_end,
+            self.physical(self.stack_start),
             self.stack_end - self.stack_start
         );
         println!(
             f,
-            " heap:\t{:x} - {:x}\t{}-bytes",
+            " heap:\t{:x} - {:x}\t@{:x}\t{}-bytes",
             self.heap_start,
             self.heap_start + self.heap_size,
+            self.physical(self.heap_start),
             self.heap_size
         );
         Ok(())
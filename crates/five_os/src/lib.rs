@@ -6,9 +6,11 @@
     alloc_error_handler,
     const_mut_refs
 )]
+#![cfg_attr(test, feature(test))]
+
+extern crate alloc;
 
 use fiveos_peripherals::{print, println};
-use fiveos_virtio::uart::Uart0;
 
 // Allow testing this library
 #[cfg(test)]
@@ -17,22 +19,52 @@ extern crate std;
 #[cfg(test)]
 extern crate test;
 
+// the boot and trap code, which only builds for riscv
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
 pub mod assembly;
+pub mod boot;
+pub mod emulate;
+pub mod fault;
+pub mod harts;
+pub mod interrupts;
+pub mod kernel_regions;
 pub mod layout;
 pub mod logo;
+pub mod machine;
 pub mod process;
+pub mod slot;
+pub mod stack;
 pub mod trap;
 
+/// Another handle to the UART, alongside the one kinit takes from PERIPHERALS,
+/// for the prints of trap and interrupt handlers that can't be passed kinit's.
+///
+/// ## Safety
+/// Uart0 is a zero sized handle with no state of its own, so handles can't
+/// disagree about anything in memory. Each byte written is a single volatile
+/// store to the transmit register, and each byte read a single volatile load,
+/// so handles used at once, by another hart or by a trap interrupting a print,
+/// interleave their bytes rather than corrupt anything. Callers must accept
+/// that, and that a byte one handle reads is gone for the others. kinit has
+/// initialized the UART before any handler can run.
+pub unsafe fn console() -> fiveos_virtio::uart::Uart0 {
+    fiveos_virtio::uart::Uart0::new()
+}
+
+#[cfg(not(test))]
 #[no_mangle]
 extern "C" fn eh_personality() {}
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    let mut uart = unsafe { Uart0::new() };
+    // safety: see console
+    let mut uart = unsafe { console() };
     println!(uart, "aborting: {}", info);
     abort();
 }
 
+#[cfg(not(test))]
 #[inline(never)]
 #[no_mangle]
 pub extern "C" fn abort() -> ! {
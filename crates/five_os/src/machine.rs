@@ -0,0 +1,489 @@
+//! The machine mode firmware below the kernel, which runs in supervisor mode.
+//!
+//! Machine mode keeps the traps it does not delegate: its own interrupts,
+//! and ecalls from supervisor mode for the services only machine mode can
+//! provide. These are the SBI extensions in [`fiveos_riscv::sbi`], so the
+//! kernel makes the same calls whether it runs on this or on other SBI
+//! firmware such as OpenSBI (see the firmware feature).
+//!
+//! Harts other than the boot hart wait here, in [`kinit_park`], until the
+//! kernel starts them with the HSM extension. Requests between harts, to
+//! start, interrupt or fence another, are flagged in the target's
+//! [`HartSbi`] and delivered with a machine software interrupt.
+
+use core::{
+    ptr::addr_of_mut,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use fiveos_riscv::cpu::registers::{
+    csr::{
+        marchid,
+        mepc::{self, Mepc},
+        mie::{self, Mie},
+        mimpid,
+        mip::{self, Mip},
+        mscratch::{self, Mscratch},
+        mvendorid,
+    },
+    mstatus::{self, PrivilegeMode},
+    satp::{self, Satp},
+};
+use fiveos_riscv::riscv_asm;
+use fiveos_riscv::sbi::{
+    base::{self, SpecVersion},
+    dbcn,
+    hsm::{self, HartState},
+    ipi, rfence,
+    srst::{self, ResetReason, ResetType},
+    time, HartMask, SbiError, SbiResult,
+};
+use fiveos_virtio::{clint, finisher};
+
+use crate::trap::TrapFrame;
+
+/// Harts the shim has trap frames and stacks for, one for each kernel stack
+/// in the linker layout's stack section (see HART_STACK_SIZE). boot.s parks
+/// any others before they use a stack, and they never show up as present.
+pub const MAX_HARTS: usize = 8;
+/// Bytes of stack for each hart's machine mode traps
+pub const MACHINE_STACK_SIZE: usize = 0x2000;
+
+/// The version of the SBI spec implemented
+const SPEC_VERSION: SpecVersion = SpecVersion { major: 2, minor: 0 };
+/// Our SBI implementation ID, "FIVE", clear of the registered IDs
+pub const IMPLEMENTATION_ID: usize = 0x4649_5645;
+/// five_os 0.2, as major << 16 | minor
+const IMPLEMENTATION_VERSION: usize = 2;
+/// Extensions serviced by [`supervisor_ecall`]
+const EXTENSIONS: [usize; 7] = [
+    base::EXTENSION,
+    time::EXTENSION,
+    ipi::EXTENSION,
+    rfence::EXTENSION,
+    hsm::EXTENSION,
+    srst::EXTENSION,
+    dbcn::EXTENSION,
+];
+
+/// indices of the argument registers in a trap frame
+const A0: usize = 10;
+const A1: usize = 11;
+const A2: usize = 12;
+const A3: usize = 13;
+const A6: usize = 16;
+const A7: usize = 17;
+
+/// Requests flagged in [`HartSbi::pending`]
+const PENDING_SUPERVISOR_SOFTWARE: usize = 1 << 0;
+const PENDING_FENCE_I: usize = 1 << 1;
+const PENDING_SFENCE_VMA: usize = 1 << 2;
+
+/// State of a hart which has not reached init_hart, so may not exist
+const ABSENT: usize = usize::MAX;
+/// State of a stopped hart while a HART_START call stores where it starts,
+/// reported as StartPending but not yet seen as such by the hart
+const START_CLAIMED: usize = usize::MAX - 1;
+
+/// What other harts need to reach a hart
+pub struct HartSbi {
+    /// a [`HartState`] code, [`ABSENT`] or [`START_CLAIMED`]
+    state: AtomicUsize,
+    /// requests for the hart to act on, see [`handle_pending`]
+    pending: AtomicUsize,
+    /// where the hart enters supervisor mode once started, and its a1
+    start_address: AtomicUsize,
+    opaque: AtomicUsize,
+}
+
+impl HartSbi {
+    const fn new(state: usize) -> HartSbi {
+        HartSbi {
+            state: AtomicUsize::new(state),
+            pending: AtomicUsize::new(0),
+            start_address: AtomicUsize::new(0),
+            opaque: AtomicUsize::new(0),
+        }
+    }
+    /// None until the hart has reached init_hart, which is how the kernel
+    /// finds the harts that exist, with [`hsm::hart_get_status`]. Also None
+    /// while a start is [`START_CLAIMED`].
+    fn state(&self) -> Option<HartState> {
+        HartState::from_code(self.state.load(Ordering::Acquire))
+    }
+    fn set_state(&self, state: HartState) {
+        self.state.store(state as usize, Ordering::Release);
+    }
+}
+
+/// Hart 0 boots the kernel, boot.s parks the others until started
+const fn initial_harts() -> [HartSbi; MAX_HARTS] {
+    let mut harts = [const { HartSbi::new(ABSENT) }; MAX_HARTS];
+    harts[0] = HartSbi::new(HartState::Started as usize);
+    harts
+}
+
+/// Kept in .data rather than .bss, like the trap frames below
+#[link_section = ".data"]
+static HARTS: [HartSbi; MAX_HARTS] = initial_harts();
+
+/// Machine mode's trap frames, one per hart. Kept in .data rather than
+/// .bss, as the other harts fill theirs in while hart 0 may still be
+/// zeroing .bss
+#[link_section = ".data"]
+static mut MACHINE_TRAPFRAMES: [TrapFrame; MAX_HARTS] = [TrapFrame::zero(); MAX_HARTS];
+
+#[repr(C, align(16))]
+struct MachineStack([u8; MACHINE_STACK_SIZE]);
+
+static mut MACHINE_STACKS: [MachineStack; MAX_HARTS] =
+    [const { MachineStack([0; MACHINE_STACK_SIZE]) }; MAX_HARTS];
+
+/// Gives the hart a trap frame and stack for its machine mode traps, and
+/// lets other harts interrupt it.
+///
+/// ## Safety
+/// Must be in machine mode, before the hart can take a trap into machine
+/// mode. As this runs at physical addresses, so do the frame and stack.
+pub unsafe fn init_hart(hart: usize) {
+    if hart >= MAX_HARTS {
+        park_absent();
+    }
+    let frame = &mut (*addr_of_mut!(MACHINE_TRAPFRAMES))[hart];
+    let stack = addr_of_mut!(MACHINE_STACKS[hart]) as usize + MACHINE_STACK_SIZE;
+    frame.hartid = hart;
+    frame.trap_stack = stack as *mut u8;
+    mscratch::write(Mscratch::from_bits(frame as *mut TrapFrame as usize));
+    mie::set(Mie::new().with_msie(true));
+    // present from here on, the boot hart is already started
+    let _ = HARTS[hart].state.compare_exchange(
+        ABSENT,
+        HartState::Stopped as usize,
+        Ordering::Release,
+        Ordering::Relaxed,
+    );
+}
+
+/// Waits forever, for a hart with an id past [`MAX_HARTS`]
+fn park_absent() -> ! {
+    loop {
+        unsafe { riscv_asm!("wfi") };
+    }
+}
+
+/// Waits in machine mode until the kernel starts this hart with
+/// [`hsm::hart_start`], then enters supervisor mode where it asked.
+/// Called from boot.s on every hart but the boot hart, after its other
+/// kinit_ steps, and by [`supervisor_ecall`] for harts which stop.
+#[no_mangle]
+pub extern "C" fn kinit_park(hart: usize) -> ! {
+    let Some(sbi) = HARTS.get(hart) else {
+        park_absent()
+    };
+    // a stopping hart is StopPending, a parking one was left Stopped by
+    // init_hart, unless the kernel has started it since
+    let _ = sbi.state.compare_exchange(
+        HartState::StopPending as usize,
+        HartState::Stopped as usize,
+        Ordering::Release,
+        Ordering::Relaxed,
+    );
+    unsafe { mie::clear(Mie::new().with_mtie(true)) };
+    // a start request raises a software interrupt, which wakes wfi
+    // even with interrupts disabled
+    while sbi.state() != Some(HartState::StartPending) {
+        unsafe { riscv_asm!("wfi") };
+        clint::set_software_pending(hart, false);
+    }
+    clint::set_software_pending(hart, false);
+    let start_address = sbi.start_address.load(Ordering::Acquire);
+    let opaque = sbi.opaque.load(Ordering::Acquire);
+    sbi.set_state(HartState::Started);
+    unsafe { enter_supervisor(hart, start_address, opaque) }
+}
+
+/// Enters supervisor mode at the physical address, with translation off,
+/// the hart id in a0 and opaque in a1, as a started hart expects.
+unsafe fn enter_supervisor(hart: usize, start_address: usize, opaque: usize) -> ! {
+    mstatus::modify(|status| {
+        status
+            .with_mpp(PrivilegeMode::Supervisor)
+            .with_mpie(true)
+            .with_sie(false)
+    });
+    satp::write(Satp::new());
+    mepc::write(Mepc::from_bits(start_address));
+    riscv_asm!(
+        "mret",
+        in("a0") hart,
+        in("a1") opaque,
+        options(noreturn),
+    )
+}
+
+/// Passes a machine timer interrupt on to supervisor mode, which asked for
+/// it with [`time::set_timer`]. The machine timer stays pending until the
+/// timer compare is moved, so it is masked until supervisor mode asks again.
+pub fn forward_timer() {
+    unsafe {
+        mie::clear(Mie::new().with_mtie(true));
+        mip::set(Mip::new().with_stip(true));
+    }
+}
+
+/// Handles a machine software interrupt, raised by another hart
+/// with requests for this one
+pub fn software_interrupt(hart: usize) {
+    clint::set_software_pending(hart, false);
+    handle_pending(hart);
+}
+
+/// Acts on the requests flagged for this hart, clearing each once done,
+/// which is what a hart waiting on a fence watches for
+fn handle_pending(hart: usize) {
+    let sbi = &HARTS[hart];
+    let pending = sbi.pending.load(Ordering::Acquire);
+    if pending & PENDING_FENCE_I != 0 {
+        unsafe { riscv_asm!("fence.i") };
+    }
+    if pending & PENDING_SFENCE_VMA != 0 {
+        unsafe { riscv_asm!("sfence.vma") };
+    }
+    if pending & PENDING_SUPERVISOR_SOFTWARE != 0 {
+        unsafe { mip::set(Mip::new().with_ssip(true)) };
+    }
+    sbi.pending.fetch_and(!pending, Ordering::AcqRel);
+}
+
+/// Serves an ecall from supervisor mode, leaving the result in the
+/// frame's a0 and a1 to be restored on return.
+pub fn supervisor_ecall(frame: &mut TrapFrame, hart: usize) {
+    let extension = frame.regs[A7];
+    let function = frame.regs[A6];
+    let args = [
+        frame.regs[A0],
+        frame.regs[A1],
+        frame.regs[A2],
+        frame.regs[A3],
+    ];
+    let result = match extension {
+        base::EXTENSION => base_call(function, args),
+        time::EXTENSION => time_call(function, args, hart),
+        ipi::EXTENSION => ipi_call(function, args, hart),
+        rfence::EXTENSION => rfence_call(function, args, hart),
+        hsm::EXTENSION => hsm_call(function, args, hart),
+        srst::EXTENSION => srst_call(function, args),
+        dbcn::EXTENSION => dbcn_call(function, args),
+        _ => Err(SbiError::NotSupported),
+    };
+    let (error, value) = match result {
+        Ok(value) => (0, value),
+        Err(error) => (error.code() as usize, 0),
+    };
+    frame.regs[A0] = error;
+    frame.regs[A1] = value;
+}
+
+fn base_call(function: usize, args: [usize; 4]) -> SbiResult<usize> {
+    match function {
+        base::GET_SPEC_VERSION => Ok(SPEC_VERSION.bits()),
+        base::GET_IMPL_ID => Ok(IMPLEMENTATION_ID),
+        base::GET_IMPL_VERSION => Ok(IMPLEMENTATION_VERSION),
+        base::PROBE_EXTENSION => Ok(EXTENSIONS.contains(&args[0]) as usize),
+        base::GET_MVENDORID => Ok(mvendorid::read().bits()),
+        base::GET_MARCHID => Ok(marchid::read().bits()),
+        base::GET_MIMPID => Ok(mimpid::read().bits()),
+        _ => Err(SbiError::NotSupported),
+    }
+}
+
+fn time_call(function: usize, args: [usize; 4], hart: usize) -> SbiResult<usize> {
+    match function {
+        time::SET_TIMER => {
+            set_timer(hart, args[0] as u64);
+            Ok(0)
+        }
+        _ => Err(SbiError::NotSupported),
+    }
+}
+
+/// Asks for a supervisor timer interrupt once the time reaches value,
+/// clearing any which is pending
+fn set_timer(hart: usize, value: u64) {
+    clint::set_timer_compare(hart, value);
+    unsafe {
+        mip::clear(Mip::new().with_stip(true));
+        mie::set(Mie::new().with_mtie(true));
+    }
+}
+
+fn ipi_call(function: usize, args: [usize; 4], hart: usize) -> SbiResult<usize> {
+    match function {
+        ipi::SEND_IPI => send(hart_mask(args)?, PENDING_SUPERVISOR_SOFTWARE, hart, false),
+        _ => Err(SbiError::NotSupported),
+    }
+}
+
+/// Remote sfence.vma flushes the whole address space of each hart, which
+/// covers any range and asid asked for
+fn rfence_call(function: usize, args: [usize; 4], hart: usize) -> SbiResult<usize> {
+    let request = match function {
+        rfence::REMOTE_FENCE_I => PENDING_FENCE_I,
+        rfence::REMOTE_SFENCE_VMA | rfence::REMOTE_SFENCE_VMA_ASID => PENDING_SFENCE_VMA,
+        _ => return Err(SbiError::NotSupported),
+    };
+    send(hart_mask(args)?, request, hart, true)
+}
+
+/// The harts named by the first two arguments, checked against those we have
+fn hart_mask(args: [usize; 4]) -> SbiResult<HartMask> {
+    let harts = HartMask {
+        mask: args[0],
+        base: args[1],
+    };
+    let valid =
+        harts.is_all() || (harts.base < MAX_HARTS && harts.mask >> (MAX_HARTS - harts.base) == 0);
+    match valid {
+        true => Ok(harts),
+        false => Err(SbiError::InvalidParam),
+    }
+}
+
+/// Flags the request for each started hart in the mask and interrupts it,
+/// or acts on it directly for this hart. Waits for the others to act on
+/// it if asked, handling any requests for this hart meanwhile so two harts
+/// fencing each other can't deadlock.
+fn send(harts: HartMask, request: usize, hart: usize, wait: bool) -> SbiResult<usize> {
+    let targets = || {
+        (0..MAX_HARTS)
+            .filter(move |&target| harts.contains(target))
+            .filter(|&target| HARTS[target].state() == Some(HartState::Started))
+    };
+    for target in targets() {
+        HARTS[target].pending.fetch_or(request, Ordering::AcqRel);
+        match target == hart {
+            true => handle_pending(hart),
+            false => clint::set_software_pending(target, true),
+        }
+    }
+    if wait {
+        for target in targets() {
+            while HARTS[target].pending.load(Ordering::Acquire) & request != 0 {
+                handle_pending(hart);
+                core::hint::spin_loop();
+            }
+        }
+    }
+    Ok(0)
+}
+
+fn hsm_call(function: usize, args: [usize; 4], hart: usize) -> SbiResult<usize> {
+    match function {
+        hsm::HART_START => {
+            let target = args[0];
+            let sbi = HARTS.get(target).ok_or(SbiError::InvalidParam)?;
+            if sbi.state.load(Ordering::Acquire) == ABSENT {
+                return Err(SbiError::InvalidParam);
+            }
+            // claimed first, so only one caller stores where the hart starts,
+            // then published, as the hart may leave kinit_park as soon as it
+            // sees StartPending
+            sbi.state
+                .compare_exchange(
+                    HartState::Stopped as usize,
+                    START_CLAIMED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .map_err(|_| SbiError::AlreadyAvailable)?;
+            sbi.start_address.store(args[1], Ordering::Relaxed);
+            sbi.opaque.store(args[2], Ordering::Relaxed);
+            sbi.set_state(HartState::StartPending);
+            clint::set_software_pending(target, true);
+            Ok(0)
+        }
+        hsm::HART_STOP => {
+            HARTS[hart].set_state(HartState::StopPending);
+            kinit_park(hart)
+        }
+        hsm::HART_GET_STATUS => {
+            let sbi = HARTS.get(args[0]).ok_or(SbiError::InvalidParam)?;
+            match sbi.state.load(Ordering::Acquire) {
+                START_CLAIMED => Ok(HartState::StartPending as usize),
+                code => HartState::from_code(code)
+                    .map(|state| state as usize)
+                    .ok_or(SbiError::InvalidParam),
+            }
+        }
+        hsm::HART_SUSPEND => suspend(args, hart),
+        _ => Err(SbiError::NotSupported),
+    }
+}
+
+/// Waits for an interrupt, which wakes wfi whether or not it is enabled
+/// in mstatus. A non-retentive suspend then enters supervisor mode as a
+/// started hart would, rather than returning.
+fn suspend(args: [usize; 4], hart: usize) -> SbiResult<usize> {
+    let (suspend_type, resume_address, opaque) = (args[0] as u32 as usize, args[1], args[2]);
+    if suspend_type != hsm::SUSPEND_RETENTIVE && suspend_type != hsm::SUSPEND_NON_RETENTIVE {
+        return Err(SbiError::InvalidParam);
+    }
+    let sbi = &HARTS[hart];
+    sbi.set_state(HartState::Suspended);
+    unsafe { riscv_asm!("wfi") };
+    sbi.set_state(HartState::Started);
+    match suspend_type {
+        hsm::SUSPEND_NON_RETENTIVE => unsafe { enter_supervisor(hart, resume_address, opaque) },
+        _ => Ok(0),
+    }
+}
+
+/// Resets with QEMU's test finisher, the call only returns if there is none
+fn srst_call(function: usize, args: [usize; 4]) -> SbiResult<usize> {
+    match function {
+        srst::SYSTEM_RESET => {
+            let reset = ResetType::from_code(args[0]).ok_or(SbiError::InvalidParam)?;
+            let reason = ResetReason::from_code(args[1]).ok_or(SbiError::InvalidParam)?;
+            match (reset, reason) {
+                (ResetType::Shutdown, ResetReason::NoReason) => finisher::power_off(),
+                (ResetType::Shutdown, ResetReason::SystemFailure) => finisher::fail(1),
+                (ResetType::ColdReboot | ResetType::WarmReboot, _) => finisher::reset(),
+            }
+            Err(SbiError::Failed)
+        }
+        _ => Err(SbiError::NotSupported),
+    }
+}
+
+/// The console is the UART, reached at physical addresses as translation
+/// is off in machine mode. Buffers above XLEN bits can't be reached.
+fn dbcn_call(function: usize, args: [usize; 4]) -> SbiResult<usize> {
+    let (length, address) = (args[0], args[1]);
+    // safety: see console
+    let mut uart = unsafe { crate::console() };
+    match function {
+        dbcn::CONSOLE_WRITE | dbcn::CONSOLE_READ if args[2] != 0 => Err(SbiError::InvalidParam),
+        dbcn::CONSOLE_WRITE => {
+            for offset in 0..length {
+                uart.put(unsafe { ((address + offset) as *const u8).read_volatile() });
+            }
+            Ok(length)
+        }
+        dbcn::CONSOLE_READ => {
+            let mut read = 0;
+            while read < length {
+                let Some(byte) = uart.get() else {
+                    break;
+                };
+                unsafe { ((address + read) as *mut u8).write_volatile(byte) };
+                read += 1;
+            }
+            Ok(read)
+        }
+        dbcn::CONSOLE_WRITE_BYTE => {
+            uart.put(args[0] as u8);
+            Ok(0)
+        }
+        _ => Err(SbiError::NotSupported),
+    }
+}
@@ -7,62 +7,145 @@ use alloc::{boxed::Box, string::String, vec};
 use core::{arch::asm, fmt::Write};
 
 use crate::{
-    global_pages::init_global_pages,
+    global_pages::{init_global_pages, kernel_page_table},
     kernel_heap::{init_kmem, inspect_heap},
     layout::LinkerLayout,
     memory_manager::init_allocator,
 };
-use five_os::*;
+use five_os::{
+    harts::HartSet,
+    kernel_regions::{register_kernel_region, KernelRegion, RegionKind},
+    trap::TrapFrame,
+    *,
+};
+use fiveos_allocator::page::PageAllocator;
 use fiveos_peripherals::{print, print_title, printhdr, println};
-use fiveos_riscv::cpu::registers::{
-    misa::Misa,
-    raw::{asm_get_marchid, asm_get_mimpid, asm_get_mvendorid},
+use fiveos_riscv::cpu::counters::{self, Counter};
+use fiveos_riscv::cpu::registers::csr::{
+    sie::{self, Sie},
+    sstatus::{self, SStatus},
+};
+use fiveos_riscv::mmu::{page_table::PAGE_SIZE, EntryFlags};
+use fiveos_riscv::sbi::hsm;
+use fiveos_virtio::{
+    clint::{CLINT_BASE_ADDRESS, CLINT_END_ADDRESS},
+    plic::{
+        PLIC, PLIC_BASE_ADDRESS, PLIC_CONTEXT_BASE_ADDRESS, PLIC_CONTEXT_END_ADDRESS,
+        PLIC_END_ADDRESS,
+    },
+    uart::{UART_BASE_ADDRESS, UART_END_ADDRESS, UART_INTERRUPT},
+    Peripherals, PERIPHERALS,
 };
-use fiveos_virtio::{plic::PLIC, Peripherals, PERIPHERALS};
 
 mod global_pages;
 mod kernel_heap;
 mod memory_manager;
 
-/// Our first entry point out of the assembly boot.s
+/// Our first entry point out of the assembly boot.s, or sbi_boot.s
 #[no_mangle]
-extern "C" fn kinit() {
+extern "C" fn kinit(hart: usize) {
     unsafe {
+        // until init_global_pages installs this hart's own frame
+        trap::install_boot_trap_frame(hart);
+        trap::setup_trap_handler();
+        trap::init_fp_status();
         let Peripherals { mut uart } = PERIPHERALS.take().unwrap_unchecked();
         uart.init();
         logo::print_logo(&mut uart);
-        print_cpu_info(&mut uart);
         let layout = LinkerLayout::get();
+        print_cpu_info(&mut uart);
+        print_cpu_features(&mut uart);
         print!(uart, "{:?}", layout);
         let mut page_allocator = init_allocator(&layout);
         print!(uart, "{:?}", page_allocator.info());
 
-        let trap_stack = page_allocator
-            .zalloc(1)
-            .expect("failed to initialize trap stack") as *mut u8 as usize;
-
         let kernel_heap_info = init_kmem(&mut page_allocator);
 
-        let kernel_memory_map =
-            init_global_pages(&layout, page_allocator, trap_stack, kernel_heap_info);
+        let harts = harts::discover(stack::hart_stack_count(&layout));
+        println!(uart, "Harts: {:?}", harts);
+        init_trap_frames(page_allocator, harts);
+
+        register_device_regions();
+
+        let kernel_memory_map = init_global_pages(&layout, page_allocator, hart, kernel_heap_info);
 
         print!(uart, "{:?}", kernel_memory_map);
+        print_title!(uart, "Kernel Page Table");
+        print!(uart, "{}", kernel_page_table());
         print!(uart, "{:?}", page_allocator);
         test_allocations(&mut uart);
         asm!("sfence.vma zero, {}", in(reg)0);
+        start_harts(&mut uart, &layout, harts, hart);
+    }
+}
+
+/// Gives each hart a trap frame, and a trap stack to go with it, indexed by hart id
+///
+/// ## Safety
+/// Must run in kinit after init_kmem and before init_global_pages,
+/// which maps the trap stacks and installs the boot hart's frame
+unsafe fn init_trap_frames(page_allocator: &mut PageAllocator<PAGE_SIZE>, harts: HartSet) {
+    let frames = Box::leak(vec![TrapFrame::zero(); harts.end()].into_boxed_slice());
+    for hart in harts.iter() {
+        let trap_stack = page_allocator
+            .zalloc(stack::TRAP_STACK_PAGES)
+            .expect("failed to allocate trap stack") as *mut u8;
+        let frame = &mut frames[hart];
+        frame.hartid = hart;
+        frame.trap_stack = trap_stack.add(stack::TRAP_STACK_PAGES * PAGE_SIZE);
+    }
+    trap::GLOBAL_TRAPFRAMES = frames;
+}
+
+/// Adds the memory mapped registers of the devices we drive to the kernel's memory map
+///
+/// ## Safety
+/// Must run in kinit before init_global_pages, as register_kernel_region
+unsafe fn register_device_regions() {
+    let devices = [
+        KernelRegion::new(
+            "Hardware UART",
+            UART_BASE_ADDRESS..UART_END_ADDRESS,
+            EntryFlags::READ_WRITE,
+            RegionKind::Device,
+        ),
+        KernelRegion::new(
+            "Hardware CLINT, MSIP",
+            CLINT_BASE_ADDRESS..CLINT_END_ADDRESS,
+            EntryFlags::READ_WRITE,
+            RegionKind::Device,
+        ),
+        KernelRegion::new(
+            "Hardware PLIC",
+            PLIC_BASE_ADDRESS..PLIC_END_ADDRESS,
+            EntryFlags::READ_WRITE,
+            RegionKind::Device,
+        ),
+        KernelRegion::new(
+            "Hardware PLIC contexts",
+            PLIC_CONTEXT_BASE_ADDRESS..PLIC_CONTEXT_END_ADDRESS,
+            EntryFlags::READ_WRITE,
+            RegionKind::Device,
+        ),
+    ];
+    for device in devices {
+        if let Err(error) = register_kernel_region(device) {
+            panic!("{}", error);
+        }
     }
 }
 
 fn test_allocations(uart: &mut impl Write) {
     println!(uart, "setting up UART receiver");
     PLIC.set_threshold(0);
-    PLIC.enable_interrupt(10);
-    PLIC.set_priority(10, 1);
+    if let Err(error) = interrupts::register_external(UART_INTERRUPT, 1, uart_input) {
+        panic!("{}", error);
+    }
 
     {
         printhdr!(uart, "testing allocations ");
-        let k = Box::<u32>::new(100);
-        println!(uart, "Boxed value = {}", &k);
+        let (k, cycles) = counters::measure(Counter::Cycle, || Box::<u32>::new(100));
+        println!(uart, "Boxed value = {}, allocated in {} cycles", &k, cycles);
         let sparkle_heart = vec![240, 159, 146, 150];
         let sparkle_heart = String::from_utf8(sparkle_heart).unwrap();
         println!(uart, "String = {}", sparkle_heart);
@@ -79,8 +162,53 @@ fn test_allocations(uart: &mut impl Write) {
     printhdr!(uart, "reached end");
 }
 
+/// Echoes what is typed into the UART, registered for its PLIC source
+fn uart_input(_hart: usize) {
+    // safety: see console, this is the only handler reading the uart
+    let mut uart = unsafe { console() };
+    if let Some(c) = uart.get() {
+        match c {
+            8 => {
+                print!(uart, "\x08 \x08");
+            }
+            10 | 13 => {
+                println!(uart, "");
+            }
+            _ => {
+                print!(uart, "{}", c as char);
+            }
+        }
+    }
+}
+
+/// Starts the other harts, which wait in the SBI firmware until asked
+fn start_harts(uart: &mut impl Write, layout: &LinkerLayout, harts: HartSet, boot_hart: usize) {
+    extern "C" {
+        static _start_hart: usize;
+    }
+    // started harts begin with translation off, hart.s turns it on
+    // with the kernel's table, given as the opaque argument
+    let entry = layout.physical(unsafe { &_start_hart as *const _ as usize });
+    let satp = unsafe { trap::GLOBAL_TRAPFRAMES[boot_hart].satp };
+    for hart in harts.iter().filter(|&hart| hart != boot_hart) {
+        match unsafe { hsm::hart_start(hart, entry, satp) } {
+            Ok(()) => println!(uart, "Started hart {}", hart),
+            Err(error) => println!(uart, "Hart {} not started: {:?}", hart, error),
+        }
+    }
+}
+
+/// Entry point for the other harts, from hart.s, with translation on
 #[no_mangle]
-extern "C" fn kinit_hart() -> ! {
+extern "C" fn kinit_hart(hart: usize) -> ! {
+    unsafe {
+        // before any trap can reach trap.s
+        trap::install_trap_frame(hart);
+        trap::setup_trap_handler();
+        trap::init_fp_status();
+        sie::set(Sie::new().with_ssie(true));
+        sstatus::set(SStatus::new().with_sie(true));
+    }
     loop {
         unsafe { asm!("wfi") };
     }
@@ -88,44 +216,41 @@ extern "C" fn kinit_hart() -> ! {
 
 ////////////////////////////////////////////// todo: relocate below this line
 fn print_cpu_info(uart: &mut impl Write) {
-    let vendor = unsafe { asm_get_mvendorid() };
-    let architecture = unsafe { asm_get_marchid() };
-    let implementation = unsafe { asm_get_mimpid() };
+    // read by boot.s, as these are machine mode registers
+    let features = boot::cpu_features();
+    let vendor = features.vendor_id;
+    let architecture = features.architecture_id;
+    let implementation = features.implementation_id;
     print_title!(uart, "CPU INFO");
-    println!(
-        uart,
-        "Vendor: {:x} | Architecture: {:x} | Implementation: {:x}",
-        vendor,
-        architecture,
-        implementation
-    );
-    print_misa_info(uart);
+    match gen_mvendorid::vendor_name(vendor) {
+        Some(name) => print!(uart, "Vendor: {}", name),
+        None => print!(uart, "Vendor: {:x}", vendor),
+    }
+    match gen_mvendorid::architecture_name(architecture) {
+        Some(name) => print!(uart, " | Architecture: {}", name),
+        None => print!(uart, " | Architecture: {:x}", architecture),
+    }
+    println!(uart, " | Implementation: {:x}", implementation);
 }
 
-fn print_misa_info(uart: &mut impl Write) {
+fn print_cpu_features(uart: &mut impl Write) {
+    let features = boot::cpu_features();
     printhdr!(uart, "Machine Instruction Set Architecture");
-    let misa = Misa::get();
-    let Some(misa) = misa else {
-        println!(uart, "ERROR: MISA reported unexpected value 0x{:?}", misa);
+    let Some(misa) = features.misa else {
+        println!(uart, "MISA not implemented, probed: {:?}", features);
         return;
     };
 
     println!(uart, "Reported base width: {}", misa.xlen());
 
-    let extensions = misa.extensions();
     print!(uart, "Extensions: ");
-    for (i, letter) in Misa::EXTENSION_NAMES.chars().enumerate() {
-        let mask = 1 << i;
-        if extensions & mask > 0 {
-            print!(uart, "{}", letter);
-        }
+    for extension in misa.iter() {
+        print!(uart, "{}", extension.letter());
     }
     println!(uart,);
+    println!(uart, "Paging modes: {:?}", features.paging);
     printhdr!(uart, "Extensions");
-    for (i, desc) in Misa::EXTENSION_DESCRIPTIONS.iter().enumerate() {
-        let mask = 1 << i;
-        if extensions & mask > 0 {
-            println!(uart, "{}", desc);
-        }
+    for extension in misa.iter() {
+        println!(uart, "{}", extension.description());
     }
 }
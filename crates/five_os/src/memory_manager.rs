@@ -1,3 +1,5 @@
+use core::ptr::addr_of_mut;
+
 use five_os::layout::LinkerLayout;
 use fiveos_allocator::page::PageAllocator;
 use fiveos_riscv::mmu::page_table::PAGE_SIZE;
@@ -10,8 +12,9 @@ static mut KERNEL_PAGE_ALLOCATOR: PageAllocator<PAGE_SIZE> = PageAllocator::unin
 /// ## Safety
 /// This is expected to only run once, in kinit.
 pub unsafe fn init_allocator(layout: &LinkerLayout) -> &'static mut PageAllocator<PAGE_SIZE> {
-    let bitmap_start = layout.heap_start;
-    let end = layout.memory_end;
+    // the allocator hands out physical addresses
+    let bitmap_start = layout.physical(layout.heap_start);
+    let end = layout.physical(layout.memory_end - 1) + 1;
     let page_allocator = PageAllocator::new(bitmap_start, end);
     let page_allocator = unsafe {
         KERNEL_PAGE_ALLOCATOR = page_allocator;
@@ -19,3 +22,15 @@ pub unsafe fn init_allocator(layout: &LinkerLayout) -> &'static mut PageAllocato
     };
     page_allocator
 }
+
+/// Allocates zeroed pages once kinit is done with the allocator, such as
+/// for a page fault resolver
+///
+/// ## Safety
+/// Accesses static mut without a lock, so must not run on two harts at once,
+/// or while kinit's reference from init_allocator is in use.
+pub unsafe fn kernel_zalloc(count: usize) -> Option<*mut u8> {
+    (*addr_of_mut!(KERNEL_PAGE_ALLOCATOR))
+        .zalloc(count)
+        .map(|page| page as *mut u8)
+}
@@ -1,9 +1,9 @@
+use fiveos_riscv::cpu::registers::mstatus::SStatus;
 use fiveos_riscv::mmu::page_table::descriptor::PageTableDescriptor;
-use fiveos_riscv::mmu::page_table::untyped::PageTableUntyped;
 use fiveos_riscv::mmu::page_table::PAGE_SIZE;
 use fiveos_riscv::mmu::EntryFlags;
 
-use crate::trap::TrapFrame;
+use crate::{boot, trap::TrapFrame};
 
 // todo: replace with atomic increment
 static mut PID_COUNTER: u32 = 0;
@@ -30,10 +30,22 @@ pub struct Process {
     instruction_pointer: usize,
     stack_pointer: *mut u8,
     page_table: *mut u8,
+    /// registers while not running, with f0-f31 and fcsr in fregs and fcsr
+    /// once the process has used them
     trap_frame: TrapFrame,
+    /// sstatus while not running, whose FS tells trap.s whether the
+    /// floating point registers need saving, see [`Process::initial_status`]
+    status: SStatus,
 }
 
 impl Process {
+    /// The sstatus a process starts with: returning to user mode with
+    /// interrupts enabled, and floating point Initial on harts which have it
+    pub fn initial_status() -> SStatus {
+        SStatus::new()
+            .with_spie(true)
+            .with_fs(boot::cpu_features().initial_fp_status())
+    }
     pub fn new(func: fn(), descriptor: &PageTableDescriptor) -> Option<Process> {
         todo!()
     }
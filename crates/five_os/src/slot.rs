@@ -0,0 +1,60 @@
+//! A function pointer set once and read from any hart, for the handlers
+//! the trap handler hands interrupts and page faults to.
+
+use core::{
+    marker::PhantomData,
+    mem::{size_of, transmute_copy},
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+/// Where a function of type F is kept, as a pointer or null
+pub struct Slot<F> {
+    pointer: AtomicPtr<()>,
+    function: PhantomData<F>,
+}
+
+impl<F: Copy> Slot<F> {
+    pub const fn empty() -> Slot<F> {
+        Slot {
+            pointer: AtomicPtr::new(null_mut()),
+            function: PhantomData,
+        }
+    }
+    /// Keeps the function, false if one is already kept
+    pub fn set(&self, function: F) -> bool {
+        const { assert!(size_of::<F>() == size_of::<*mut ()>()) };
+        // safety: the same size, as checked above
+        let pointer = unsafe { transmute_copy::<F, *mut ()>(&function) };
+        self.pointer
+            .compare_exchange(null_mut(), pointer, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+    pub fn get(&self) -> Option<F> {
+        let pointer = self.pointer.load(Ordering::Acquire);
+        // safety: only ever set from an F
+        (!pointer.is_null()).then(|| unsafe { transmute_copy::<*mut (), F>(&pointer) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Slot;
+
+    fn one() -> usize {
+        1
+    }
+
+    fn two() -> usize {
+        2
+    }
+
+    #[test]
+    fn set_once() {
+        let slot = Slot::<fn() -> usize>::empty();
+        assert!(slot.get().is_none());
+        assert!(slot.set(one));
+        assert!(!slot.set(two));
+        assert_eq!(slot.get().map(|function| function()), Some(1));
+    }
+}
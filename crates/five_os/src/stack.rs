@@ -0,0 +1,66 @@
+//! Kernel and trap stacks for each hart, and the guard pages below them.
+//!
+//! Each guard page is left unmapped in the kernel page table so a stack
+//! overflow faults instead of silently overwriting whatever lies below.
+//! Machine mode does not translate addresses, so the guards only catch
+//! overflows of code running with translation on.
+
+use core::ops::Range;
+
+use fiveos_riscv::mmu::page_table::PAGE_SIZE;
+
+use crate::{layout::LinkerLayout, trap::TrapFrame};
+
+/// Bytes of the kernel stack given to each hart by boot.s, sbi_boot.s and
+/// hart.s, including its guard page.
+pub const HART_STACK_SIZE: usize = 0x10000;
+
+/// Pages allocated for each trap stack, including its guard page.
+pub const TRAP_STACK_PAGES: usize = 2;
+
+/// Bytes of a trap stack above its guard page, which trap.s is given to
+/// tell a nested trap from an overflow of the trap stack.
+pub const TRAP_STACK_USABLE: usize = (TRAP_STACK_PAGES - 1) * PAGE_SIZE;
+
+/// Number of harts which fit a stack in the stack section of the layout
+pub fn hart_stack_count(layout: &LinkerLayout) -> usize {
+    (layout.stack_end - layout.stack_start) / HART_STACK_SIZE
+}
+
+/// The guard page at the bottom of the given hart's kernel stack
+pub fn hart_stack_guard(layout: &LinkerLayout, hart: usize) -> Range<usize> {
+    let bottom = layout.stack_end - (hart + 1) * HART_STACK_SIZE;
+    bottom..bottom + PAGE_SIZE
+}
+
+/// The guard page at the bottom of a trap stack, given the top of the stack
+pub fn trap_stack_guard(trap_stack: usize) -> Range<usize> {
+    let bottom = trap_stack - TRAP_STACK_PAGES * PAGE_SIZE;
+    bottom..bottom + PAGE_SIZE
+}
+
+/// Whether a trap taken with this sp interrupted the handler on the trap
+/// stack, as decided by save_frame in trap.s before it calls any rust.
+/// An sp in the guard page is not, so the trap handler overflowing its stack
+/// goes on to be reported as an overflow, on the trap stack from its top.
+pub fn is_nested(trap_stack: usize, sp: usize) -> bool {
+    (trap_stack - TRAP_STACK_USABLE..=trap_stack).contains(&sp)
+}
+
+/// Finds the hart whose kernel or trap stack overflowed, if the address is
+/// in one of the guard pages. The trap stacks are those of the frames, which
+/// are indexed by hart.
+pub fn overflowed_hart(
+    layout: &LinkerLayout,
+    frames: &[TrapFrame],
+    address: usize,
+) -> Option<usize> {
+    (0..hart_stack_count(layout))
+        .find(|&hart| hart_stack_guard(layout, hart).contains(&address))
+        .or_else(|| {
+            frames.iter().position(|frame| {
+                !frame.trap_stack.is_null()
+                    && trap_stack_guard(frame.trap_stack as usize).contains(&address)
+            })
+        })
+}
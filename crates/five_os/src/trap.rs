@@ -1,9 +1,31 @@
-use core::ptr::null_mut;
-use fiveos_riscv::cpu::registers::mcause::{AsyncCause, KnownCause, SyncCause, TrapCause};
+use core::{
+    fmt::Write,
+    mem::offset_of,
+    ptr::{addr_of, addr_of_mut, null_mut},
+};
+use fiveos_riscv::cpu::registers::{
+    csr::{
+        sie::{self, Sie},
+        sip::{self, Sip},
+        sscratch::{self, Sscratch},
+        sstatus,
+    },
+    mcause::{Exception, Interrupt, Trap, TrapCause},
+    mstatus::{MStatus, SStatus, UnitStatus},
+    mtvec::{set_supervisor_trap_vector, Stvec, TrapEntry},
+    satp,
+};
+use fiveos_riscv::{instruction::ECALL_LENGTH, vector_table};
 use fiveos_virtio::plic::PLIC;
-use fiveos_virtio::uart::{Uart, Uart0, UART_BASE_ADDRESS};
 
+use crate::emulate;
+use crate::fault::{self, Access, PageFault, Resolution};
+use crate::interrupts::{self, InterruptSource};
 use crate::layout::LinkerLayout;
+use crate::{
+    boot, console, machine,
+    stack::{self, TRAP_STACK_USABLE},
+};
 use crate::{print, println};
 
 /// Context information collected in trap.s before calling rust trap handler
@@ -12,7 +34,8 @@ use crate::{print, println};
 pub struct TrapFrame {
     /// General purpose registers
     pub regs: [usize; 32],
-    /// Floating point purpose registers
+    /// Floating point purpose registers, saved along with fcsr only
+    /// when the trapped code dirtied them, see mstatus.FS
     pub fregs: [usize; 32],
     /// satp
     pub satp: usize,
@@ -20,55 +43,177 @@ pub struct TrapFrame {
     pub trap_stack: *mut u8,
     /// core id
     pub hartid: usize,
+    /// floating point control and status
+    pub fcsr: usize,
 }
 
 impl TrapFrame {
+    /// Offsets of the fields trap.s uses, given to it by assembly.rs
+    pub const REGS_OFFSET: usize = offset_of!(TrapFrame, regs);
+    pub const FREGS_OFFSET: usize = offset_of!(TrapFrame, fregs);
+    pub const TRAP_STACK_OFFSET: usize = offset_of!(TrapFrame, trap_stack);
+    pub const HARTID_OFFSET: usize = offset_of!(TrapFrame, hartid);
+    pub const FCSR_OFFSET: usize = offset_of!(TrapFrame, fcsr);
     const NULL: TrapFrame = TrapFrame {
         regs: [0; 32],
         fregs: [0; 32],
         satp: 0,
         trap_stack: null_mut(),
         hartid: 0,
+        fcsr: 0,
     };
     pub const fn zero() -> TrapFrame {
         TrapFrame::NULL
     }
 }
 
-/// Global store of trapframes, one per core/hart. we're providing for 4 harts here.
-/// todo: initialize in kinit with accurate number of harts
-pub static mut GLOBAL_TRAPFRAMES: &mut [TrapFrame; 4] = &mut [TrapFrame::NULL; 4];
+/// Where trap.s finds status.FS, given to it by assembly.rs with the value
+/// it saves the floating point registers for
+pub const FS_SHIFT: usize = 13;
+pub const FS_DIRTY: usize = UnitStatus::Dirty as usize;
 
-fn handle_external_interrupt(uart: &mut Uart0, hart: usize) {
+/// Whether trap.s saves the floating point registers of a trap taken with
+/// this status, see save_fp_state
+pub fn saves_fp_state(status: usize) -> bool {
+    (status >> FS_SHIFT) & 0b11 == FS_DIRTY
+}
+
+/// The status a supervisor trap taken with this status returns with, FS
+/// Clean if the registers were saved, see restore_fp_state
+pub fn restored_fp_status(status: usize) -> usize {
+    if saves_fp_state(status) {
+        status & !(1 << FS_SHIFT)
+    } else {
+        status
+    }
+}
+
+/// Start the kernel's context on this hart with the floating point status
+/// of a new context, see [`CpuFeatures::initial_fp_status`], so trap.s saves
+/// the registers once the kernel uses them.
+///
+/// [`CpuFeatures::initial_fp_status`]: fiveos_riscv::cpu::features::CpuFeatures::initial_fp_status
+///
+/// ## Safety
+/// Must run as the hart enters the kernel, after the features are probed
+/// and before the kernel has used floating point registers.
+pub unsafe fn init_fp_status() {
+    let fs = boot::cpu_features().initial_fp_status();
+    sstatus::modify(|status| status.with_fs(fs));
+}
+
+/// Global store of trapframes, indexed by hart id, and sized by kinit from
+/// the harts it finds. These are the kernel's, for supervisor mode traps,
+/// see machine.rs for machine mode's.
+pub static mut GLOBAL_TRAPFRAMES: &mut [TrapFrame] = &mut [];
+
+/// The boot hart's trap frame until kinit has made GLOBAL_TRAPFRAMES,
+/// so a trap taken that early has somewhere to save registers and a stack
+/// to be reported on.
+static mut BOOT_TRAPFRAME: TrapFrame = TrapFrame::zero();
+
+/// Only the part of a trap stack above its guard page, as it has none
+#[repr(C, align(16))]
+struct BootTrapStack([u8; TRAP_STACK_USABLE]);
+
+static mut BOOT_TRAP_STACK: BootTrapStack = BootTrapStack([0; TRAP_STACK_USABLE]);
+
+/// Hands the boot trap frame to trap.s, through sscratch, until
+/// init_global_pages installs the boot hart's own with install_trap_frame.
+///
+/// ## Safety
+/// Only for the boot hart, at the start of kinit while the others are parked.
+pub unsafe fn install_boot_trap_frame(hart: usize) {
+    let frame = &mut *addr_of_mut!(BOOT_TRAPFRAME);
+    frame.hartid = hart;
+    frame.trap_stack = (addr_of_mut!(BOOT_TRAP_STACK) as *mut u8).add(TRAP_STACK_USABLE);
+    sscratch::write(Sscratch::from_bits(frame as *mut TrapFrame as usize));
+}
+
+/// Hands this hart's trap frame to trap.s, through sscratch. Interrupts
+/// must not be enabled on the hart before this.
+///
+/// ## Safety
+/// The frame must be set up, with its trap stack, and stay at this address
+/// for as long as the hart takes traps.
+pub unsafe fn install_trap_frame(hart: usize) {
+    let frame = &mut (*addr_of_mut!(GLOBAL_TRAPFRAMES))[hart];
+    sscratch::write(Sscratch::from_bits(frame as *mut TrapFrame as usize));
+}
+
+/// Claims the PLIC's interrupt and runs the handler the device's driver registered
+fn handle_external_interrupt(uart: &mut impl Write, hart: usize) {
     if let Some(interrupt) = PLIC.claim() {
-        match interrupt.number() {
-            10 => {
-                // on qemu/virtio this is the UART singaling input
-                // todo: can these match statements be extricated from the trap handler logic
-                // so different hardware can be supported by compile options
-
-                if let Some(c) = uart.get() {
-                    match c {
-                        8 => {
-                            print!(uart, "\x08 \x08");
-                        }
-                        10 | 13 => {
-                            println!(uart, "");
-                        }
-                        _ => {
-                            print!(uart, "{}", c as char);
-                        }
-                    }
-                }
-            }
-            _ => (),
+        let source = InterruptSource::External(interrupt.number());
+        if !interrupts::dispatch(source, hart) {
+            println!(uart, "Unhandled {:?}: core#{}", source, hart);
         }
         PLIC.complete(interrupt);
     } else {
-        println!(uart, "Machine external interrupt: core#{}", hart);
+        println!(uart, "External interrupt with none to claim: core#{}", hart);
+    }
+}
+
+extern "C" {
+    fn asm_supervisor_trap_vector();
+    fn asm_trap_vector();
+    fn asm_machine_software_vector();
+    fn asm_machine_timer_vector();
+}
+
+vector_table! {
+    /// Machine mode's vector table, see trap.s. Exceptions and any
+    /// interrupts without their own entry go to rust_trap.
+    pub fn machine_vectors {
+        exception: asm_trap_vector,
+        default: asm_trap_vector,
+        MachineSoftware: asm_machine_software_vector,
+        MachineTimer: asm_machine_timer_vector,
     }
 }
 
+/// The vector for when vectored mode is not supported
+pub fn machine_trap_entry() -> TrapEntry {
+    asm_trap_vector
+}
+
+/// Report a fault in the guard page below one of the stacks as an overflow,
+/// rather than as a generic page fault.
+fn check_stack_overflow(layout: &LinkerLayout, frames: &[TrapFrame], tval: usize) {
+    if let Some(hart) = stack::overflowed_hart(layout, frames, tval) {
+        panic!("stack overflow on hart {}: fault at 0x{:08x}", hart, tval);
+    }
+}
+
+/// Passes a page fault to the resolver, returning if the faulting
+/// instruction can run again
+fn handle_page_fault(fault: &PageFault) {
+    match fault::resolve(fault) {
+        Resolution::Retry => (),
+        // there are no processes yet, so nothing to kill but the kernel
+        Resolution::Kill => panic!("Killed by page fault: {:?}", fault),
+        Resolution::Unresolved => panic!(
+            "{:?} page fault: #{}/0x{:08x}/0x{:08x}",
+            fault.access, fault.hart, fault.epc, fault.address
+        ),
+    }
+}
+
+/// Called by trap.s instead of a handler for a trap taken while on the trap
+/// stack, whose frame holds the registers of the trap it interrupted
+#[no_mangle]
+extern "C" fn rust_nested_trap(frame: &TrapFrame, cause: usize, epc: usize) -> ! {
+    panic!(
+        "nested trap on hart {}: {:?} at 0x{:08x}, with sp 0x{:08x} on the trap stack",
+        frame.hartid,
+        TrapCause(cause).trap(),
+        epc,
+        frame.regs[2]
+    );
+}
+
+/// Handles the traps machine mode keeps: its own interrupts, and ecalls
+/// from supervisor mode. Everything else is delegated to rust_supervisor_trap.
 #[no_mangle]
 #[repr(align(4))]
 extern "C" fn rust_trap(
@@ -79,88 +224,311 @@ extern "C" fn rust_trap(
     status: usize,
     frame: &mut TrapFrame,
 ) -> usize {
-    let cause = TrapCause(acause).known_cause();
+    let cause = TrapCause(acause).trap();
+    let mut return_pc = epc;
+    // safety: see console
+    let mut uart = unsafe { console() };
+    use Exception as E;
+    match cause {
+        Trap::Interrupt(interrupt) => machine_interrupt(interrupt, hart),
+        Trap::Exception(exception) => match exception {
+            E::EnvironmentCallSmode => {
+                machine::supervisor_ecall(frame, hart);
+                return_pc += ECALL_LENGTH;
+            }
+            E::EnvironmentCallMmode => {
+                println!(
+                    uart,
+                    "External call from Machine mode?!:#{}/0x{:08x}", hart, epc
+                );
+                return_pc += ECALL_LENGTH;
+            }
+            _ => panic!(
+                "Unhandled machine mode trap: #{}/0x{:08x}/{} {:?} {:?}",
+                hart,
+                epc,
+                tval,
+                exception,
+                MStatus::from_bits(status)
+            ),
+        },
+    }
+    return_pc
+}
+
+/// Handles the kernel's traps, delegated to supervisor mode by kinit_delegation
+#[no_mangle]
+#[repr(align(4))]
+extern "C" fn rust_supervisor_trap(
+    epc: usize,
+    tval: usize,
+    acause: usize,
+    hart: usize,
+    status: usize,
+    frame: &mut TrapFrame,
+) -> usize {
+    let cause = TrapCause(acause).trap();
     let mut return_pc = epc;
-    // let cause_number = cause.number();
-    // safety: this is not actually safe. Only in place for debug prints.
-    let mut uart = unsafe { Uart0::new() };
-    use AsyncCause as AC;
-    use KnownCause as KC;
-    use SyncCause as SC;
+    // safety: see console
+    let mut uart = unsafe { console() };
+    use Exception as E;
+    use Interrupt as I;
     match cause {
-        KC::Sync(sync_cause) => match sync_cause {
-            SC::MachineSoftwareInterrupt => {
-                println!(uart, "Machine software interrupt: core#{}", hart)
+        Trap::Interrupt(interrupt) => match interrupt {
+            I::SupervisorSoftware => {
+                unsafe { sip::clear(Sip::new().with_ssip(true)) };
+                if !interrupts::dispatch(InterruptSource::Software, hart) {
+                    println!(uart, "Supervisor software interrupt: core#{}", hart);
+                }
+            }
+            I::SupervisorTimer => {
+                if !interrupts::dispatch(InterruptSource::Timer, hart) {
+                    println!(uart, "Supervisor timer interrupt: core#{}", hart);
+                    // stays pending until the next timer is set through machine mode
+                    unsafe { sie::clear(Sie::new().with_stie(true)) };
+                }
             }
-            SC::MachineTimerInterrupt => println!(uart, "Machine timer interrupt: core#{}", hart),
-            SC::MachineExternalInterrupt => handle_external_interrupt(&mut uart, hart),
-            _ => panic!("Unhandled async trap: core#{} -> {:#x}\n", hart, acause),
+            I::SupervisorExternal => handle_external_interrupt(&mut uart, hart),
+            _ => panic!("Unhandled interrupt: core#{} -> {:?}\n", hart, interrupt),
         },
-        KC::Async(async_cause) => match async_cause {
-            AC::InstructionAddressMisaligned => panic!(
+        Trap::Exception(exception) => match exception {
+            E::InstructionAddressMisaligned => panic!(
                 "Instruction address misaligned: #{}/0x{:08x}/{}",
                 hart, epc, tval
             ),
-            AC::InstructionAccessFault => panic!(
-                "Instruction access fault: #{}/0x{:08x}/{} {status}: {frame:?}",
-                hart, epc, tval
+            E::InstructionAccessFault => panic!(
+                "Instruction access fault: #{}/0x{:08x}/{} {:?}: {frame:?}",
+                hart,
+                epc,
+                tval,
+                SStatus::from_bits(status)
             ),
-            AC::IllegalInstruction => {
-                panic!("Illegal instruction: #{}/0x{:08x}/{}", hart, epc, tval)
+            E::IllegalInstruction => {
+                match unsafe { emulate::illegal_instruction(frame, epc, tval) } {
+                    Some(pc) => return_pc = pc,
+                    None => panic!("Illegal instruction: #{}/0x{:08x}/{}", hart, epc, tval),
+                }
             }
-            AC::Breakpoint => {
+            E::Breakpoint => {
                 println!(uart, "Skipped breakpoint: #{}/0x{:08x}", hart, epc);
-                return_pc += 4;
+                return_pc = unsafe { emulate::skip_instruction(epc) };
             }
-            AC::LoadAddressMisaligned => {
-                panic!("Load address misaligned: #{}/0x{:08x}/{}", hart, epc, tval)
+            E::LoadAddressMisaligned | E::StoreAMOAddressMisaligned => {
+                match unsafe { emulate::misaligned_access(frame, epc) } {
+                    Some(pc) => return_pc = pc,
+                    None => panic!("{:?}: #{}/0x{:08x}/{}", exception, hart, epc, tval),
+                }
             }
-            AC::LoadAccessFault => panic!("Load access fault: #{}/0x{:08x}/{}", hart, epc, tval),
-            AC::StoreAMOAddressMisaligned => panic!(
-                "Store/AMO address misaligned: #{}/0x{:08x}/{}",
-                hart, epc, tval
-            ),
-            AC::StoreAMOAccessFault => panic!("Store/AMO fault: #{}/0x{:08x}/{}", hart, epc, tval),
-            AC::EnvironmentCallUmode => {
+            E::LoadAccessFault => panic!("Load access fault: #{}/0x{:08x}/{}", hart, epc, tval),
+            E::StoreAMOAccessFault => panic!("Store/AMO fault: #{}/0x{:08x}/{}", hart, epc, tval),
+            E::EnvironmentCallUmode => {
                 println!(
                     uart,
                     "External call from user mode: #{}/0x{:08x}", hart, epc
                 );
-                return_pc += 4;
+                return_pc += ECALL_LENGTH;
             }
-            AC::EnvironmentCallSmode => {
-                println!(
-                    uart,
-                    "External call from supervisor mode:#{}/0x{:08x}", hart, epc
-                );
-                return_pc += 4;
+            E::InstructionPageFault | E::LoadPageFault | E::StoreAMOPageFault => {
+                // safety: the trap stacks are only written during kinit
+                let frames = unsafe { &*addr_of!(GLOBAL_TRAPFRAMES) };
+                check_stack_overflow(&LinkerLayout::get(), frames, tval);
+                let fault = PageFault {
+                    hart,
+                    epc,
+                    address: tval,
+                    access: Access::from_exception(exception).unwrap(),
+                    user: !SStatus::from_bits(status).spp(),
+                    satp: satp::read(),
+                };
+                handle_page_fault(&fault);
             }
-            AC::EnvironmentCallMmode => {
-                println!(
-                    uart,
-                    "External call from Machine mode?!:#{}/0x{:08x}", hart, epc
-                );
-                return_pc += 4;
-            }
-            AC::InstructionPageFault => {
-                panic!("Instruction page fault: #{}/0x{:08x}/{}", hart, epc, tval);
-            }
-            AC::LoadPageFault => {
-                panic!("Load page fault: #{}/0x{:08x}/{}", hart, epc, tval);
-            }
-            AC::StoreAMOPageFault => {
-                panic!("Store page fault: #{}/0x{:08x}/{}", hart, epc, tval);
-            }
-            AC::Reserved => {
-                panic!("Unhandled synchronous trap: #{}/{:?}", hart, acause);
+            _ => {
+                panic!("Unhandled synchronous trap: #{}/{:?}", hart, exception);
             }
         },
     }
     return_pc
 }
 
-pub fn setup_trap_handler() {
-    let layout = LinkerLayout::get();
-    let _ts = layout.trap_start;
-    // TODO: set up trap handler
+/// Handles one of the interrupts machine mode keeps, whether it came through
+/// rust_trap or one of the interrupt's own entries in [`machine_vectors`]
+fn machine_interrupt(interrupt: Interrupt, hart: usize) {
+    match interrupt {
+        Interrupt::MachineSoftware => machine::software_interrupt(hart),
+        Interrupt::MachineTimer => machine::forward_timer(),
+        // external interrupts go to supervisor mode, whose PLIC context
+        // handle_external_interrupt claims from, and meie is never set
+        _ => panic!("Unhandled interrupt: core#{} -> {:?}\n", hart, interrupt),
+    }
+}
+
+/// Defines the handlers trap.s calls from the machine interrupts' entries in
+/// [`machine_vectors`], which take rust_trap's arguments and pass the
+/// interrupt their slot is for to machine_interrupt.
+macro_rules! machine_interrupt_entries {
+    ($($name:ident: $interrupt:ident),* $(,)?) => {
+        $(
+            #[doc = concat!("Entry from the ", stringify!($interrupt), " slot of [`machine_vectors`]")]
+            #[no_mangle]
+            extern "C" fn $name(
+                epc: usize,
+                _tval: usize,
+                _cause: usize,
+                hart: usize,
+                _status: usize,
+                _frame: &mut TrapFrame,
+            ) -> usize {
+                machine_interrupt(Interrupt::$interrupt, hart);
+                epc
+            }
+        )*
+    };
+}
+
+machine_interrupt_entries! {
+    rust_machine_software: MachineSoftware,
+    rust_machine_timer: MachineTimer,
+}
+
+/// Sends the kernel's traps to the supervisor entry in trap.s, through
+/// the trap frame put in sscratch by install_trap_frame.
+///
+/// ## Safety
+/// sscratch must already hold a trap frame, from install_trap_frame or
+/// install_boot_trap_frame, as trap.s saves registers to it on any trap.
+pub unsafe fn setup_trap_handler() {
+    let vector = Stvec::direct(asm_supervisor_trap_vector);
+    if !set_supervisor_trap_vector(vector) {
+        panic!("supervisor trap vector not supported");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fiveos_riscv::cpu::registers::mstatus::{SStatus, UnitStatus};
+
+    use super::{check_stack_overflow, restored_fp_status, saves_fp_state, TrapFrame};
+    use crate::layout::LinkerLayout;
+    use crate::stack::{hart_stack_guard, is_nested, trap_stack_guard, HART_STACK_SIZE};
+
+    const STACK_END: usize = 0x8020_0000;
+
+    /// A layout with stacks for 4 harts, the only part used here
+    fn layout() -> LinkerLayout {
+        LinkerLayout {
+            text_start: 0,
+            trap_start: 0,
+            text_end: 0,
+            global_pointer: 0,
+            rodata_start: 0,
+            rodata_end: 0,
+            data_start: 0,
+            data_end: 0,
+            bss_start: 0,
+            bss_end: 0,
+            memory_start: 0,
+            stack_start: STACK_END - 4 * HART_STACK_SIZE,
+            stack_end: STACK_END,
+            heap_start: 0,
+            heap_size: 0,
+            memory_end: 0,
+            trap_vector: 0,
+            kernel_offset: 0,
+        }
+    }
+
+    /// Frames for 4 harts, with trap stacks for all but hart 0
+    fn frames() -> [TrapFrame; 4] {
+        let mut frames = [TrapFrame::zero(); 4];
+        for (hart, frame) in frames.iter_mut().enumerate().skip(1) {
+            frame.trap_stack = (0x9000_0000 + hart * 0x10000) as *mut u8;
+        }
+        frames
+    }
+
+    #[test]
+    #[should_panic(expected = "stack overflow on hart 2")]
+    fn kernel_stack_guard() {
+        let layout = layout();
+        let guard = hart_stack_guard(&layout, 2);
+        check_stack_overflow(&layout, &frames(), guard.end - 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "stack overflow on hart 3")]
+    fn trap_stack_guard_page() {
+        let frames = frames();
+        let guard = trap_stack_guard(frames[3].trap_stack as usize);
+        check_stack_overflow(&layout(), &frames, guard.start);
+    }
+
+    #[test]
+    #[should_panic(expected = "stack overflow on hart 1")]
+    fn trap_stack_overflow_is_not_nested() {
+        let frames = frames();
+        let trap_stack = frames[1].trap_stack as usize;
+        let guard = trap_stack_guard(trap_stack);
+        // the lowest the handler can go and still nest another trap
+        assert!(is_nested(trap_stack, guard.end));
+        assert!(is_nested(trap_stack, trap_stack));
+        // the handler's push into the guard page faults with sp there, and is
+        // handled from the top of the trap stack rather than as nested
+        let sp = guard.end - 16;
+        assert!(!is_nested(trap_stack, sp));
+        check_stack_overflow(&layout(), &frames, sp + 8);
+    }
+
+    #[test]
+    fn other_faults() {
+        let layout = layout();
+        let frames = frames();
+        let trap_guard = trap_stack_guard(frames[1].trap_stack as usize);
+        for address in [
+            0,
+            hart_stack_guard(&layout, 0).end,
+            hart_stack_guard(&layout, 3).start - 1,
+            STACK_END,
+            trap_guard.end,
+            trap_guard.start - 1,
+        ] {
+            check_stack_overflow(&layout, &frames, address);
+        }
+    }
+
+    fn status(fs: UnitStatus) -> usize {
+        SStatus::new().with_sie(true).with_fs(fs).bits()
+    }
+
+    #[test]
+    fn only_dirty_floating_point_is_saved() {
+        assert!(!saves_fp_state(status(UnitStatus::Off)));
+        assert!(!saves_fp_state(status(UnitStatus::Initial)));
+        assert!(!saves_fp_state(status(UnitStatus::Clean)));
+        assert!(saves_fp_state(status(UnitStatus::Dirty)));
+    }
+
+    #[test]
+    fn restored_floating_point_is_clean() {
+        let restored = SStatus::from_bits(restored_fp_status(status(UnitStatus::Dirty)));
+        assert_eq!(restored.fs(), UnitStatus::Clean);
+        assert!(restored.sie());
+        // nothing was saved, so nothing changes
+        for fs in [UnitStatus::Off, UnitStatus::Initial, UnitStatus::Clean] {
+            assert_eq!(restored_fp_status(status(fs)), status(fs));
+        }
+    }
+
+    /// a context starting Initial is saved once it writes its registers,
+    /// and again only after it writes them since the last restore
+    #[test]
+    fn floating_point_saved_after_each_write() {
+        let started = status(UnitStatus::Initial);
+        assert!(!saves_fp_state(started));
+        let written = status(UnitStatus::Dirty);
+        assert!(saves_fp_state(written));
+        let restored = restored_fp_status(written);
+        assert!(!saves_fp_state(restored));
+        let written_again = restored | status(UnitStatus::Dirty);
+        assert!(saves_fp_state(written_again));
+    }
 }
@@ -1,5 +1,3 @@
-#[repr(transparent)]
-pub struct PhysicalAddress(u64);
-pub struct InvalidPhysicalAddress(u64);
-#[repr(transparent)]
-pub struct VirtualAddress(u64);
+//! Address types, see [`crate::mmu`] for their use in address translation.
+
+pub use crate::mmu::{physical_address::PhysAddr, virtual_address::VirtAddr};
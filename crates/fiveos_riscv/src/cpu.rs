@@ -1 +1,3 @@
+pub mod counters;
+pub mod features;
 pub mod registers;
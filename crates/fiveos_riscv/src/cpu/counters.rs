@@ -0,0 +1,317 @@
+//! The hardware performance counters: cycle, time, instret and the
+//! programmable hpmcounter3 to hpmcounter31.
+//!
+//! Counters are read through the unprivileged csrs, which machine mode can
+//! always read and lower modes can read once enabled in mcounteren and
+//! scounteren. Machine mode can also write its copies of the counters and
+//! choose the events the programmable counters count.
+//!
+//! All counters are 64 bits wide, RV32 reads them in two halves.
+
+pub use super::registers::csr::{mcounteren, mcountinhibit, scounteren};
+
+/// The first programmable counter
+pub const HPM_FIRST: usize = 3;
+/// The last programmable counter
+pub const HPM_LAST: usize = 31;
+
+/// One of the counters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Counter {
+    /// clock cycles of this hart
+    Cycle,
+    /// wall-clock time, at a rate set by the platform
+    Time,
+    /// instructions retired by this hart
+    Instret,
+    /// a programmable counter, see [`Counter::hpm`]
+    Hpm(HpmIndex),
+}
+
+/// The index of a programmable counter, only made by [`Counter::hpm`]
+/// so it is always from 3 to 31.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HpmIndex(usize);
+
+impl HpmIndex {
+    pub const fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl Counter {
+    /// The programmable counter with this index, from 3 to 31
+    pub const fn hpm(index: usize) -> Counter {
+        assert!(
+            index >= HPM_FIRST && index <= HPM_LAST,
+            "no such programmable counter"
+        );
+        Counter::Hpm(HpmIndex(index))
+    }
+    /// The bit for this counter in mcounteren, scounteren and mcountinhibit
+    pub const fn bit(self) -> usize {
+        match self {
+            Counter::Cycle => 1 << 0,
+            Counter::Time => 1 << 1,
+            Counter::Instret => 1 << 2,
+            Counter::Hpm(HpmIndex(index)) => 1 << index,
+        }
+    }
+    /// Read the counter, which must be enabled for the current privilege mode
+    pub fn read(self) -> u64 {
+        match self {
+            Counter::Cycle => cycle(),
+            Counter::Time => time(),
+            Counter::Instret => instret(),
+            Counter::Hpm(HpmIndex(index)) => hpmcounter(index),
+        }
+    }
+}
+
+/// Reads a 64 bit counter csr, named by a prefix and optional index
+macro_rules! read_counter {
+    ($name:literal $(, $index:literal)?) => {{
+        #[cfg(target_pointer_width = "64")]
+        {
+            let value: u64;
+            unsafe { riscv_asm!(concat!("csrr {}, ", $name $(, $index)?), out(reg) value) };
+            value
+        }
+        // reread the high half in case the low half overflowed in between
+        #[cfg(target_pointer_width = "32")]
+        loop {
+            let (high, low, check): (u32, u32, u32);
+            unsafe {
+                riscv_asm!(
+                    concat!("csrr {}, ", $name $(, $index)?, "h"),
+                    concat!("csrr {}, ", $name $(, $index)?),
+                    concat!("csrr {}, ", $name $(, $index)?, "h"),
+                    out(reg) high,
+                    out(reg) low,
+                    out(reg) check,
+                )
+            };
+            if high == check {
+                break (high as u64) << 32 | low as u64;
+            }
+        }
+    }};
+}
+
+/// Writes a 64 bit machine counter csr, named by a prefix and optional index
+macro_rules! write_counter {
+    ($value:expr, $name:literal $(, $index:literal)?) => {{
+        let value: u64 = $value;
+        #[cfg(target_pointer_width = "64")]
+        riscv_asm!(concat!("csrw ", $name $(, $index)?, ", {}"), in(reg) value);
+        // clear the low half first so it can't carry into the new high half
+        #[cfg(target_pointer_width = "32")]
+        riscv_asm!(
+            concat!("csrw ", $name $(, $index)?, ", zero"),
+            concat!("csrw ", $name $(, $index)?, "h, {}"),
+            concat!("csrw ", $name $(, $index)?, ", {}"),
+            in(reg) (value >> 32) as u32,
+            in(reg) value as u32,
+        );
+    }};
+}
+
+/// Matches the index of a programmable counter, passing it to $action as a
+/// literal, as the csr it names must be known when assembling.
+macro_rules! match_hpm {
+    ($index:expr, $action:ident!($($args:tt)*)) => {
+        match_hpm!(@ $index, $action, ($($args)*),
+            3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17,
+            18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31)
+    };
+    (@ $index:expr, $action:ident, $args:tt, $($n:literal),*) => {
+        match $index {
+            $($n => match_hpm!(@call $action, $args, $n),)*
+            _ => panic!("no programmable counter {}", $index),
+        }
+    };
+    (@call $action:ident, ($($args:tt)*), $n:literal) => {
+        $action!($($args)*, $n)
+    };
+}
+
+/// Clock cycles of this hart
+#[inline]
+pub fn cycle() -> u64 {
+    read_counter!("cycle")
+}
+
+/// Wall-clock time, which machine mode may have to emulate on some platforms
+#[inline]
+pub fn time() -> u64 {
+    read_counter!("time")
+}
+
+/// Instructions retired by this hart
+#[inline]
+pub fn instret() -> u64 {
+    read_counter!("instret")
+}
+
+/// A programmable counter, from 3 to 31
+pub fn hpmcounter(index: usize) -> u64 {
+    match_hpm!(index, read_counter!("hpmcounter"))
+}
+
+/// Machine mode's copy of cycle
+#[inline]
+pub fn mcycle() -> u64 {
+    read_counter!("mcycle")
+}
+
+/// Machine mode's copy of instret
+#[inline]
+pub fn minstret() -> u64 {
+    read_counter!("minstret")
+}
+
+/// Machine mode's copy of a programmable counter, from 3 to 31
+pub fn mhpmcounter(index: usize) -> u64 {
+    match_hpm!(index, read_counter!("mhpmcounter"))
+}
+
+/// Set the cycle counter, e.g. to zero it
+///
+/// ## Safety
+/// Must be in machine mode, and will confuse anything measuring with cycle.
+pub unsafe fn set_mcycle(value: u64) {
+    write_counter!(value, "mcycle")
+}
+
+/// Set the instructions retired counter
+///
+/// ## Safety
+/// see [`set_mcycle`]
+pub unsafe fn set_minstret(value: u64) {
+    write_counter!(value, "minstret")
+}
+
+/// Set a programmable counter, from 3 to 31
+///
+/// ## Safety
+/// see [`set_mcycle`]
+pub unsafe fn set_mhpmcounter(index: usize, value: u64) {
+    match_hpm!(index, write_counter!(value, "mhpmcounter"))
+}
+
+/// The event a programmable counter counts, which is platform specific
+/// apart from 0 meaning no event.
+pub fn mhpmevent(index: usize) -> usize {
+    macro_rules! read_event {
+        ($name:literal, $index:literal) => {{
+            let value: usize;
+            unsafe { riscv_asm!(concat!("csrr {}, ", $name, $index), out(reg) value) };
+            value
+        }};
+    }
+    match_hpm!(index, read_event!("mhpmevent"))
+}
+
+/// Choose the event a programmable counter counts, from 3 to 31.
+/// Hardware without the counter or event ignores the write.
+///
+/// ## Safety
+/// Must be in machine mode.
+pub unsafe fn set_mhpmevent(index: usize, event: usize) {
+    macro_rules! write_event {
+        ($name:literal, $index:literal) => {
+            riscv_asm!(concat!("csrw ", $name, $index, ", {}"), in(reg) event)
+        };
+    }
+    match_hpm!(index, write_event!("mhpmevent"))
+}
+
+/// Let supervisor mode read the counter
+///
+/// ## Safety
+/// Must be in machine mode.
+pub unsafe fn allow_supervisor(counter: Counter) {
+    mcounteren::set(mcounteren::Mcounteren::from_bits(counter.bit()));
+}
+
+/// Let user mode read the counter, which must also be allowed for supervisor mode
+///
+/// ## Safety
+/// Must be in supervisor or machine mode.
+pub unsafe fn allow_user(counter: Counter) {
+    scounteren::set(scounteren::Scounteren::from_bits(counter.bit()));
+}
+
+/// Stop or restart a counter incrementing. The time counter can't be stopped.
+///
+/// ## Safety
+/// Must be in machine mode.
+pub unsafe fn inhibit(counter: Counter, stopped: bool) {
+    let mask = mcountinhibit::Mcountinhibit::from_bits(counter.bit());
+    match stopped {
+        true => mcountinhibit::set(mask),
+        false => mcountinhibit::clear(mask),
+    }
+}
+
+/// Counts the ticks of a counter from its creation until it is dropped,
+/// then passes them to a report function, e.g. to print them.
+pub struct ScopedTimer<F: FnMut(u64)> {
+    counter: Counter,
+    start: u64,
+    report: F,
+}
+
+impl<F: FnMut(u64)> ScopedTimer<F> {
+    pub fn new(counter: Counter, report: F) -> ScopedTimer<F> {
+        ScopedTimer {
+            counter,
+            start: counter.read(),
+            report,
+        }
+    }
+    /// Ticks counted so far
+    pub fn elapsed(&self) -> u64 {
+        self.counter.read().wrapping_sub(self.start)
+    }
+}
+
+impl<F: FnMut(u64)> Drop for ScopedTimer<F> {
+    fn drop(&mut self) {
+        let elapsed = self.elapsed();
+        (self.report)(elapsed);
+    }
+}
+
+/// Runs f, returning its result and how many ticks of the counter it took
+pub fn measure<R>(counter: Counter, f: impl FnOnce() -> R) -> (R, u64) {
+    let start = counter.read();
+    let result = f();
+    (result, counter.read().wrapping_sub(start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits() {
+        assert_eq!(Counter::Cycle.bit(), 1);
+        assert_eq!(Counter::Time.bit(), 2);
+        assert_eq!(Counter::Instret.bit(), 4);
+        assert_eq!(Counter::hpm(HPM_FIRST).bit(), 1 << 3);
+        assert_eq!(Counter::hpm(HPM_LAST).bit(), 1 << 31);
+    }
+
+    #[test]
+    #[should_panic(expected = "no such programmable counter")]
+    fn hpm_below_range() {
+        Counter::hpm(2);
+    }
+
+    #[test]
+    #[should_panic(expected = "no such programmable counter")]
+    fn hpm_above_range() {
+        Counter::hpm(32);
+    }
+}
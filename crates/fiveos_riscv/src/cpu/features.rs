@@ -0,0 +1,276 @@
+//! What this processor can do, from misa and from probing for optional behaviour.
+
+use core::fmt::Debug;
+
+use super::registers::{
+    csr::{marchid, mimpid, mvendorid, sstatus},
+    misa::{Misa, MisaExtension},
+    mstatus::{self, UnitStatus},
+};
+use crate::mmu::{set_translation_table, TableTypes};
+use crate::sbi;
+
+/// Floating point registers available, which decides how they are saved
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FloatingPoint {
+    None,
+    /// 32-bit registers
+    Single,
+    /// 64-bit registers
+    Double,
+    /// 128-bit registers
+    Quad,
+}
+
+/// The address translation modes accepted by satp. The kernel only builds
+/// Sv39 tables, so it checks for Sv39 rather than picking the largest mode.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct PagingModes(u16);
+
+impl PagingModes {
+    const PROBED: [TableTypes; 4] = [
+        TableTypes::Sv32,
+        TableTypes::Sv39,
+        TableTypes::Sv48,
+        TableTypes::Sv57,
+    ];
+    pub fn supports(&self, mode: TableTypes) -> bool {
+        mode == TableTypes::None || self.0 & (1 << mode as u16) != 0
+    }
+}
+
+impl Debug for PagingModes {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut list = f.debug_list();
+        for mode in PagingModes::PROBED {
+            if self.supports(mode) {
+                list.entry(&format_args!("Sv{}", mode_bits(mode)));
+            }
+        }
+        list.finish()
+    }
+}
+
+const fn mode_bits(mode: TableTypes) -> usize {
+    match mode {
+        TableTypes::None => 0,
+        TableTypes::Sv32 => 32,
+        TableTypes::Sv39 => 39,
+        TableTypes::Sv48 => 48,
+        TableTypes::Sv57 => 57,
+    }
+}
+
+/// Features of the hart, so kernel code can branch on them rather than on misa bits.
+#[derive(Clone, Copy, Debug)]
+pub struct CpuFeatures {
+    /// None if misa is not implemented, which the spec allows
+    pub misa: Option<Misa>,
+    pub xlen: usize,
+    pub floating_point: FloatingPoint,
+    pub vector: bool,
+    pub paging: PagingModes,
+    /// mvendorid, the JEDEC ID of the vendor or 0
+    pub vendor_id: usize,
+    /// marchid, the microarchitecture or 0
+    pub architecture_id: usize,
+    /// mimpid, the version of the implementation or 0
+    pub implementation_id: usize,
+}
+
+impl CpuFeatures {
+    /// Reads misa, and probes for the features it may not report by
+    /// checking which values mstatus and satp accept.
+    ///
+    /// Without misa a writable mstatus.FS is taken to mean the D extension,
+    /// as the F extension alone is rare in harts which run an OS.
+    ///
+    /// ## Safety
+    /// Must be run in machine mode with translation off, as it writes satp,
+    /// and not while floating point or vector state is live.
+    pub unsafe fn probe() -> CpuFeatures {
+        let misa = Misa::get();
+        let xlen = match misa {
+            Some(misa) => misa.xlen(),
+            None => usize::BITS as usize,
+        };
+
+        let status = mstatus::read();
+        mstatus::write(
+            status
+                .with_fs(UnitStatus::Initial)
+                .with_vs(UnitStatus::Initial),
+        );
+        let probed = mstatus::read();
+        mstatus::write(status);
+
+        let floating_point = match misa {
+            Some(misa) if misa.has(MisaExtension::Q) => FloatingPoint::Quad,
+            Some(misa) if misa.has(MisaExtension::D) => FloatingPoint::Double,
+            Some(misa) if misa.has(MisaExtension::F) => FloatingPoint::Single,
+            Some(_) => FloatingPoint::None,
+            None if probed.fs() != UnitStatus::Off => FloatingPoint::Double,
+            None => FloatingPoint::None,
+        };
+        let vector = match misa {
+            Some(misa) => misa.has(MisaExtension::V),
+            None => probed.vs() != UnitStatus::Off,
+        };
+
+        // machine mode only, so recorded for the kernel to print later
+        let vendor_id = mvendorid::read().bits();
+        let architecture_id = marchid::read().bits();
+        let implementation_id = mimpid::read().bits();
+
+        // unsupported modes leave satp unchanged
+        let mut paging = PagingModes::default();
+        for mode in PagingModes::PROBED {
+            if set_translation_table(mode, 0) {
+                paging.0 |= 1 << mode as u16;
+            }
+        }
+        set_translation_table(TableTypes::None, 0);
+
+        CpuFeatures {
+            misa,
+            xlen,
+            floating_point,
+            vector,
+            paging,
+            vendor_id,
+            architecture_id,
+            implementation_id,
+        }
+    }
+    /// Probes what supervisor mode can see, for when SBI firmware owns
+    /// machine mode: misa is out of reach, and the ids come from the firmware.
+    ///
+    /// Writing satp here would turn translation on, so paging is assumed to
+    /// be the mode every hart with paging must support, Sv39 on RV64 and Sv32 on RV32.
+    ///
+    /// ## Safety
+    /// Must be run in supervisor mode under SBI firmware, and not while
+    /// floating point or vector state is live.
+    pub unsafe fn probe_supervisor() -> CpuFeatures {
+        let status = sstatus::read();
+        sstatus::write(
+            status
+                .with_fs(UnitStatus::Initial)
+                .with_vs(UnitStatus::Initial),
+        );
+        let probed = sstatus::read();
+        sstatus::write(status);
+
+        let floating_point = match probed.fs() {
+            UnitStatus::Off => FloatingPoint::None,
+            _ => FloatingPoint::Double,
+        };
+        let mode = match usize::BITS {
+            32 => TableTypes::Sv32,
+            _ => TableTypes::Sv39,
+        };
+
+        CpuFeatures {
+            misa: None,
+            xlen: usize::BITS as usize,
+            floating_point,
+            vector: probed.vs() != UnitStatus::Off,
+            paging: PagingModes(1 << mode as u16),
+            vendor_id: sbi::base::mvendorid(),
+            architecture_id: sbi::base::marchid(),
+            implementation_id: sbi::base::mimpid(),
+        }
+    }
+    /// The floating point status a new context starts with: Initial, so the
+    /// trap path saves its registers once it has used them, or Off on harts
+    /// without floating point, where using them is an illegal instruction.
+    pub fn initial_fp_status(&self) -> UnitStatus {
+        match self.floating_point {
+            FloatingPoint::None => UnitStatus::Off,
+            _ => UnitStatus::Initial,
+        }
+    }
+    /// Checks for an extension, falling back to the probed features if misa
+    /// is not implemented.
+    pub fn has(&self, extension: MisaExtension) -> bool {
+        match self.misa {
+            Some(misa) => misa.has(extension),
+            None => match extension {
+                MisaExtension::F => self.floating_point >= FloatingPoint::Single,
+                MisaExtension::D => self.floating_point >= FloatingPoint::Double,
+                MisaExtension::Q => self.floating_point >= FloatingPoint::Quad,
+                MisaExtension::V => self.vector,
+                MisaExtension::S => self.paging != PagingModes::default(),
+                _ => false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::format;
+
+    use super::{CpuFeatures, FloatingPoint, PagingModes};
+    use crate::cpu::registers::{
+        misa::{Misa, MisaExtension},
+        mstatus::UnitStatus,
+    };
+    use crate::mmu::TableTypes;
+
+    fn features(floating_point: FloatingPoint) -> CpuFeatures {
+        CpuFeatures {
+            misa: None,
+            xlen: 64,
+            floating_point,
+            vector: false,
+            paging: PagingModes::default(),
+            vendor_id: 0,
+            architecture_id: 0,
+            implementation_id: 0,
+        }
+    }
+
+    #[test]
+    fn contexts_start_with_floating_point_initial() {
+        assert_eq!(
+            features(FloatingPoint::None).initial_fp_status(),
+            UnitStatus::Off
+        );
+        for floating_point in [
+            FloatingPoint::Single,
+            FloatingPoint::Double,
+            FloatingPoint::Quad,
+        ] {
+            assert_eq!(
+                features(floating_point).initial_fp_status(),
+                UnitStatus::Initial
+            );
+        }
+    }
+
+    #[test]
+    fn paging_modes() {
+        let modes = PagingModes(1 << TableTypes::Sv39 as u16 | 1 << TableTypes::Sv48 as u16);
+        assert!(modes.supports(TableTypes::Sv39) && modes.supports(TableTypes::Sv48));
+        assert!(!modes.supports(TableTypes::Sv32) && !modes.supports(TableTypes::Sv57));
+        assert_eq!(format!("{:?}", modes), "[Sv39, Sv48]");
+        // bare translation is always there
+        assert!(PagingModes::default().supports(TableTypes::None));
+        assert_eq!(format!("{:?}", PagingModes::default()), "[]");
+    }
+
+    #[test]
+    fn has_falls_back_without_misa() {
+        let mut probed = features(FloatingPoint::Double);
+        assert!(probed.has(MisaExtension::F) && probed.has(MisaExtension::D));
+        assert!(!probed.has(MisaExtension::Q) && !probed.has(MisaExtension::V));
+        assert!(!probed.has(MisaExtension::S));
+        probed.paging = PagingModes(1 << TableTypes::Sv39 as u16);
+        assert!(probed.has(MisaExtension::S));
+
+        // misa is believed over the probed features
+        probed.misa = Misa::from_raw(2 << (usize::BITS - 2) | MisaExtension::V.bit() as usize);
+        assert!(probed.has(MisaExtension::V) && !probed.has(MisaExtension::D));
+    }
+}
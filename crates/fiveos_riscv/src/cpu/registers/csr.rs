@@ -0,0 +1,605 @@
+//! Typed access to control and status registers, generated from the
+//! descriptions at the bottom of this file.
+//!
+//! Each CSR gets a module named after it, holding a value type with a
+//! getter and `with_` builder for each of its fields, along with `read`,
+//! `write`, `set` and `clear` functions for the register itself, or just
+//! `read` for the read-only ones. Field
+//! positions are from The RISC-V Instruction Set Manual Vol II,
+//! Privileged Architecture Version 1.12. Registers laid out differently
+//! on RV32 and RV64 are described once for each, chosen by target_pointer_width.
+
+/// Describes a CSR and the fields inside it.
+///
+/// Fields are either a single `bit(offset)`, read as a bool, or
+/// `bits(offset, width)`, read as a usize shifted down to bit 0.
+/// A field of bits can be read as an enum implementing `From<u8>`
+/// and `Into<u8>` instead with `bits(offset, width) as Type`.
+///
+/// Registers marked `read_only` after their name only get `read`.
+macro_rules! csr {
+    (
+        $(#[$meta:meta])*
+        $module:ident: $name:ident = $csr:literal read_only { $($fields:tt)* }
+    ) => {
+        $(#[$meta])*
+        pub mod $module {
+            #[allow(unused_imports)]
+            use super::*;
+
+            csr!(@value $name = $csr { $($fields)* });
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        $module:ident: $name:ident = $csr:literal { $($fields:tt)* }
+    ) => {
+        $(#[$meta])*
+        pub mod $module {
+            #[allow(unused_imports)]
+            use super::*;
+
+            csr!(@value $name = $csr { $($fields)* });
+            csr!(@write $name = $csr);
+        }
+    };
+    (@value $name:ident = $csr:literal {
+        $( $(#[$field_meta:meta])* $field:ident: $kind:ident $args:tt $(as $type:ty)? ),* $(,)?
+    }) => {
+        #[doc = concat!("Value of the ", $csr, " register")]
+        #[derive(Clone, Copy, PartialEq, Eq, Default)]
+        pub struct $name(usize);
+
+        impl $name {
+            #[inline]
+            pub const fn new() -> $name {
+                $name(0)
+            }
+            #[inline]
+            pub const fn from_bits(bits: usize) -> $name {
+                $name(bits)
+            }
+            #[inline]
+            pub const fn bits(self) -> usize {
+                self.0
+            }
+            $( csr_field!($(#[$field_meta])* $field: $kind $args $(as $type)?); )*
+        }
+
+        impl core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.debug_struct(stringify!($name))
+                    .field("bits", &format_args!("0x{:x}", self.0))
+                    $( .field(stringify!($field), &self.$field()) )*
+                    .finish()
+            }
+        }
+
+        #[doc = concat!("Read the ", $csr, " register")]
+        #[inline]
+        pub fn read() -> $name {
+            let bits: usize;
+            unsafe { riscv_asm!(concat!("csrr {}, ", $csr), out(reg) bits) };
+            $name(bits)
+        }
+    };
+    (@write $name:ident = $csr:literal) => {
+        #[doc = concat!("Replace the value of the ", $csr, " register")]
+        ///
+        /// ## Safety
+        /// Control registers change how the processor behaves,
+        /// the caller must ensure that is expected.
+        #[inline]
+        pub unsafe fn write(value: $name) {
+            riscv_asm!(concat!("csrw ", $csr, ", {}"), in(reg) value.0);
+        }
+
+        #[doc = concat!("Change the ", $csr, " register by reading it, passing the value through f and writing the result")]
+        ///
+        /// ## Safety
+        /// see [`write`], and the register must not be changed
+        /// elsewhere in between, e.g. by an interrupt handler.
+        #[inline]
+        pub unsafe fn modify(f: impl FnOnce($name) -> $name) {
+            write(f(read()));
+        }
+
+        #[doc = concat!("Set the bits of the ", $csr, " register which are set in mask")]
+        ///
+        /// ## Safety
+        /// see [`write`]
+        #[inline]
+        pub unsafe fn set(mask: $name) {
+            riscv_asm!(concat!("csrs ", $csr, ", {}"), in(reg) mask.0);
+        }
+
+        #[doc = concat!("Clear the bits of the ", $csr, " register which are set in mask")]
+        ///
+        /// ## Safety
+        /// see [`write`]
+        #[inline]
+        pub unsafe fn clear(mask: $name) {
+            riscv_asm!(concat!("csrc ", $csr, ", {}"), in(reg) mask.0);
+        }
+    };
+}
+
+/// Getter and builder for a single field of a CSR value type
+macro_rules! csr_field {
+    ($(#[$meta:meta])* $field:ident: bit ($offset:expr)) => {
+        ::paste::paste! {
+            $(#[$meta])*
+            #[inline]
+            pub const fn $field(&self) -> bool {
+                self.0 & (1 << ($offset)) != 0
+            }
+            $(#[$meta])*
+            #[inline]
+            pub const fn [< with_ $field >](self, flag: bool) -> Self {
+                Self((self.0 & !(1 << ($offset))) | (flag as usize) << ($offset))
+            }
+        }
+    };
+    ($(#[$meta:meta])* $field:ident: bits ($offset:expr, $width:expr) as $type:ty) => {
+        ::paste::paste! {
+            $(#[$meta])*
+            #[inline]
+            pub fn $field(&self) -> $type {
+                <$type>::from(((self.0 >> ($offset)) & ((1 << ($width)) - 1)) as u8)
+            }
+            $(#[$meta])*
+            #[inline]
+            pub fn [< with_ $field >](self, value: $type) -> Self {
+                let mask = ((1 << ($width)) - 1) << ($offset);
+                let value = <u8>::from(value) as usize;
+                Self((self.0 & !mask) | ((value << ($offset)) & mask))
+            }
+        }
+    };
+    ($(#[$meta:meta])* $field:ident: bits ($offset:expr, $width:expr)) => {
+        ::paste::paste! {
+            $(#[$meta])*
+            #[inline]
+            pub const fn $field(&self) -> usize {
+                (self.0 >> ($offset)) & ((1 << ($width)) - 1)
+            }
+            $(#[$meta])*
+            #[inline]
+            pub const fn [< with_ $field >](self, value: usize) -> Self {
+                let mask = ((1 << ($width)) - 1) << ($offset);
+                Self((self.0 & !mask) | ((value << ($offset)) & mask))
+            }
+        }
+    };
+}
+
+#[cfg(target_pointer_width = "64")]
+use super::mstatus::Xlen;
+use super::mstatus::{PrivilegeMode, UnitStatus};
+use super::mtvec::TrapMode;
+
+/// width of the registers in bits
+const XLEN: usize = usize::BITS as usize;
+
+csr! {
+    /// Machine interrupt enable
+    mie: Mie = "mie" {
+        /// supervisor software interrupt
+        ssie: bit(1),
+        /// machine software interrupt
+        msie: bit(3),
+        /// supervisor timer interrupt
+        stie: bit(5),
+        /// machine timer interrupt
+        mtie: bit(7),
+        /// supervisor external interrupt
+        seie: bit(9),
+        /// machine external interrupt
+        meie: bit(11),
+    }
+}
+
+csr! {
+    /// Machine interrupt pending
+    mip: Mip = "mip" {
+        /// supervisor software interrupt
+        ssip: bit(1),
+        /// machine software interrupt, read-only
+        msip: bit(3),
+        /// supervisor timer interrupt
+        stip: bit(5),
+        /// machine timer interrupt, read-only
+        mtip: bit(7),
+        /// supervisor external interrupt
+        seip: bit(9),
+        /// machine external interrupt, read-only
+        meip: bit(11),
+    }
+}
+
+csr! {
+    /// Supervisor interrupt enable, the supervisor view of mie
+    sie: Sie = "sie" {
+        /// supervisor software interrupt
+        ssie: bit(1),
+        /// supervisor timer interrupt
+        stie: bit(5),
+        /// supervisor external interrupt
+        seie: bit(9),
+    }
+}
+
+csr! {
+    /// Supervisor interrupt pending, the supervisor view of mip
+    sip: Sip = "sip" {
+        /// supervisor software interrupt
+        ssip: bit(1),
+        /// supervisor timer interrupt, read-only
+        stip: bit(5),
+        /// supervisor external interrupt, read-only
+        seip: bit(9),
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+csr! {
+    /// Machine status, with the RV64 layout
+    mstatus: MStatus = "mstatus" {
+        /// supervisor interrupts enabled
+        sie: bit(1),
+        /// machine interrupts enabled
+        mie: bit(3),
+        /// supervisor interrupts enabled previously
+        spie: bit(5),
+        /// user mode data accesses are big endian
+        ube: bit(6),
+        /// machine interrupts enabled previously
+        mpie: bit(7),
+        /// supervisor previous privilege, set when the trap came from supervisor mode
+        spp: bit(8),
+        /// vector unit status
+        vs: bits(9, 2) as UnitStatus,
+        /// machine previous privilege
+        mpp: bits(11, 2) as PrivilegeMode,
+        /// floating point unit status
+        fs: bits(13, 2) as UnitStatus,
+        /// other extension status, read-only
+        xs: bits(15, 2) as UnitStatus,
+        /// modify privilege, loads and stores use the privilege in mpp
+        mprv: bit(17),
+        /// permit supervisor access to user memory
+        sum: bit(18),
+        /// make executable pages readable
+        mxr: bit(19),
+        /// trap virtual memory, trap supervisor satp access and sfence.vma
+        tvm: bit(20),
+        /// timeout wait, trap wfi below machine mode
+        tw: bit(21),
+        /// trap sret
+        tsr: bit(22),
+        /// width of user mode registers
+        uxl: bits(32, 2) as Xlen,
+        /// width of supervisor mode registers
+        sxl: bits(34, 2) as Xlen,
+        /// supervisor mode data accesses are big endian
+        sbe: bit(36),
+        /// machine mode data accesses are big endian
+        mbe: bit(37),
+        /// some unit is dirty, read-only
+        sd: bit(XLEN - 1),
+    }
+}
+
+#[cfg(target_pointer_width = "32")]
+csr! {
+    /// Machine status, with the RV32 layout, which leaves uxl, sxl, sbe and
+    /// mbe to mstatush
+    mstatus: MStatus = "mstatus" {
+        /// supervisor interrupts enabled
+        sie: bit(1),
+        /// machine interrupts enabled
+        mie: bit(3),
+        /// supervisor interrupts enabled previously
+        spie: bit(5),
+        /// user mode data accesses are big endian
+        ube: bit(6),
+        /// machine interrupts enabled previously
+        mpie: bit(7),
+        /// supervisor previous privilege, set when the trap came from supervisor mode
+        spp: bit(8),
+        /// vector unit status
+        vs: bits(9, 2) as UnitStatus,
+        /// machine previous privilege
+        mpp: bits(11, 2) as PrivilegeMode,
+        /// floating point unit status
+        fs: bits(13, 2) as UnitStatus,
+        /// other extension status, read-only
+        xs: bits(15, 2) as UnitStatus,
+        /// modify privilege, loads and stores use the privilege in mpp
+        mprv: bit(17),
+        /// permit supervisor access to user memory
+        sum: bit(18),
+        /// make executable pages readable
+        mxr: bit(19),
+        /// trap virtual memory, trap supervisor satp access and sfence.vma
+        tvm: bit(20),
+        /// timeout wait, trap wfi below machine mode
+        tw: bit(21),
+        /// trap sret
+        tsr: bit(22),
+        /// some unit is dirty, read-only
+        sd: bit(XLEN - 1),
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+csr! {
+    /// Supervisor status, the supervisor view of mstatus, with the RV64 layout
+    sstatus: SStatus = "sstatus" {
+        /// supervisor interrupts enabled
+        sie: bit(1),
+        /// supervisor interrupts enabled previously
+        spie: bit(5),
+        /// user mode data accesses are big endian
+        ube: bit(6),
+        /// supervisor previous privilege, set when the trap came from supervisor mode
+        spp: bit(8),
+        /// vector unit status
+        vs: bits(9, 2) as UnitStatus,
+        /// floating point unit status
+        fs: bits(13, 2) as UnitStatus,
+        /// other extension status, read-only
+        xs: bits(15, 2) as UnitStatus,
+        /// permit supervisor access to user memory
+        sum: bit(18),
+        /// make executable pages readable
+        mxr: bit(19),
+        /// width of user mode registers
+        uxl: bits(32, 2) as Xlen,
+        /// some unit is dirty, read-only
+        sd: bit(XLEN - 1),
+    }
+}
+
+#[cfg(target_pointer_width = "32")]
+csr! {
+    /// Supervisor status, the supervisor view of mstatus, with the RV32 layout
+    sstatus: SStatus = "sstatus" {
+        /// supervisor interrupts enabled
+        sie: bit(1),
+        /// supervisor interrupts enabled previously
+        spie: bit(5),
+        /// user mode data accesses are big endian
+        ube: bit(6),
+        /// supervisor previous privilege, set when the trap came from supervisor mode
+        spp: bit(8),
+        /// vector unit status
+        vs: bits(9, 2) as UnitStatus,
+        /// floating point unit status
+        fs: bits(13, 2) as UnitStatus,
+        /// other extension status, read-only
+        xs: bits(15, 2) as UnitStatus,
+        /// permit supervisor access to user memory
+        sum: bit(18),
+        /// make executable pages readable
+        mxr: bit(19),
+        /// some unit is dirty, read-only
+        sd: bit(XLEN - 1),
+    }
+}
+
+csr! {
+    /// Machine trap vector base address
+    mtvec: Mtvec = "mtvec" {
+        /// whether interrupts jump to their own entry
+        mode: bits(0, 2) as TrapMode,
+        /// address of the trap vector, shifted down by 2
+        base: bits(2, XLEN - 2),
+    }
+}
+
+csr! {
+    /// Supervisor trap vector base address
+    stvec: Stvec = "stvec" {
+        /// whether interrupts jump to their own entry
+        mode: bits(0, 2) as TrapMode,
+        /// address of the trap vector, shifted down by 2
+        base: bits(2, XLEN - 2),
+    }
+}
+
+csr! {
+    /// Supervisor exception program counter
+    sepc: Sepc = "sepc" {}
+}
+
+csr! {
+    /// Machine exception program counter
+    mepc: Mepc = "mepc" {}
+}
+
+csr! {
+    /// Machine trap cause
+    mcause: Mcause = "mcause" {
+        /// the trap was an interrupt rather than an exception
+        interrupt: bit(XLEN - 1),
+        /// exception or interrupt code
+        code: bits(0, XLEN - 1),
+    }
+}
+
+impl mcause::Mcause {
+    /// Decodes the cause
+    #[inline]
+    pub const fn trap(&self) -> super::mcause::Trap {
+        super::mcause::Trap::from_cause(self.bits())
+    }
+}
+
+csr! {
+    /// Supervisor trap cause
+    scause: Scause = "scause" {
+        /// the trap was an interrupt rather than an exception
+        interrupt: bit(XLEN - 1),
+        /// exception or interrupt code
+        code: bits(0, XLEN - 1),
+    }
+}
+
+impl scause::Scause {
+    /// Decodes the cause, which uses the same codes as mcause
+    #[inline]
+    pub const fn trap(&self) -> super::mcause::Trap {
+        super::mcause::Trap::from_cause(self.bits())
+    }
+}
+
+csr! {
+    /// Supervisor trap value, e.g. the faulting address
+    stval: Stval = "stval" {}
+}
+
+csr! {
+    /// Supervisor scratch register, for use by the trap handler
+    sscratch: Sscratch = "sscratch" {}
+}
+
+csr! {
+    /// Machine scratch register, for use by the trap handler
+    mscratch: Mscratch = "mscratch" {}
+}
+
+csr! {
+    /// Machine exception delegation, exceptions with a set bit
+    /// are handled in supervisor mode when they occur below machine mode
+    medeleg: Medeleg = "medeleg" {
+        instruction_misaligned: bit(0),
+        instruction_access_fault: bit(1),
+        illegal_instruction: bit(2),
+        breakpoint: bit(3),
+        load_misaligned: bit(4),
+        load_access_fault: bit(5),
+        store_misaligned: bit(6),
+        store_access_fault: bit(7),
+        user_ecall: bit(8),
+        supervisor_ecall: bit(9),
+        instruction_page_fault: bit(12),
+        load_page_fault: bit(13),
+        store_page_fault: bit(15),
+    }
+}
+
+csr! {
+    /// Machine interrupt delegation, interrupts with a set bit
+    /// are handled in supervisor mode when they occur below machine mode
+    mideleg: Mideleg = "mideleg" {
+        /// supervisor software interrupt
+        ssi: bit(1),
+        /// supervisor timer interrupt
+        sti: bit(5),
+        /// supervisor external interrupt
+        sei: bit(9),
+    }
+}
+
+csr! {
+    /// Machine counter enable, which counters supervisor mode may read
+    mcounteren: Mcounteren = "mcounteren" {
+        /// cycle
+        cy: bit(0),
+        /// time
+        tm: bit(1),
+        /// instret
+        ir: bit(2),
+        /// hpmcounter3 to hpmcounter31, from bit 0
+        hpm: bits(3, 29),
+    }
+}
+
+csr! {
+    /// Supervisor counter enable, which counters user mode may read
+    scounteren: Scounteren = "scounteren" {
+        /// cycle
+        cy: bit(0),
+        /// time
+        tm: bit(1),
+        /// instret
+        ir: bit(2),
+        /// hpmcounter3 to hpmcounter31, from bit 0
+        hpm: bits(3, 29),
+    }
+}
+
+csr! {
+    /// Machine counter inhibit, counters with a set bit stop incrementing
+    mcountinhibit: Mcountinhibit = "mcountinhibit" {
+        /// cycle
+        cy: bit(0),
+        /// instret
+        ir: bit(2),
+        /// hpmcounter3 to hpmcounter31, from bit 0
+        hpm: bits(3, 29),
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+csr! {
+    /// Supervisor address translation and protection, with the RV64 layout
+    satp: Satp = "satp" {
+        /// physical page number of the root page table
+        ppn: bits(0, 44),
+        /// address space id
+        asid: bits(44, 16),
+        /// translation mode, see [`crate::mmu::TableTypes`]
+        mode: bits(60, 4),
+    }
+}
+
+#[cfg(target_pointer_width = "32")]
+csr! {
+    /// Supervisor address translation and protection, with the RV32 layout
+    satp: Satp = "satp" {
+        /// physical page number of the root page table
+        ppn: bits(0, 22),
+        /// address space id
+        asid: bits(22, 9),
+        /// translation mode, see [`crate::mmu::TableTypes`]
+        mode: bits(31, 1),
+    }
+}
+
+csr! {
+    /// Machine ISA, the base width and extensions, see [`super::misa::Misa`]
+    /// for the decoded value. Writable on some harts, but only ever read here.
+    misa: RawMisa = "misa" read_only {
+        /// a bit per extension, A in bit 0
+        extensions: bits(0, 26),
+        /// base width, 1 for 32, 2 for 64 and 3 for 128 bits
+        mxl: bits(XLEN - 2, 2),
+    }
+}
+
+csr! {
+    /// Hart id, unique among the harts of the system
+    mhartid: Mhartid = "mhartid" read_only {}
+}
+
+csr! {
+    /// Vendor id, the JEDEC manufacturer id, 0 when not implemented
+    mvendorid: Mvendorid = "mvendorid" read_only {
+        /// id within the bank, without the parity bit
+        offset: bits(0, 7),
+        /// number of continuation codes before the id
+        bank: bits(7, 25),
+    }
+}
+
+csr! {
+    /// Architecture id, the microarchitecture, 0 when not implemented
+    marchid: Marchid = "marchid" read_only {}
+}
+
+csr! {
+    /// Implementation id, the version of the implementation, 0 when not implemented
+    mimpid: Mimpid = "mimpid" read_only {}
+}
@@ -1,140 +1,336 @@
-use core::{arch::asm, mem};
-
-use num_enum::{FromPrimitive, IntoPrimitive};
-
-pub enum TrapKind {
-    Interrupt,
-    Exception,
-}
+use core::mem;
 
+/// A raw mcause or scause value
 #[derive(Debug)]
 #[repr(transparent)]
 pub struct TrapCause(pub usize);
 
 impl TrapCause {
-    pub fn read() -> TrapCause {
-        TrapCause(get())
+    pub fn is_interrupt(&self) -> bool {
+        self.0 & KINDMASK != 0
     }
-    pub fn is_async(&self) -> bool {
-        (self.0 >> 63) & 1 == 1
+    /// The exception or interrupt code, without the interrupt bit
+    pub fn code(&self) -> usize {
+        self.0 & !KINDMASK
     }
-    pub fn number(&self) -> usize {
-        self.0 & 0xfff
+    pub fn trap(&self) -> Trap {
+        Trap::from_cause(self.0)
     }
-    pub fn is_software(&self) -> bool {
-        let n = self.number();
-        !self.is_async() && n <= 3
+}
+
+/// Decoded cause of a trap, as found in mcause or scause.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trap {
+    Interrupt(Interrupt),
+    Exception(Exception),
+}
+
+impl Trap {
+    /// Decodes a raw mcause or scause value
+    ///
+    /// ```
+    /// # use fiveos_riscv::cpu::registers::mcause::{Exception, Interrupt, Trap};
+    /// let interrupt = 1 << (usize::BITS - 1);
+    /// assert_eq!(Trap::from_cause(interrupt | 7), Trap::Interrupt(Interrupt::MachineTimer));
+    /// assert_eq!(Trap::from_cause(interrupt | 13), Trap::Interrupt(Interrupt::CounterOverflow));
+    /// assert_eq!(Trap::from_cause(interrupt | 17), Trap::Interrupt(Interrupt::Platform(17)));
+    /// assert_eq!(Trap::from_cause(interrupt | 4), Trap::Interrupt(Interrupt::Reserved(4)));
+    /// assert_eq!(Trap::from_cause(7), Trap::Exception(Exception::StoreAMOAccessFault));
+    /// assert_eq!(Trap::from_cause(18), Trap::Exception(Exception::SoftwareCheck));
+    /// assert_eq!(Trap::from_cause(19), Trap::Exception(Exception::HardwareError));
+    /// assert_eq!(Trap::from_cause(14), Trap::Exception(Exception::Reserved(14)));
+    /// assert_eq!(Trap::from_cause(50), Trap::Exception(Exception::Custom(50)));
+    /// for code in 0..64 {
+    ///     assert_eq!(Trap::from_cause(code).cause(), code);
+    ///     assert_eq!(Trap::from_cause(interrupt | code).cause(), interrupt | code);
+    /// }
+    /// ```
+    pub const fn from_cause(cause: usize) -> Trap {
+        let code = cause & !KINDMASK;
+        if cause & KINDMASK != 0 {
+            Trap::Interrupt(Interrupt::from_code(code))
+        } else {
+            Trap::Exception(Exception::from_code(code))
+        }
     }
-    pub fn is_timer(&self) -> bool {
-        let n = self.number();
-        !self.is_async() && n > 3 && n <= 7
+    /// Encodes the trap as a raw mcause or scause value
+    pub const fn cause(self) -> usize {
+        match self {
+            Trap::Interrupt(interrupt) => KINDMASK | interrupt.code(),
+            Trap::Exception(exception) => exception.code(),
+        }
     }
-    pub fn is_external(&self) -> bool {
-        let n = self.number();
-        !self.is_async() && n > 7 && n <= 11
+    pub const fn is_interrupt(self) -> bool {
+        matches!(self, Trap::Interrupt(_))
     }
-    pub fn is_user(&self) -> bool {
-        let n = self.number();
-        n == 0 || n == 4 || n == 8
+}
+
+/// Interrupt codes, with the interrupt bit of the cause set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    SupervisorSoftware,
+    VirtualSupervisorSoftware,
+    MachineSoftware,
+    SupervisorTimer,
+    VirtualSupervisorTimer,
+    MachineTimer,
+    SupervisorExternal,
+    VirtualSupervisorExternal,
+    MachineExternal,
+    SupervisorGuestExternal,
+    /// local counter overflow, from the Sscofpmf extension
+    CounterOverflow,
+    /// codes 16 and above are designated for platform use
+    Platform(usize),
+    /// codes below 16 not defined by the spec, including the
+    /// user mode interrupts 0, 4 and 8 of earlier versions
+    Reserved(usize),
+}
+
+impl Interrupt {
+    pub const fn from_code(code: usize) -> Interrupt {
+        use Interrupt::*;
+        match code {
+            1 => SupervisorSoftware,
+            2 => VirtualSupervisorSoftware,
+            3 => MachineSoftware,
+            5 => SupervisorTimer,
+            6 => VirtualSupervisorTimer,
+            7 => MachineTimer,
+            9 => SupervisorExternal,
+            10 => VirtualSupervisorExternal,
+            11 => MachineExternal,
+            12 => SupervisorGuestExternal,
+            13 => CounterOverflow,
+            16.. => Platform(code),
+            _ => Reserved(code),
+        }
     }
-    pub fn is_supervisor(&self) -> bool {
-        let n = self.number();
-        n == 1 || n == 5 || n == 9
+    pub const fn code(self) -> usize {
+        use Interrupt::*;
+        match self {
+            SupervisorSoftware => 1,
+            VirtualSupervisorSoftware => 2,
+            MachineSoftware => 3,
+            SupervisorTimer => 5,
+            VirtualSupervisorTimer => 6,
+            MachineTimer => 7,
+            SupervisorExternal => 9,
+            VirtualSupervisorExternal => 10,
+            MachineExternal => 11,
+            SupervisorGuestExternal => 12,
+            CounterOverflow => 13,
+            Platform(code) | Reserved(code) => code,
+        }
     }
-    pub fn is_machine(&self) -> bool {
-        let n = self.number();
-        n == 3 || n == 7 || n == 11
+    pub const fn is_software(self) -> bool {
+        matches!(self.code(), 1..=3)
     }
-    pub fn known_cause(&self) -> KnownCause {
-        match self.is_async() {
-            true => KnownCause::Async(self.number().into()),
-            false => KnownCause::Async(self.number().into()),
-        }
+    pub const fn is_timer(self) -> bool {
+        matches!(self.code(), 5..=7)
+    }
+    pub const fn is_external(self) -> bool {
+        matches!(self.code(), 9..=12)
     }
 }
 
-/// Parsed values of mcause.
-/// Unexpected values are filtered out into Reserved values
-pub enum KnownCause {
-    Sync(SyncCause),
-    Async(AsyncCause),
-}
-
-/// Defined sync causes.
-/// Causes 2, 6, 10, 12, 13, 14, 15 are reserved in this version of the spec.
-/// Causes >=16 are implemention defined.
-#[derive(FromPrimitive, IntoPrimitive)]
-#[repr(usize)]
-pub enum SyncCause {
-    UserSoftwareInterrupt = 0,
-    SupervisorSoftwareInterrupt = 1,
-    Reserved = 2,
-    MachineSoftwareInterrupt = 3,
-    UserTimerInterrupt = 4,
-    SupervisorTimerInterrupt = 5,
-    MachineTimerInterrupt = 7,
-    UserExternalInterrupt = 8,
-    SupervisorExternalInterrupt = 9,
-    MachineExternalInterrupt = 11,
-    #[num_enum(default)]
-    ImplementationDefinedLocalInterrupts = 16,
+/// Exception codes, with the interrupt bit of the cause clear.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exception {
+    InstructionAddressMisaligned,
+    InstructionAccessFault,
+    IllegalInstruction,
+    Breakpoint,
+    LoadAddressMisaligned,
+    LoadAccessFault,
+    StoreAMOAddressMisaligned,
+    StoreAMOAccessFault,
+    EnvironmentCallUmode,
+    EnvironmentCallSmode,
+    EnvironmentCallVSmode,
+    EnvironmentCallMmode,
+    InstructionPageFault,
+    LoadPageFault,
+    StoreAMOPageFault,
+    /// from the Ssdbltrp extension
+    DoubleTrap,
+    /// from the Zicfiss and Zicfilp extensions
+    SoftwareCheck,
+    HardwareError,
+    InstructionGuestPageFault,
+    LoadGuestPageFault,
+    VirtualInstruction,
+    StoreAMOGuestPageFault,
+    /// codes 24-31 and 48-63 are designated for custom use
+    Custom(usize),
+    Reserved(usize),
 }
 
-/// Defined async causes.
-/// Causes 10, 14, >=16 are reserved in this version of the spec.
-#[derive(FromPrimitive, IntoPrimitive)]
-#[repr(usize)]
-pub enum AsyncCause {
-    InstructionAddressMisaligned = 0,
-    InstructionAccessFault = 1,
-    IllegalInstruction = 2,
-    Breakpoint = 3,
-    LoadAddressMisaligned = 4,
-    LoadAccessFault = 5,
-    StoreAMOAddressMisaligned = 6,
-    StoreAMOAccessFault = 7,
-    EnvironmentCallUmode = 8,
-    EnvironmentCallSmode = 9,
-    EnvironmentCallMmode = 11,
-    InstructionPageFault = 12,
-    LoadPageFault = 13,
-    StoreAMOPageFault = 15,
-    #[num_enum(default)]
-    Reserved = 254,
+impl Exception {
+    pub const fn from_code(code: usize) -> Exception {
+        use Exception::*;
+        match code {
+            0 => InstructionAddressMisaligned,
+            1 => InstructionAccessFault,
+            2 => IllegalInstruction,
+            3 => Breakpoint,
+            4 => LoadAddressMisaligned,
+            5 => LoadAccessFault,
+            6 => StoreAMOAddressMisaligned,
+            7 => StoreAMOAccessFault,
+            8 => EnvironmentCallUmode,
+            9 => EnvironmentCallSmode,
+            10 => EnvironmentCallVSmode,
+            11 => EnvironmentCallMmode,
+            12 => InstructionPageFault,
+            13 => LoadPageFault,
+            15 => StoreAMOPageFault,
+            16 => DoubleTrap,
+            18 => SoftwareCheck,
+            19 => HardwareError,
+            20 => InstructionGuestPageFault,
+            21 => LoadGuestPageFault,
+            22 => VirtualInstruction,
+            23 => StoreAMOGuestPageFault,
+            24..=31 | 48..=63 => Custom(code),
+            _ => Reserved(code),
+        }
+    }
+    pub const fn code(self) -> usize {
+        use Exception::*;
+        match self {
+            InstructionAddressMisaligned => 0,
+            InstructionAccessFault => 1,
+            IllegalInstruction => 2,
+            Breakpoint => 3,
+            LoadAddressMisaligned => 4,
+            LoadAccessFault => 5,
+            StoreAMOAddressMisaligned => 6,
+            StoreAMOAccessFault => 7,
+            EnvironmentCallUmode => 8,
+            EnvironmentCallSmode => 9,
+            EnvironmentCallVSmode => 10,
+            EnvironmentCallMmode => 11,
+            InstructionPageFault => 12,
+            LoadPageFault => 13,
+            StoreAMOPageFault => 15,
+            DoubleTrap => 16,
+            SoftwareCheck => 18,
+            HardwareError => 19,
+            InstructionGuestPageFault => 20,
+            LoadGuestPageFault => 21,
+            VirtualInstruction => 22,
+            StoreAMOGuestPageFault => 23,
+            Custom(code) | Reserved(code) => code,
+        }
+    }
+    /// Page faults, which may be resolved by changing the page table
+    pub const fn is_page_fault(self) -> bool {
+        matches!(self.code(), 12 | 13 | 15)
+    }
 }
 
 const XLEN: usize = mem::size_of::<usize>() * 8;
 const KINDMASK: usize = 0b1 << (XLEN - 1);
 
-/// Gets the kind of trap triggered, an interrupt or an exception.
-#[inline]
-pub fn kind() -> TrapKind {
-    let test: usize;
-    unsafe { asm!("csrr {tmp}, mcause", tmp = out(reg) test) };
-    match test & KINDMASK == 0 {
-        true => TrapKind::Exception,
-        false => TrapKind::Interrupt,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERRUPTS: [(usize, Interrupt); 11] = [
+        (1, Interrupt::SupervisorSoftware),
+        (2, Interrupt::VirtualSupervisorSoftware),
+        (3, Interrupt::MachineSoftware),
+        (5, Interrupt::SupervisorTimer),
+        (6, Interrupt::VirtualSupervisorTimer),
+        (7, Interrupt::MachineTimer),
+        (9, Interrupt::SupervisorExternal),
+        (10, Interrupt::VirtualSupervisorExternal),
+        (11, Interrupt::MachineExternal),
+        (12, Interrupt::SupervisorGuestExternal),
+        (13, Interrupt::CounterOverflow),
+    ];
+
+    const EXCEPTIONS: [(usize, Exception); 22] = [
+        (0, Exception::InstructionAddressMisaligned),
+        (1, Exception::InstructionAccessFault),
+        (2, Exception::IllegalInstruction),
+        (3, Exception::Breakpoint),
+        (4, Exception::LoadAddressMisaligned),
+        (5, Exception::LoadAccessFault),
+        (6, Exception::StoreAMOAddressMisaligned),
+        (7, Exception::StoreAMOAccessFault),
+        (8, Exception::EnvironmentCallUmode),
+        (9, Exception::EnvironmentCallSmode),
+        (10, Exception::EnvironmentCallVSmode),
+        (11, Exception::EnvironmentCallMmode),
+        (12, Exception::InstructionPageFault),
+        (13, Exception::LoadPageFault),
+        (15, Exception::StoreAMOPageFault),
+        (16, Exception::DoubleTrap),
+        (18, Exception::SoftwareCheck),
+        (19, Exception::HardwareError),
+        (20, Exception::InstructionGuestPageFault),
+        (21, Exception::LoadGuestPageFault),
+        (22, Exception::VirtualInstruction),
+        (23, Exception::StoreAMOGuestPageFault),
+    ];
+
+    #[test]
+    fn interrupt_codes() {
+        for (code, interrupt) in INTERRUPTS {
+            assert_eq!(Interrupt::from_code(code), interrupt);
+            assert_eq!(interrupt.code(), code);
+        }
     }
-}
 
-/// Gets the code from mcause
-#[inline]
-pub fn code() -> usize {
-    let out: usize;
-    unsafe { asm!("csrr {tmp}, mcause", tmp = out(reg) out) };
-    out & !KINDMASK
-}
+    #[test]
+    fn invalid_interrupt_codes() {
+        for code in [0, 4, 8, 14, 15] {
+            assert_eq!(Interrupt::from_code(code), Interrupt::Reserved(code));
+        }
+        for code in [16, 17, 31, 63, KINDMASK - 1] {
+            assert_eq!(Interrupt::from_code(code), Interrupt::Platform(code));
+        }
+        for code in 0..64 {
+            assert_eq!(Interrupt::from_code(code).code(), code);
+        }
+    }
 
-/// Gets the raw mcause value
-#[inline]
-pub fn get() -> usize {
-    let out: usize;
-    unsafe { asm!("csrr {tmp}, mcause", tmp = out(reg) out) };
-    out
-}
+    #[test]
+    fn exception_codes() {
+        for (code, exception) in EXCEPTIONS {
+            assert_eq!(Exception::from_code(code), exception);
+            assert_eq!(exception.code(), code);
+        }
+    }
 
-/// attempt to set mcause
-#[inline]
-pub unsafe fn set(value: usize) {
-    unsafe { asm!("csrw mcause, {tmp}", tmp = in(reg) value) };
+    #[test]
+    fn invalid_exception_codes() {
+        for code in [14, 17, 32, 47, 64, KINDMASK - 1] {
+            assert_eq!(Exception::from_code(code), Exception::Reserved(code));
+        }
+        for code in (24..32).chain(48..64) {
+            assert_eq!(Exception::from_code(code), Exception::Custom(code));
+        }
+        for code in 0..64 {
+            assert_eq!(Exception::from_code(code).code(), code);
+        }
+    }
+
+    #[test]
+    fn causes() {
+        for (code, interrupt) in INTERRUPTS {
+            let cause = KINDMASK | code;
+            assert_eq!(Trap::from_cause(cause), Trap::Interrupt(interrupt));
+            assert_eq!(Trap::Interrupt(interrupt).cause(), cause);
+            assert!(TrapCause(cause).is_interrupt());
+            assert_eq!(TrapCause(cause).code(), code);
+        }
+        for (code, exception) in EXCEPTIONS {
+            assert_eq!(Trap::from_cause(code), Trap::Exception(exception));
+            assert_eq!(Trap::Exception(exception).cause(), code);
+            assert!(!TrapCause(code).is_interrupt());
+        }
+    }
 }
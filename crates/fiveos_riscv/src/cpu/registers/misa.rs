@@ -1,12 +1,11 @@
-use core::arch::asm;
-
-use num_enum::{FromPrimitive, IntoPrimitive};
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
 
 const EXTENSION_MASK: usize = (1 << 26) - 1;
 const XLEN: usize = core::mem::size_of::<usize>() * 8;
 const BASE: usize = 0b11 << (XLEN - 2);
 
-#[derive(Debug, Clone, Copy, FromPrimitive, IntoPrimitive)]
+/// Base integer width, as found in the MXL field of misa
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 pub enum Architecture {
     #[num_enum(default)]
@@ -16,6 +15,122 @@ pub enum Architecture {
     OneTwentyEight = 3,
 }
 
+impl Architecture {
+    /// Register width in bits, 0 if unknown
+    pub const fn xlen(self) -> usize {
+        match self {
+            Architecture::Unknown => 0,
+            Architecture::ThirtyTwo => 32,
+            Architecture::SixtyFour => 64,
+            Architecture::OneTwentyEight => 128,
+        }
+    }
+}
+
+/// The extensions reported in misa, one bit per letter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum MisaExtension {
+    /// Atomics
+    A = 0,
+    /// Bit-manipulation
+    B = 1,
+    /// Compressed instructions
+    C = 2,
+    /// Double-precision floating point
+    D = 3,
+    /// Embedded base ISA, with 16 registers
+    E = 4,
+    /// Single-precision floating point
+    F = 5,
+    /// Reserved, the G shorthand is not a misa bit
+    G = 6,
+    /// Hypervisor
+    H = 7,
+    /// Integer base ISA
+    I = 8,
+    /// Reserved
+    J = 9,
+    /// Reserved
+    K = 10,
+    /// Reserved
+    L = 11,
+    /// Integer multiply & divide
+    M = 12,
+    /// User-level interrupts, from earlier versions of the spec
+    N = 13,
+    /// Reserved
+    O = 14,
+    /// Packed-SIMD
+    P = 15,
+    /// Quad-precision floating point
+    Q = 16,
+    /// Reserved
+    R = 17,
+    /// Supervisor mode
+    S = 18,
+    /// Reserved
+    T = 19,
+    /// User mode
+    U = 20,
+    /// Vector
+    V = 21,
+    /// Reserved
+    W = 22,
+    /// Non-standard extensions present
+    X = 23,
+    /// Reserved
+    Y = 24,
+    /// Reserved
+    Z = 25,
+}
+
+impl MisaExtension {
+    pub const ALL: [MisaExtension; 26] = {
+        use MisaExtension::*;
+        [
+            A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        ]
+    };
+    pub const fn bit(self) -> u32 {
+        1 << self as u8
+    }
+    pub const fn letter(self) -> char {
+        (b'A' + self as u8) as char
+    }
+    pub const fn description(self) -> &'static str {
+        use MisaExtension::*;
+        match self {
+            A => "Atomics (A)",
+            B => "Bit-manipulation (B)",
+            C => "Compressed (C)",
+            D => "Double-precision floating point (D)",
+            E => "Embedded base ISA (E)",
+            F => "Single-precision floating point (F)",
+            H => "Hypervisor (H)",
+            I => "Integer base ISA (I)",
+            M => "Integer multiply & divide (M)",
+            N => "User-level interrupts (N)",
+            P => "Packed-SIMD (P)",
+            Q => "Quad precision floating point (Q)",
+            S => "Supervisor Mode (S)",
+            U => "User Mode (U)",
+            V => "Vector (V)",
+            X => "Nonstandard extensions present (X)",
+            G => "Reserved (G)",
+            J => "Reserved (J)",
+            K => "Reserved (K)",
+            L => "Reserved (L)",
+            O => "Reserved (O)",
+            R => "Reserved (R)",
+            T => "Reserved (T)",
+            W => "Reserved (W)",
+            Y => "Reserved (Y)",
+            Z => "Reserved (Z)",
+        }
+    }
+}
+
 /// The Machine Instruction Set Architecture register
 #[derive(Debug, Clone, Copy)]
 pub struct Misa {
@@ -25,71 +140,99 @@ pub struct Misa {
 
 impl Misa {
     pub const EXPECTED_XLEN: usize = XLEN;
-    pub const EXTENSION_NAMES: &'static str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
-    pub const EXTENSION_DESCRIPTIONS: [&'static str; 26] = [
-        "Atomics (A)",
-        "Reserved (B)", // Bit-manipulation
-        "Compressed (C)",
-        "Double-precision floating point (D)",
-        "Embedded base ISA (E)",
-        "Single-precision floating point (F)",
-        "Additional standards present (G)",
-        "Hypervisor (H)",
-        "Integer base ISA (I)",
-        "Reserved (J)", // dynamically translated languages
-        "Reserved (K)",
-        "Reserved (L)",
-        "Integer multiply & divide (M)",
-        "User-level interrupts (N)",
-        "Reserved (O)",
-        "Reserved (P)", // packed-simd
-        "Quad precision floating point (Q)",
-        "Reserved (R)",
-        "Supervisor Mode (S)",
-        "Reserved (T)",
-        "User Mode (U)",
-        "Reserved (V)", // vector
-        "Reserved (W)",
-        "Nonstandard extensions present (X)",
-        "Reserved (Y)",
-        "Reserved (Z)",
-    ];
 
+    /// Reads misa, which is only accessible from machine mode.
+    /// Returns None if misa is not implemented.
     pub fn get() -> Option<Misa> {
-        let misa = unsafe {
-            let misa: usize;
-            asm!("csrr   {}, misa", out(reg) misa);
-            misa
-        };
-        if misa == 0 {
-            None
-        } else {
-            let base: Architecture = (((misa & BASE) >> (XLEN - 2)) as u8).into();
-            let extensions = (misa & EXTENSION_MASK) as u32;
-            Some(Misa { base, extensions })
+        Misa::from_raw(super::csr::misa::read().bits())
+    }
+    /// Decodes a misa value as read into a register of this width,
+    /// returning None for the zero value of an unimplemented misa.
+    pub fn from_raw(misa: usize) -> Option<Misa> {
+        let base: Architecture = (((misa & BASE) >> (XLEN - 2)) as u8).into();
+        match base {
+            Architecture::Unknown => None,
+            base => Some(Misa {
+                base,
+                extensions: (misa & EXTENSION_MASK) as u32,
+            }),
         }
     }
     pub fn base(&self) -> Architecture {
         self.base
     }
     pub fn xlen(&self) -> usize {
-        use Architecture as A;
-        match self.base {
-            A::Unknown => unreachable!(),
-            A::ThirtyTwo => 32,
-            A::SixtyFour => 64,
-            A::OneTwentyEight => 128,
-        }
+        self.base.xlen()
     }
     pub fn extensions(&self) -> u32 {
         self.extensions
     }
-    pub fn extension_name(extension: u8) -> Option<char> {
-        Self::EXTENSION_NAMES.chars().nth(extension as usize)
+    pub fn has(&self, extension: MisaExtension) -> bool {
+        self.extensions & extension.bit() != 0
     }
-    pub fn extension_description(extension: u8) -> Option<&'static str> {
-        Self::EXTENSION_DESCRIPTIONS
+    /// The extensions which are present, in alphabetical order
+    pub fn iter(&self) -> impl Iterator<Item = MisaExtension> + '_ {
+        MisaExtension::ALL
             .into_iter()
-            .nth(extension as usize)
+            .filter(|extension| self.has(*extension))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::{Architecture, Misa, MisaExtension, XLEN};
+
+    /// misa of a QEMU rv64gc hart, less the MXL field: ACDFIMSU
+    const RV64GC: usize = 0x14112d;
+
+    fn misa(mxl: usize, extensions: usize) -> usize {
+        mxl << (XLEN - 2) | extensions
+    }
+
+    #[test]
+    fn unimplemented_misa_is_none() {
+        assert!(Misa::from_raw(0).is_none());
+        // extension bits without a base are still unimplemented
+        assert!(Misa::from_raw(RV64GC).is_none());
+    }
+
+    #[test]
+    fn base_from_mxl() {
+        for (mxl, base, xlen) in [
+            (1, Architecture::ThirtyTwo, 32),
+            (2, Architecture::SixtyFour, 64),
+            (3, Architecture::OneTwentyEight, 128),
+        ] {
+            let decoded = Misa::from_raw(misa(mxl, RV64GC)).unwrap();
+            assert_eq!(decoded.base(), base);
+            assert_eq!(decoded.xlen(), xlen);
+            assert_eq!(decoded.extensions() as usize, RV64GC);
+        }
+    }
+
+    #[test]
+    fn extension_letters() {
+        let decoded = Misa::from_raw(misa(2, RV64GC)).unwrap();
+        let letters: Vec<char> = decoded.iter().map(MisaExtension::letter).collect();
+        assert_eq!(letters, ['A', 'C', 'D', 'F', 'I', 'M', 'S', 'U']);
+        assert!(decoded.has(MisaExtension::I) && decoded.has(MisaExtension::S));
+        assert!(!decoded.has(MisaExtension::Q) && !decoded.has(MisaExtension::V));
+    }
+
+    #[test]
+    fn every_extension_bit() {
+        for extension in MisaExtension::ALL {
+            let decoded = Misa::from_raw(misa(2, extension.bit() as usize)).unwrap();
+            assert_eq!(decoded.iter().collect::<Vec<_>>(), [extension]);
+            assert_eq!(
+                MisaExtension::try_from(extension.letter() as u8 - b'A'),
+                Ok(extension)
+            );
+        }
+        // the bits between the extensions and MXL are not extensions
+        let decoded = Misa::from_raw(misa(2, 1 << 26)).unwrap();
+        assert_eq!(decoded.extensions(), 0);
     }
 }
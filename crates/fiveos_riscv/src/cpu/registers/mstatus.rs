@@ -1,81 +1,37 @@
-///! Access to the mstatus csr, based on The RISC-V Instruction Set Manual Vol II, Privileged Architecture Version 1.9
-use core::arch::asm;
+//! Access to the mstatus and sstatus csrs, based on The RISC-V Instruction Set Manual Vol II, Privileged Architecture Version 1.12
+//!
+//! The register values are [`MStatus`] and [`SStatus`], generated in [`super::csr`]
+//! along with the functions to read and write them, with the layout of
+//! RV32 or RV64 to match the target.
 use num_enum::{FromPrimitive, IntoPrimitive};
-use paste::paste;
 
-macro_rules! bool_access {
-    ($field_name:ident, $desc:literal ,$mask:ident) => {
-        paste! {
-            #[doc="Get the \"" $desc "\" field from the mstatus register"]
-            #[inline]
-            pub fn [< get_ $field_name >](&self) -> bool {
-                let tmp: usize;
-                unsafe { asm!("csrr {tmp}, mstatus", tmp = out(reg) tmp); }
-                (tmp & mask::$mask) != 0
-            }
-
-            #[doc="Set the \"" $desc "\" field in the mstatus register"]
-            #[inline]
-            pub fn [< set_ $field_name >] (&self, value: bool) {
-                if value {
-                    unsafe { asm!("csrs mstatus, {tmp}", tmp = in(reg) mask::$mask); }
-                } else {
-                    unsafe { asm!("csrc mstatus, {tmp}", tmp = in(reg) mask::$mask); }
-                }
-            }
-        }
-    };
-}
-
-macro_rules! enum_access {
-    ($field_name:ident, $desc:literal, $enum_name:ident, $mask_offset:ident) => {
-        paste! {
-            #[doc="Get the \"" $desc "\" field from the mstatus register"]
-            #[inline]
-            pub fn [< get_ $field_name >] (&self) -> $enum_name {
-                let tmp: usize;
-                unsafe {asm!("csrr {tmp}, mstatus", tmp = out(reg) tmp);}
-                let tmp: usize = ((tmp & mask::$mask_offset.0) >> mask::$mask_offset.1);
-                <$enum_name as From<u8>>::from(tmp as u8)
-            }
-            #[doc="Set the \"" $desc "\" field in the mstatus register, first clearing that field."]
-            #[inline]
-            pub fn [< set_ $field_name >] (&self, value: $enum_name){
-                let tmp = (<$enum_name as Into<u8>>::into(value) as usize) << mask::$mask_offset.1;
-                let mask = mask::$mask_offset.0;
-                unsafe {asm!("csrc mstatus, {mask}", "csrs mstatus, {tmp}", mask = in(reg) mask, tmp = in(reg) tmp);}
-            }
-        }
-    };
-}
-
-/// ZST for accessing the MStatus register
-#[derive(Clone, Copy)]
-pub struct MStatus {}
+pub use super::csr::mstatus::{clear, modify, read, set, write, MStatus};
+pub use super::csr::sstatus::SStatus;
 
 impl MStatus {
-    bool_access!(mie, "machine interrupts enabled", MIE);
-    bool_access!(hie, "hypervisor interrupts enabled", HIE);
-    bool_access!(sie, "supervisor interrupts enabled", SIE);
-    bool_access!(uie, "user interrupts enabled", UIE);
-
-    bool_access!(mpie, "machine interrupts enabled previously", MPIE);
-    bool_access!(hpie, "hypervisor interrupts enabled previously", HPIE);
-    bool_access!(spie, "supervisor interrupts enabled previously", SPIE);
-    bool_access!(upie, "user interrupts enabled previously", UPIE);
-
-    bool_access!(spp, "supervisor previous privilege", SPP);
-    enum_access!(hpp, "hypervisor previous privilege", PrivilegeMode, HPP);
-    enum_access!(mpp, "machine previous privilege", PrivilegeMode, MPP);
-
-    enum_access!(fs, "floating point status", UnitStatus, FS);
-    enum_access!(xs, "extension status", UnitStatus, XS);
-    bool_access!(sd, "some dirty", SD);
-
-    bool_access!(mprv, "memory privilege", MPRV);
-    bool_access!(mxr, "make executable readable", MXR);
-    bool_access!(pum, "protect user memory", PUM);
-    enum_access!(vm, "virtualization management", VirtualMemoryMode, VM);
+    /// The fields of mstatus which are visible through sstatus: sie, spie,
+    /// ube, spp, vs, fs, xs, sum, mxr, uxl on RV64, and sd
+    const SSTATUS_MASK: usize = 1 << 1
+        | 1 << 5
+        | 1 << 6
+        | 1 << 8
+        | 0b11 << 9
+        | 0b11 << 13
+        | 0b11 << 15
+        | 1 << 18
+        | 1 << 19
+        | MStatus::UXL_MASK
+        | 1 << (usize::BITS - 1);
+    #[cfg(target_pointer_width = "64")]
+    const UXL_MASK: usize = 0b11 << 32;
+    #[cfg(target_pointer_width = "32")]
+    const UXL_MASK: usize = 0;
+
+    /// The value sstatus reads as while mstatus holds this value
+    #[inline]
+    pub const fn sstatus(&self) -> SStatus {
+        SStatus::from_bits(self.bits() & MStatus::SSTATUS_MASK)
+    }
 }
 
 /// State of processor units that may need context saves,
@@ -83,7 +39,7 @@ impl MStatus {
 ///
 /// In the case of multiple vendor defined extensions, the
 /// value provided is the worst-case.
-#[derive(Debug, Eq, PartialEq, FromPrimitive, IntoPrimitive)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, FromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 pub enum UnitStatus {
     /// All are off
@@ -97,65 +53,109 @@ pub enum UnitStatus {
     Dirty = 3,
 }
 
-/// The state of virtual memory management.
-/// Note that reserved values are not preserved here
-#[repr(u8)]
-#[derive(Debug, Eq, PartialEq, FromPrimitive, IntoPrimitive)]
-pub enum VirtualMemoryMode {
-    Bare = 0,
-    BaseBound = 1,
-    SeparateBaseBound = 2,
-    #[num_enum(default)]
-    Reserved,
-    Sv32 = 8,
-    Sv39 = 9,
-    Sv48 = 10,
-    Sv57 = 11,
-    Sv64 = 12,
-}
-
 /// The mode that determines what access the CPU currently has
-#[derive(Debug, Eq, PartialEq, FromPrimitive, IntoPrimitive)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, FromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 pub enum PrivilegeMode {
     #[num_enum(default)]
     User = 0,
     Supervisor = 1,
-    Hypervisor = 2,
+    /// was hypervisor mode in earlier versions of the spec
+    Reserved = 2,
     Machine = 3,
 }
 
-mod mask {
-    //! masks for the fields inside mstatus
-
-    use core::mem::size_of;
-    const XLEN: usize = size_of::<usize>() * 8;
-
-    // interrupt-enable bits for each privilege mode
-    pub const UIE: usize = 1 << 0;
-    pub const SIE: usize = 1 << 1;
-    pub const HIE: usize = 1 << 2;
-    pub const MIE: usize = 1 << 3;
-
-    // previous interrupt enable status
-    pub const UPIE: usize = 1 << 4;
-    pub const SPIE: usize = 1 << 5;
-    pub const HPIE: usize = 1 << 6;
-    pub const MPIE: usize = 1 << 7;
-
-    // previous privilege modes
-    pub const SPP: usize = 1 << 8;
-    pub const HPP: (usize, usize) = (0b11 << 9, 9);
-    pub const MPP: (usize, usize) = (0b11 << 11, 11);
-
-    // processor extension status
-    pub const FS: (usize, usize) = (0b11 << 13, 13);
-    pub const XS: (usize, usize) = (0b11 << 15, 15);
-    pub const SD: usize = 1 << (XLEN - 1);
+/// Register width of a privilege mode, as found in uxl and sxl
+#[derive(Clone, Copy, Debug, Eq, PartialEq, FromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum Xlen {
+    #[num_enum(default)]
+    Reserved = 0,
+    Rv32 = 1,
+    Rv64 = 2,
+    Rv128 = 3,
+}
 
-    // protection modes
-    pub const MPRV: usize = 1 << 17;
-    pub const PUM: usize = 1 << 18;
-    pub const MXR: usize = 1 << 19;
-    pub const VM: (usize, usize) = (0b1111 << 24, 24);
+/// The RV64 layout, the one the host tests run with
+#[cfg(all(test, target_pointer_width = "64"))]
+mod tests {
+    use std::format;
+
+    use super::{MStatus, PrivilegeMode, SStatus, UnitStatus, Xlen};
+
+    #[test]
+    fn field_positions() {
+        let bit = |status: MStatus| status.bits().trailing_zeros();
+        let status = MStatus::new();
+        assert_eq!(bit(status.with_sie(true)), 1);
+        assert_eq!(bit(status.with_mie(true)), 3);
+        assert_eq!(bit(status.with_spp(true)), 8);
+        assert_eq!(bit(status.with_sum(true)), 18);
+        assert_eq!(bit(status.with_mxr(true)), 19);
+        assert_eq!(bit(status.with_tvm(true)), 20);
+        assert_eq!(bit(status.with_tw(true)), 21);
+        assert_eq!(bit(status.with_tsr(true)), 22);
+        assert_eq!(bit(status.with_sbe(true)), 36);
+        assert_eq!(bit(status.with_mbe(true)), 37);
+        assert_eq!(status.with_sd(true).bits(), 1 << (usize::BITS - 1));
+
+        assert_eq!(status.with_vs(UnitStatus::Dirty).bits(), 0b11 << 9);
+        assert_eq!(status.with_mpp(PrivilegeMode::Machine).bits(), 0b11 << 11);
+        assert_eq!(status.with_fs(UnitStatus::Clean).bits(), 0b10 << 13);
+        assert_eq!(status.with_xs(UnitStatus::Initial).bits(), 0b01 << 15);
+        assert_eq!(status.with_uxl(Xlen::Rv64).bits(), 0b10 << 32);
+        assert_eq!(status.with_sxl(Xlen::Rv32).bits(), 0b01 << 34);
+
+        let status = MStatus::from_bits(0b01 << 11 | 0b11 << 13 | 0b10 << 34);
+        assert_eq!(status.mpp(), PrivilegeMode::Supervisor);
+        assert_eq!(status.fs(), UnitStatus::Dirty);
+        assert_eq!(status.sxl(), Xlen::Rv64);
+        assert_eq!(status.vs(), UnitStatus::Off);
+    }
+
+    #[test]
+    fn sstatus_is_masked() {
+        let machine_only = MStatus::new()
+            .with_mie(true)
+            .with_mpie(true)
+            .with_mpp(PrivilegeMode::Machine)
+            .with_mprv(true)
+            .with_tvm(true)
+            .with_tw(true)
+            .with_tsr(true)
+            .with_sxl(Xlen::Rv64)
+            .with_sbe(true)
+            .with_mbe(true);
+        assert_eq!(machine_only.sstatus().bits(), 0);
+
+        let shared = MStatus::new()
+            .with_sie(true)
+            .with_spie(true)
+            .with_ube(true)
+            .with_spp(true)
+            .with_vs(UnitStatus::Initial)
+            .with_fs(UnitStatus::Dirty)
+            .with_xs(UnitStatus::Clean)
+            .with_sum(true)
+            .with_mxr(true)
+            .with_uxl(Xlen::Rv64)
+            .with_sd(true);
+        let sstatus = MStatus::from_bits(shared.bits() | machine_only.bits()).sstatus();
+        assert_eq!(sstatus.bits(), shared.bits());
+        assert_eq!(sstatus.fs(), UnitStatus::Dirty);
+        assert_eq!(sstatus.uxl(), Xlen::Rv64);
+    }
+
+    #[test]
+    fn debug() {
+        let status = SStatus::new()
+            .with_sie(true)
+            .with_fs(UnitStatus::Initial)
+            .with_uxl(Xlen::Rv64);
+        assert_eq!(
+            format!("{:?}", status),
+            "SStatus { bits: 0x200002002, sie: true, spie: false, ube: false, spp: false, \
+             vs: Off, fs: Initial, xs: Off, sum: false, mxr: false, uxl: Rv64, sd: false }"
+        );
+    }
 }
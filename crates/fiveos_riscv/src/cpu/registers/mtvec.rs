@@ -1,38 +1,169 @@
-//! Machine Trap Vector
-
-use core::arch::asm;
-
-#[inline]
-pub unsafe fn set_trap_vector(handler: fn()) {
-    let address = handler as *const fn() as usize;
-    assert!(
-        address & 0b11 == 0,
-        "Attempted to set trap vector to misaligned pointer"
-    );
-    asm!("csrw mtvec, {tmp}", tmp = in(reg) address);
+//! Machine and supervisor trap vectors, where a hart jumps on a trap.
+//!
+//! In direct mode every trap jumps to one entry point. In vectored mode
+//! exceptions still jump to the base address, but interrupts jump 4 bytes
+//! per cause code past it, into a table of jumps built by [`vector_table!`].
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+pub use super::csr::mtvec::{read, write, Mtvec};
+pub use super::csr::stvec::Stvec;
+use super::csr::{mtvec, stvec};
+use super::mcause::Interrupt;
+
+/// An assembly trap entry point, which saves any register it uses
+/// and returns with mret or sret rather than ret.
+pub type TrapEntry = unsafe extern "C" fn();
+
+/// How the hart picks the address to jump to, the mode field of mtvec and stvec
+#[derive(Clone, Copy, Debug, Eq, PartialEq, FromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum TrapMode {
+    /// all traps jump to the base address
+    Direct = 0,
+    /// interrupts jump to the base address plus 4 times their cause code
+    Vectored = 1,
+    #[num_enum(default)]
+    Reserved = 2,
 }
 
-#[inline]
-pub unsafe fn set_mode(bool: bool) {
-    if bool {
-        asm!("csrc mtvec, {tmp}", tmp = in(reg) 0b1);
-    } else {
-        asm!("csrs mtvec, {tmp}", tmp = in(reg) 0b1);
+/// Entries in a vector table, one for each of the interrupt codes
+/// defined by the spec. Platform interrupts from 16 up are not covered.
+pub const VECTOR_TABLE_ENTRIES: usize = 16;
+
+/// A table of jump instructions built by [`vector_table!`], in the text section.
+#[repr(C, align(4))]
+pub struct VectorTable([u32; VECTOR_TABLE_ENTRIES]);
+
+impl VectorTable {
+    pub fn address(&self) -> usize {
+        self as *const VectorTable as usize
     }
+    /// Where a hart using this table jumps to for the interrupt
+    pub fn entry(&self, interrupt: Interrupt) -> usize {
+        self.address() + 4 * interrupt.code()
+    }
+}
+
+macro_rules! trap_vector {
+    ($name:ident) => {
+        impl $name {
+            /// All traps jump to the entry, which must be 4 byte aligned
+            pub fn direct(entry: TrapEntry) -> $name {
+                let address = entry as usize;
+                assert!(
+                    address & 0b11 == 0,
+                    "Attempted to set trap vector to misaligned entry 0x{:x}",
+                    address
+                );
+                $name::new()
+                    .with_base(address >> 2)
+                    .with_mode(TrapMode::Direct)
+            }
+            /// Exceptions jump to the start of the table, and interrupts to their entry in it
+            pub fn vectored(table: &'static VectorTable) -> $name {
+                $name::new()
+                    .with_base(table.address() >> 2)
+                    .with_mode(TrapMode::Vectored)
+            }
+            /// Address of the entry point or vector table
+            pub fn address(&self) -> usize {
+                self.base() << 2
+            }
+        }
+    };
+}
+
+trap_vector!(Mtvec);
+trap_vector!(Stvec);
+
+/// Send machine mode traps to the vector. Returns false if the hart does not
+/// support the mode or alignment, which leaves mtvec holding what the hart made of it.
+///
+/// ## Safety
+/// Must be in machine mode, and the entry points must handle every trap
+/// that can be taken from here on.
+pub unsafe fn set_trap_vector(vector: Mtvec) -> bool {
+    mtvec::write(vector);
+    mtvec::read() == vector
 }
 
-#[inline]
-pub unsafe fn set_low_bits(value: u8) {
-    assert!(
-        value & !0b11 == 0,
-        "Attempted to set values outside of mtvec low bits"
-    );
-    asm!("csrc mtvec, {tmp}", "csrs mtvec, {v}", tmp = in(reg) 0b11, v = in(reg) value & 0b11);
+/// Send supervisor mode traps to the vector, see [`set_trap_vector`]
+///
+/// ## Safety
+/// Must be in supervisor or machine mode, and the entry points must handle
+/// every trap that can be taken in supervisor mode from here on.
+pub unsafe fn set_supervisor_trap_vector(vector: Stvec) -> bool {
+    stvec::write(vector);
+    stvec::read() == vector
 }
 
-#[inline]
-pub fn read() -> usize {
-    let out: usize;
-    unsafe {asm!("csrr {tmp}, mtvec", tmp = out(reg) out)};
-    out
+/// Builds a [`VectorTable`] in assembly, returned by a function of the given name.
+///
+/// Slot 0 jumps to the `exception` entry, as do all exceptions in vectored
+/// mode. The slots for the named [`Interrupt`]s jump to their entries, and
+/// all others jump to `default`. Entries are [`TrapEntry`] functions,
+/// e.g. declared in an `extern "C"` block for an assembly label.
+///
+/// ```ignore
+/// vector_table! {
+///     pub fn machine_vectors {
+///         exception: asm_trap_vector,
+///         default: asm_trap_vector,
+///         MachineTimer: asm_timer_vector,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! vector_table {
+    (
+        $(#[$meta:meta])*
+        $vis:vis fn $name:ident {
+            exception: $exception:path,
+            default: $default:path,
+            $( $interrupt:ident: $entry:path ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis fn $name() -> &'static $crate::cpu::registers::mtvec::VectorTable {
+            // the table is named after the function, and each used slot
+            // gets a symbol so the assembler can tell which are left over
+            #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+            ::core::arch::global_asm!(
+                $( concat!(".set ", stringify!($name), "_slot_{}, {} # ", stringify!($interrupt)), )*
+                ".pushsection .text",
+                ".option push",
+                // every slot must be a 4 byte jump
+                ".option norvc",
+                ".balign 4",
+                concat!(".global ", stringify!($name), "_vector_table"),
+                concat!(stringify!($name), "_vector_table:"),
+                "j {exception}",
+                ".irp code, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15",
+                concat!(".ifdef ", stringify!($name), "_slot_\\code"),
+                concat!("j ", stringify!($name), "_slot_\\code"),
+                ".else",
+                "j {default}",
+                ".endif",
+                ".endr",
+                ".option pop",
+                ".popsection",
+                $(
+                    const $crate::cpu::registers::mcause::Interrupt::$interrupt.code(),
+                    sym $entry,
+                )*
+                exception = sym $exception,
+                default = sym $default,
+            );
+            // the entries are only named in the assembly, elsewhere there is none
+            #[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
+            let _ = [$exception as usize, $default as usize, $($entry as usize),*];
+            extern "C" {
+                #[link_name = concat!(stringify!($name), "_vector_table")]
+                static TABLE: $crate::cpu::registers::mtvec::VectorTable;
+            }
+            // safety: the table is only ever read, by the hart
+            unsafe { &*::core::ptr::addr_of!(TABLE) }
+        }
+    };
 }
@@ -0,0 +1,425 @@
+//! Physical Memory Protection, based on The RISC-V Instruction Set Manual Vol II, Privileged Architecture Version 1.12
+//!
+//! Each PMP entry is an address register, pmpaddrN, and an 8-bit configuration
+//! packed into the pmpcfgN registers. Supervisor and user mode may only access
+//! memory matched by an entry granting them access, so machine mode must set
+//! these up before dropping to a lower privilege. Machine mode itself is only
+//! restricted by locked entries.
+use core::fmt::Debug;
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+/// Number of entries supported here, implementations may have up to 64
+/// but the common ones have 8 or 16.
+pub const ENTRIES: usize = 16;
+
+/// Smallest region a PMP entry can describe, in bytes
+pub const GRANULE: usize = 4;
+
+/// How the address register of a PMP entry selects the memory it matches
+#[derive(Clone, Copy, Debug, Eq, PartialEq, FromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum AddressMatching {
+    /// the entry matches nothing
+    #[num_enum(default)]
+    Off = 0,
+    /// top of range, matching from the previous entry's address up to this one's
+    Tor = 1,
+    /// naturally aligned four byte region
+    Na4 = 2,
+    /// naturally aligned power-of-two region of eight bytes or more
+    Napot = 3,
+}
+
+/// The configuration of a single PMP entry
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct PmpConfig {
+    inner: u8,
+}
+
+impl PmpConfig {
+    const READ_BIT: u8 = 1 << 0;
+    const WRITE_BIT: u8 = 1 << 1;
+    const EXECUTE_BIT: u8 = 1 << 2;
+    const MATCHING: (u8, u8) = (0b11 << 3, 3);
+    const LOCK_BIT: u8 = 1 << 7;
+
+    pub const READ: PmpConfig = PmpConfig::new().with_readable(true);
+    pub const READ_WRITE: PmpConfig = PmpConfig::READ.with_writable(true);
+    pub const READ_EXECUTE: PmpConfig = PmpConfig::READ.with_executable(true);
+    pub const READ_WRITE_EXECUTE: PmpConfig = PmpConfig::READ_WRITE.with_executable(true);
+
+    #[inline]
+    pub const fn new() -> PmpConfig {
+        PmpConfig { inner: 0 }
+    }
+    #[inline]
+    pub const fn from_u8(inner: u8) -> PmpConfig {
+        PmpConfig { inner }
+    }
+    #[inline]
+    pub const fn as_u8(self) -> u8 {
+        self.inner
+    }
+    #[inline]
+    pub const fn is_readable(&self) -> bool {
+        self.inner & Self::READ_BIT != 0
+    }
+    #[inline]
+    pub const fn is_writable(&self) -> bool {
+        self.inner & Self::WRITE_BIT != 0
+    }
+    #[inline]
+    pub const fn is_executable(&self) -> bool {
+        self.inner & Self::EXECUTE_BIT != 0
+    }
+    /// Locked entries also apply to machine mode, and cannot be changed until reset
+    #[inline]
+    pub const fn is_locked(&self) -> bool {
+        self.inner & Self::LOCK_BIT != 0
+    }
+    #[inline]
+    pub fn matching(&self) -> AddressMatching {
+        AddressMatching::from((self.inner & Self::MATCHING.0) >> Self::MATCHING.1)
+    }
+    #[inline]
+    pub const fn with_readable(self, flag: bool) -> PmpConfig {
+        self.with_bit(Self::READ_BIT, flag)
+    }
+    #[inline]
+    pub const fn with_writable(self, flag: bool) -> PmpConfig {
+        self.with_bit(Self::WRITE_BIT, flag)
+    }
+    #[inline]
+    pub const fn with_executable(self, flag: bool) -> PmpConfig {
+        self.with_bit(Self::EXECUTE_BIT, flag)
+    }
+    #[inline]
+    pub const fn with_locked(self, flag: bool) -> PmpConfig {
+        self.with_bit(Self::LOCK_BIT, flag)
+    }
+    #[inline]
+    pub const fn with_matching(self, matching: AddressMatching) -> PmpConfig {
+        PmpConfig {
+            inner: (self.inner & !Self::MATCHING.0) | (matching as u8) << Self::MATCHING.1,
+        }
+    }
+    #[inline]
+    const fn with_bit(self, bit: u8, flag: bool) -> PmpConfig {
+        PmpConfig {
+            inner: (self.inner & !bit) | if flag { bit } else { 0 },
+        }
+    }
+}
+
+impl Debug for PmpConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let r = if self.is_readable() { "R" } else { "-" };
+        let w = if self.is_writable() { "W" } else { "-" };
+        let x = if self.is_executable() { "X" } else { "-" };
+        let l = if self.is_locked() { " locked" } else { "" };
+        write!(f, "{}{}{} {:?}{}", r, w, x, self.matching(), l)
+    }
+}
+
+/// Produces the value of pmpaddr for a NAPOT entry matching the naturally
+/// aligned region of size bytes at base.
+///
+/// Size must be a power of two of at least eight bytes, and base a multiple of it.
+pub const fn napot_address(base: usize, size: usize) -> usize {
+    assert!(
+        size.is_power_of_two() && size >= 2 * GRANULE,
+        "NAPOT regions must be a power of two of at least 8 bytes"
+    );
+    assert!(
+        base & (size - 1) == 0,
+        "NAPOT regions must be aligned to their size"
+    );
+    (base >> 2) | ((size >> 3) - 1)
+}
+
+/// Reads the raw pmpaddr register of the entry, which holds bits 2 and up of an address
+pub fn read_address(index: usize) -> usize {
+    let value: usize;
+    macro_rules! read_csr {
+        ($($n:literal),*) => {
+            match index {
+                $($n => unsafe { riscv_asm!(concat!("csrr {}, pmpaddr", $n), out(reg) value) },)*
+                _ => panic!("no PMP entry {}", index),
+            }
+        };
+    }
+    read_csr!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+    value
+}
+
+/// Writes the raw pmpaddr register of the entry, ignored if the entry is locked
+///
+/// ## Safety
+/// Changes which memory lower privilege modes, or a locked entry's machine mode, can access.
+pub unsafe fn write_address(index: usize, value: usize) {
+    macro_rules! write_csr {
+        ($($n:literal),*) => {
+            match index {
+                $($n => riscv_asm!(concat!("csrw pmpaddr", $n, ", {}"), in(reg) value),)*
+                _ => panic!("no PMP entry {}", index),
+            }
+        };
+    }
+    write_csr!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+}
+
+/// Which pmpcfg register holds the configuration of the entry, and the shift to it.
+///
+/// Each register holds XLEN/8 entries, and only the even registers exist on RV64.
+const fn config_location(index: usize) -> (usize, usize) {
+    match usize::BITS {
+        32 => (index / 4, (index % 4) * 8),
+        64 => ((index / 8) * 2, (index % 8) * 8),
+        _ => unimplemented!(),
+    }
+}
+
+fn read_config_register(register: usize) -> usize {
+    let value: usize;
+    macro_rules! read_csr {
+        ($($n:literal),*) => {
+            match register {
+                $($n => unsafe { riscv_asm!(concat!("csrr {}, pmpcfg", $n), out(reg) value) },)*
+                _ => panic!("no pmpcfg{} register", register),
+            }
+        };
+    }
+    // the odd registers are only named on RV32
+    #[cfg(target_pointer_width = "32")]
+    read_csr!(0, 1, 2, 3);
+    #[cfg(target_pointer_width = "64")]
+    read_csr!(0, 2);
+    value
+}
+
+unsafe fn write_config_register(register: usize, value: usize) {
+    macro_rules! write_csr {
+        ($($n:literal),*) => {
+            match register {
+                $($n => riscv_asm!(concat!("csrw pmpcfg", $n, ", {}"), in(reg) value),)*
+                _ => panic!("no pmpcfg{} register", register),
+            }
+        };
+    }
+    #[cfg(target_pointer_width = "32")]
+    write_csr!(0, 1, 2, 3);
+    #[cfg(target_pointer_width = "64")]
+    write_csr!(0, 2);
+}
+
+/// Reads the configuration of the entry out of its pmpcfg register
+pub fn read_config(index: usize) -> PmpConfig {
+    assert!(index < ENTRIES, "no PMP entry {}", index);
+    let (register, shift) = config_location(index);
+    PmpConfig::from_u8((read_config_register(register) >> shift) as u8)
+}
+
+/// Replaces the configuration of the entry, leaving the other entries
+/// sharing its pmpcfg register alone. Ignored if the entry is locked.
+///
+/// ## Safety
+/// Changes which memory lower privilege modes, or a locked entry's machine mode, can access.
+pub unsafe fn write_config(index: usize, config: PmpConfig) {
+    assert!(index < ENTRIES, "no PMP entry {}", index);
+    let (register, shift) = config_location(index);
+    let value = read_config_register(register) & !(0xff << shift);
+    write_config_register(register, value | (config.as_u8() as usize) << shift);
+}
+
+/// Builds up a list of PMP entries to be programmed all at once.
+///
+/// Entries are checked in order and the first match decides the access, so
+/// regions added earlier take precedence over later ones they overlap.
+#[derive(Clone, Copy)]
+pub struct PmpBuilder {
+    entries: [(usize, PmpConfig); ENTRIES],
+    count: usize,
+}
+
+impl PmpBuilder {
+    pub const fn new() -> PmpBuilder {
+        PmpBuilder {
+            entries: [(0, PmpConfig::new()); ENTRIES],
+            count: 0,
+        }
+    }
+    /// Add a naturally aligned power-of-two region of size bytes at base,
+    /// with the permissions and lock bit of config.
+    pub fn napot(self, base: usize, size: usize, config: PmpConfig) -> PmpBuilder {
+        if size == GRANULE {
+            assert!(
+                base & (GRANULE - 1) == 0,
+                "NA4 regions must be aligned to 4 bytes"
+            );
+            self.push(base >> 2, config.with_matching(AddressMatching::Na4))
+        } else {
+            let address = napot_address(base, size);
+            self.push(address, config.with_matching(AddressMatching::Napot))
+        }
+    }
+    /// Add the region from start up to end, with the permissions and lock bit
+    /// of config. Both must be multiples of 4 bytes.
+    ///
+    /// This uses a single TOR entry when start is 0 or the end of the previous
+    /// region added this way, otherwise an extra entry marks the start.
+    pub fn range(self, start: usize, end: usize, config: PmpConfig) -> PmpBuilder {
+        assert!(
+            start & (GRANULE - 1) == 0 && end & (GRANULE - 1) == 0,
+            "PMP ranges must be aligned to 4 bytes"
+        );
+        assert!(start < end, "PMP range 0x{:x}-0x{:x} is empty", start, end);
+        let follows_previous = match self.count {
+            0 => start == 0,
+            count => {
+                let (address, previous) = self.entries[count - 1];
+                address == start >> 2
+                    && matches!(
+                        previous.matching(),
+                        AddressMatching::Tor | AddressMatching::Off
+                    )
+            }
+        };
+        let builder = if follows_previous {
+            self
+        } else {
+            self.push(start >> 2, PmpConfig::new())
+        };
+        builder.push(end >> 2, config.with_matching(AddressMatching::Tor))
+    }
+    fn push(mut self, address: usize, config: PmpConfig) -> PmpBuilder {
+        assert!(self.count < ENTRIES, "out of PMP entries");
+        self.entries[self.count] = (address, config);
+        self.count += 1;
+        self
+    }
+    /// The entries which will be programmed, as pmpaddr value and configuration
+    pub fn entries(&self) -> &[(usize, PmpConfig)] {
+        &self.entries[..self.count]
+    }
+    /// Program this hart's PMP with the entries, turning off the remaining ones.
+    ///
+    /// ## Safety
+    /// Replaces which memory lower privilege modes can access, and when entries
+    /// are locked, which memory machine mode can access until reset.
+    pub unsafe fn apply(&self) {
+        // turn every entry off before changing the addresses, so
+        // none matches a mix of the old and new values
+        for index in 0..ENTRIES {
+            write_config(index, PmpConfig::new());
+        }
+        for (index, (address, _)) in self.entries.iter().enumerate() {
+            write_address(index, *address);
+        }
+        for (index, (_, config)) in self.entries.iter().enumerate() {
+            write_config(index, *config);
+        }
+        riscv_asm!("sfence.vma");
+    }
+}
+
+impl Default for PmpBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for PmpBuilder {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries(self.entries().iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{config_location, napot_address, AddressMatching, PmpBuilder, PmpConfig, ENTRIES};
+
+    #[test]
+    fn napot_addresses() {
+        assert_eq!(napot_address(0x8000_0000, 8), 0x2000_0000);
+        assert_eq!(napot_address(0x8000_0000, 0x1000), 0x2000_01ff);
+        assert_eq!(napot_address(0x8000_0000, 0x8000_0000), 0x2fff_ffff);
+    }
+
+    #[test]
+    #[should_panic(expected = "aligned to their size")]
+    fn napot_must_be_aligned() {
+        napot_address(0x8000_1000, 0x2000);
+    }
+
+    #[test]
+    fn napot_and_na4_entries() {
+        let pmp = PmpBuilder::new()
+            .napot(0x8000_0000, 0x1000, PmpConfig::READ)
+            .napot(0x1000, 4, PmpConfig::READ_WRITE);
+        let [(napot, napot_config), (na4, na4_config)] = pmp.entries() else {
+            panic!("expected two entries, got {:?}", pmp);
+        };
+        assert_eq!(*napot, 0x2000_01ff);
+        assert_eq!(napot_config.matching(), AddressMatching::Napot);
+        assert!(napot_config.is_readable() && !napot_config.is_writable());
+        assert_eq!(*na4, 0x400);
+        assert_eq!(na4_config.matching(), AddressMatching::Na4);
+        assert!(na4_config.is_writable());
+    }
+
+    #[test]
+    fn ranges_share_entries() {
+        // from 0 the top of the range is enough, as is following on
+        let pmp = PmpBuilder::new()
+            .range(0, 0x8000_0000, PmpConfig::READ_WRITE)
+            .range(0x8000_0000, 0x8800_0000, PmpConfig::READ_WRITE_EXECUTE);
+        assert_eq!(pmp.entries().len(), 2);
+        assert_eq!(pmp.entries()[0].0, 0x2000_0000);
+        assert_eq!(pmp.entries()[1].0, 0x2200_0000);
+        for (_, config) in pmp.entries() {
+            assert_eq!(config.matching(), AddressMatching::Tor);
+        }
+        assert!(pmp.entries()[1].1.is_executable());
+    }
+
+    #[test]
+    fn ranges_elsewhere_mark_their_start() {
+        let pmp = PmpBuilder::new()
+            .range(0x1000, 0x2000, PmpConfig::READ)
+            .range(0x3000, 0x4000, PmpConfig::READ);
+        let addresses: std::vec::Vec<_> = pmp
+            .entries()
+            .iter()
+            .map(|(address, config)| (*address, config.matching()))
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                (0x400, AddressMatching::Off),
+                (0x800, AddressMatching::Tor),
+                (0xc00, AddressMatching::Off),
+                (0x1000, AddressMatching::Tor),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "out of PMP entries")]
+    fn out_of_entries() {
+        let mut pmp = PmpBuilder::default();
+        for entry in 0..=ENTRIES {
+            pmp = pmp.napot(entry * 8, 8, PmpConfig::READ);
+        }
+    }
+
+    #[test]
+    fn config_locations() {
+        #[cfg(target_pointer_width = "64")]
+        let expected = [(0, (0, 0)), (7, (0, 56)), (8, (2, 0)), (15, (2, 56))];
+        #[cfg(target_pointer_width = "32")]
+        let expected = [(0, (0, 0)), (3, (0, 24)), (4, (1, 0)), (15, (3, 24))];
+        for (index, location) in expected {
+            assert_eq!(config_location(index), location);
+        }
+    }
+}
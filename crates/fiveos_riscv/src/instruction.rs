@@ -0,0 +1,438 @@
+//! Decoding of the few instructions a trap handler needs to complete on
+//! the hardware's behalf: integer loads and stores, which may be
+//! misaligned, and the csr instructions, for registers such as time
+//! which may not be implemented. Both the 4 byte encodings and their
+//! 2 byte compressed forms are decoded.
+
+/// Size of a memory access
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte = 1,
+    Half = 2,
+    Word = 4,
+    Double = 8,
+}
+
+impl Width {
+    pub const fn bytes(self) -> usize {
+        self as usize
+    }
+}
+
+/// The operations of the csr instructions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsrOp {
+    /// csrrw, csrrwi
+    Write,
+    /// csrrs, csrrsi
+    Set,
+    /// csrrc, csrrci
+    Clear,
+}
+
+/// The source of a csr instruction's operand
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsrSource {
+    Register(usize),
+    /// the 5 bit immediate of csrrwi, csrrsi and csrrci
+    Immediate(usize),
+}
+
+/// A decoded instruction, registers given by number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// rd = memory[rs1 + offset], sign extended when signed
+    Load {
+        width: Width,
+        signed: bool,
+        rd: usize,
+        rs1: usize,
+        offset: isize,
+    },
+    /// memory[rs1 + offset] = rs2
+    Store {
+        width: Width,
+        rs1: usize,
+        rs2: usize,
+        offset: isize,
+    },
+    /// rd = csr, then csr is written, set or cleared with the source
+    Csr {
+        op: CsrOp,
+        rd: usize,
+        source: CsrSource,
+        csr: usize,
+    },
+}
+
+/// An instruction, with the number of bytes its encoding takes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decoded {
+    pub instruction: Instruction,
+    pub length: usize,
+}
+
+/// Bytes taken by the instruction starting with the given 16 bits, from
+/// the length encoding in its low bits. None for the encodings reserved
+/// for instructions of 192 bits and longer.
+///
+/// ```
+/// # use fiveos_riscv::instruction::length;
+/// // c.ebreak
+/// assert_eq!(length(0x9002), Some(2));
+/// // ebreak, and ecall
+/// assert_eq!(length(0x0073), Some(4));
+/// assert_eq!(length(0b001_1111), Some(6));
+/// assert_eq!(length(0b011_1111), Some(8));
+/// assert_eq!(length(0b010_0000_0111_1111), Some(14));
+/// assert_eq!(length(0b111_0000_0111_1111), None);
+/// ```
+pub const fn length(low: u16) -> Option<usize> {
+    if low & 0b11 != 0b11 {
+        Some(2)
+    } else if low & 0b1_1100 != 0b1_1100 {
+        Some(4)
+    } else if low & 0b11_1111 == 0b01_1111 {
+        Some(6)
+    } else if low & 0b111_1111 == 0b011_1111 {
+        Some(8)
+    } else if low & 0b111_1111 == 0b111_1111 && low >> 12 & 0b111 != 0b111 {
+        // 80 + 16 * nnn bits
+        Some(10 + 2 * (low >> 12 & 0b111) as usize)
+    } else {
+        None
+    }
+}
+
+/// Bytes taken by ecall, which has no compressed form, so can be stepped
+/// over without being read, as from machine mode, where epc is virtual
+pub const ECALL_LENGTH: usize = 4;
+
+/// Decodes an instruction, from its first 4 bytes, or the first 2 when
+/// compressed. None for anything else, as only the instructions above
+/// are decoded.
+///
+/// ```
+/// # use fiveos_riscv::instruction::*;
+/// // ld a0, 8(sp)
+/// let ld = decode(0x0081_3503).unwrap();
+/// assert_eq!(ld.length, 4);
+/// assert_eq!(
+///     ld.instruction,
+///     Instruction::Load { width: Width::Double, signed: true, rd: 10, rs1: 2, offset: 8 }
+/// );
+/// // c.sw a1, 4(a0)
+/// let sw = decode(0xc14c).unwrap();
+/// assert_eq!(sw.length, 2);
+/// assert_eq!(
+///     sw.instruction,
+///     Instruction::Store { width: Width::Word, rs1: 10, rs2: 11, offset: 4 }
+/// );
+/// // rdtime a0
+/// let rdtime = decode(0xc010_2573).unwrap();
+/// assert_eq!(
+///     rdtime.instruction,
+///     Instruction::Csr { op: CsrOp::Set, rd: 10, source: CsrSource::Register(0), csr: 0xc01 }
+/// );
+/// ```
+pub fn decode(bits: u32) -> Option<Decoded> {
+    let length = length(bits as u16)?;
+    let instruction = match length {
+        2 => decode_compressed(bits as u16),
+        4 => decode_full(bits),
+        _ => None,
+    }?;
+    Some(Decoded {
+        instruction,
+        length,
+    })
+}
+
+const LOAD: u32 = 0b000_0011;
+const STORE: u32 = 0b010_0011;
+const SYSTEM: u32 = 0b111_0011;
+
+/// Bits start..start + count of an encoding
+const fn field(bits: u32, start: u32, count: u32) -> usize {
+    ((bits >> start) & ((1 << count) - 1)) as usize
+}
+
+/// Sign extends the low count bits of value, e.g. an immediate field or
+/// a value loaded from memory
+///
+/// ```
+/// # use fiveos_riscv::instruction::sign_extend;
+/// assert_eq!(sign_extend(0xfff, 12), -1);
+/// assert_eq!(sign_extend(0x7ff, 12), 2047);
+/// assert_eq!(sign_extend(0x80, 8), -128);
+/// ```
+pub const fn sign_extend(value: usize, count: u32) -> isize {
+    let shift = usize::BITS - count;
+    ((value << shift) as isize) >> shift
+}
+
+/// Doubleword accesses only exist on RV64 and up
+const fn has_double() -> bool {
+    usize::BITS >= 64
+}
+
+fn decode_full(bits: u32) -> Option<Instruction> {
+    let rd = field(bits, 7, 5);
+    let funct3 = field(bits, 12, 3);
+    let rs1 = field(bits, 15, 5);
+    let rs2 = field(bits, 20, 5);
+    match bits & 0b111_1111 {
+        LOAD => {
+            let (width, signed) = match funct3 {
+                0b000 => (Width::Byte, true),
+                0b001 => (Width::Half, true),
+                0b010 => (Width::Word, true),
+                0b011 if has_double() => (Width::Double, true),
+                0b100 => (Width::Byte, false),
+                0b101 => (Width::Half, false),
+                0b110 if has_double() => (Width::Word, false),
+                _ => return None,
+            };
+            let offset = sign_extend(field(bits, 20, 12), 12);
+            Some(Instruction::Load {
+                width,
+                signed,
+                rd,
+                rs1,
+                offset,
+            })
+        }
+        STORE => {
+            let width = match funct3 {
+                0b000 => Width::Byte,
+                0b001 => Width::Half,
+                0b010 => Width::Word,
+                0b011 if has_double() => Width::Double,
+                _ => return None,
+            };
+            let offset = sign_extend(field(bits, 25, 7) << 5 | field(bits, 7, 5), 12);
+            Some(Instruction::Store {
+                width,
+                rs1,
+                rs2,
+                offset,
+            })
+        }
+        SYSTEM => {
+            let (op, source) = match funct3 {
+                0b001 => (CsrOp::Write, CsrSource::Register(rs1)),
+                0b010 => (CsrOp::Set, CsrSource::Register(rs1)),
+                0b011 => (CsrOp::Clear, CsrSource::Register(rs1)),
+                0b101 => (CsrOp::Write, CsrSource::Immediate(rs1)),
+                0b110 => (CsrOp::Set, CsrSource::Immediate(rs1)),
+                0b111 => (CsrOp::Clear, CsrSource::Immediate(rs1)),
+                // ecall, ebreak, the returns, wfi and sfence.vma
+                _ => return None,
+            };
+            let csr = field(bits, 20, 12);
+            Some(Instruction::Csr {
+                op,
+                rd,
+                source,
+                csr,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn decode_compressed(bits: u16) -> Option<Instruction> {
+    let bits = bits as u32;
+    let funct3 = field(bits, 13, 3);
+    // the 3 bit register fields of the quadrant 0 formats name x8-x15
+    let short_rs1 = 8 + field(bits, 7, 3);
+    let short_rd = 8 + field(bits, 2, 3);
+    // c.lw, c.sw offset[5:3|2|6]
+    let word_offset = field(bits, 10, 3) << 3 | field(bits, 6, 1) << 2 | field(bits, 5, 1) << 6;
+    // c.ld, c.sd offset[5:3|7:6]
+    let double_offset = field(bits, 10, 3) << 3 | field(bits, 5, 2) << 6;
+    let rd = field(bits, 7, 5);
+    let rs2 = field(bits, 2, 5);
+    let instruction = match (bits & 0b11, funct3) {
+        // c.lw
+        (0b00, 0b010) => Instruction::Load {
+            width: Width::Word,
+            signed: true,
+            rd: short_rd,
+            rs1: short_rs1,
+            offset: word_offset as isize,
+        },
+        // c.ld
+        (0b00, 0b011) if has_double() => Instruction::Load {
+            width: Width::Double,
+            signed: true,
+            rd: short_rd,
+            rs1: short_rs1,
+            offset: double_offset as isize,
+        },
+        // c.sw
+        (0b00, 0b110) => Instruction::Store {
+            width: Width::Word,
+            rs1: short_rs1,
+            rs2: short_rd,
+            offset: word_offset as isize,
+        },
+        // c.sd
+        (0b00, 0b111) if has_double() => Instruction::Store {
+            width: Width::Double,
+            rs1: short_rs1,
+            rs2: short_rd,
+            offset: double_offset as isize,
+        },
+        // c.lwsp, offset[5|4:2|7:6]
+        (0b10, 0b010) if rd != 0 => Instruction::Load {
+            width: Width::Word,
+            signed: true,
+            rd,
+            rs1: 2,
+            offset: (field(bits, 12, 1) << 5 | field(bits, 4, 3) << 2 | field(bits, 2, 2) << 6)
+                as isize,
+        },
+        // c.ldsp, offset[5|4:3|8:6]
+        (0b10, 0b011) if has_double() && rd != 0 => Instruction::Load {
+            width: Width::Double,
+            signed: true,
+            rd,
+            rs1: 2,
+            offset: (field(bits, 12, 1) << 5 | field(bits, 5, 2) << 3 | field(bits, 2, 3) << 6)
+                as isize,
+        },
+        // c.swsp, offset[5:2|7:6]
+        (0b10, 0b110) => Instruction::Store {
+            width: Width::Word,
+            rs1: 2,
+            rs2,
+            offset: (field(bits, 9, 4) << 2 | field(bits, 7, 2) << 6) as isize,
+        },
+        // c.sdsp, offset[5:3|8:6]
+        (0b10, 0b111) if has_double() => Instruction::Store {
+            width: Width::Double,
+            rs1: 2,
+            rs2,
+            offset: (field(bits, 10, 3) << 3 | field(bits, 7, 3) << 6) as isize,
+        },
+        _ => return None,
+    };
+    Some(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(width: Width, signed: bool, rd: usize, rs1: usize, offset: isize) -> Instruction {
+        Instruction::Load {
+            width,
+            signed,
+            rd,
+            rs1,
+            offset,
+        }
+    }
+
+    fn store(width: Width, rs1: usize, rs2: usize, offset: isize) -> Instruction {
+        Instruction::Store {
+            width,
+            rs1,
+            rs2,
+            offset,
+        }
+    }
+
+    /// Every load and store form, as assembled by llvm-mc
+    const FULL: [(&str, u32); 11] = [
+        ("lb a0, -1(a1)", 0xfff5_8503),
+        ("lh a0, 2(a1)", 0x0025_9503),
+        ("lw a0, -2048(a1)", 0x8005_a503),
+        ("ld a0, 2047(a1)", 0x7ff5_b503),
+        ("lbu t0, 3(s0)", 0x0034_4283),
+        ("lhu t0, -3(s0)", 0xffd4_5283),
+        ("lwu t0, 5(s0)", 0x0054_6283),
+        ("sb a2, -1(a3)", 0xfec6_8fa3),
+        ("sh a2, 6(a3)", 0x00c6_9323),
+        ("sw a2, -2048(a3)", 0x80c6_a023),
+        ("sd a2, 2047(a3)", 0x7ec6_bfa3),
+    ];
+
+    const COMPRESSED: [(&str, u16); 16] = [
+        ("c.lw a0, 4(a1)", 0x41c8),
+        ("c.lw s1, 124(a5)", 0x5fe4),
+        ("c.ld a0, 8(a1)", 0x6588),
+        ("c.ld s1, 248(a5)", 0x7fe4),
+        ("c.sw a0, 4(a1)", 0xc1c8),
+        ("c.sw s1, 124(a5)", 0xdfe4),
+        ("c.sd a0, 8(a1)", 0xe588),
+        ("c.sd s1, 248(a5)", 0xffe4),
+        ("c.lwsp a0, 4(sp)", 0x4512),
+        ("c.lwsp t6, 252(sp)", 0x5ffe),
+        ("c.ldsp a0, 8(sp)", 0x6522),
+        ("c.ldsp t6, 504(sp)", 0x7ffe),
+        ("c.swsp a0, 4(sp)", 0xc22a),
+        ("c.swsp t6, 252(sp)", 0xdffe),
+        ("c.sdsp a0, 8(sp)", 0xe42a),
+        ("c.sdsp t6, 504(sp)", 0xfffe),
+    ];
+
+    #[test]
+    fn full_loads_and_stores() {
+        use Width::*;
+        let expected = [
+            load(Byte, true, 10, 11, -1),
+            load(Half, true, 10, 11, 2),
+            load(Word, true, 10, 11, -2048),
+            load(Double, true, 10, 11, 2047),
+            load(Byte, false, 5, 8, 3),
+            load(Half, false, 5, 8, -3),
+            load(Word, false, 5, 8, 5),
+            store(Byte, 13, 12, -1),
+            store(Half, 13, 12, 6),
+            store(Word, 13, 12, -2048),
+            store(Double, 13, 12, 2047),
+        ];
+        for ((name, bits), instruction) in FULL.into_iter().zip(expected) {
+            let decoded = decode(bits).unwrap_or_else(|| panic!("{} not decoded", name));
+            assert_eq!(decoded.length, 4, "{}", name);
+            assert_eq!(decoded.instruction, instruction, "{}", name);
+        }
+    }
+
+    #[test]
+    fn compressed_loads_and_stores() {
+        use Width::*;
+        let expected = [
+            load(Word, true, 10, 11, 4),
+            load(Word, true, 9, 15, 124),
+            load(Double, true, 10, 11, 8),
+            load(Double, true, 9, 15, 248),
+            store(Word, 11, 10, 4),
+            store(Word, 15, 9, 124),
+            store(Double, 11, 10, 8),
+            store(Double, 15, 9, 248),
+            load(Word, true, 10, 2, 4),
+            load(Word, true, 31, 2, 252),
+            load(Double, true, 10, 2, 8),
+            load(Double, true, 31, 2, 504),
+            store(Word, 2, 10, 4),
+            store(Word, 2, 31, 252),
+            store(Double, 2, 10, 8),
+            store(Double, 2, 31, 504),
+        ];
+        for ((name, bits), instruction) in COMPRESSED.into_iter().zip(expected) {
+            let decoded = decode(bits as u32).unwrap_or_else(|| panic!("{} not decoded", name));
+            assert_eq!(decoded.length, 2, "{}", name);
+            assert_eq!(decoded.instruction, instruction, "{}", name);
+        }
+    }
+
+    #[test]
+    fn reserved_compressed_loads() {
+        // c.lwsp and c.ldsp into x0 are reserved
+        assert_eq!(decode(0x4012), None);
+        assert_eq!(decode(0x6022), None);
+    }
+}
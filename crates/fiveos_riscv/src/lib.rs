@@ -7,6 +7,70 @@
     const_mut_refs
 )]
 
+/// `core::arch::asm!`, for riscv targets only. Elsewhere the instructions
+/// are left out: inputs are still evaluated, outputs are given their default
+/// value, and only `options(noreturn)` panics. So this crate and those built
+/// on it still build on the host, where the code which doesn't need the
+/// hardware can be tested.
+#[macro_export]
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+macro_rules! riscv_asm {
+    ($($args:tt)*) => {
+        core::arch::asm!($($args)*)
+    };
+}
+
+#[macro_export]
+#[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
+macro_rules! riscv_asm {
+    (@operands) => {
+        $crate::host_asm::<()>()
+    };
+    (@operands options() $(, $($rest:tt)*)?) => {
+        $crate::riscv_asm!(@operands $($($rest)*)?)
+    };
+    (@operands options(noreturn $(, $option:ident)*) $(, $($rest:tt)*)?) => {{
+        $crate::riscv_asm!(@operands $($($rest)*)?);
+        unimplemented!("riscv instructions on another architecture")
+    }};
+    (@operands options($skip:ident $(, $option:ident)*) $(, $($rest:tt)*)?) => {
+        $crate::riscv_asm!(@operands options($($option),*) $(, $($rest)*)?)
+    };
+    (@operands $name:ident = $($rest:tt)*) => {
+        $crate::riscv_asm!(@operands $($rest)*)
+    };
+    (@operands in($register:tt) $input:expr $(, $($rest:tt)*)?) => {{
+        let _ = &$input;
+        $crate::riscv_asm!(@operands $($($rest)*)?)
+    }};
+    (@operands $inout:ident($register:tt) $input:expr => $output:expr $(, $($rest:tt)*)?) => {{
+        let _ = &$input;
+        $output = $crate::host_asm();
+        $crate::riscv_asm!(@operands $($($rest)*)?)
+    }};
+    (@operands $out:ident($register:tt) $output:expr $(, $($rest:tt)*)?) => {{
+        $output = $crate::host_asm();
+        $crate::riscv_asm!(@operands $($($rest)*)?)
+    }};
+    ($template:expr $(, $($operands:tt)*)?) => {
+        $crate::riscv_asm!(@operands $($($operands)*)?)
+    };
+}
+
+/// What [`riscv_asm`] gives its outputs on the host. Unsafe like the
+/// instructions it stands in for, so the same unsafe blocks are needed.
+#[doc(hidden)]
+#[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn host_asm<T: Default>() -> T {
+    T::default()
+}
+
+#[cfg(test)]
+extern crate std;
+
 pub mod address;
 pub mod cpu;
+pub mod instruction;
 pub mod mmu;
+pub mod sbi;
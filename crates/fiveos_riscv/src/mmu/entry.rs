@@ -84,52 +84,52 @@ impl EntryFlags {
     #[inline]
     pub const fn set_valid(&mut self, flag: bool) {
         let flag = if flag { 1 } else { 0 };
-        self.inner |= flag << 0;
+        self.inner = (self.inner & !(1 << 0)) | flag << 0;
     }
     #[inline]
     pub const fn set_readable(&mut self, flag: bool) {
         let flag = if flag { 1 } else { 0 };
-        self.inner |= flag << 1;
+        self.inner = (self.inner & !(1 << 1)) | flag << 1;
     }
     #[inline]
     pub const fn set_writable(&mut self, flag: bool) {
         let flag = if flag { 1 } else { 0 };
-        self.inner |= flag << 2;
+        self.inner = (self.inner & !(1 << 2)) | flag << 2;
     }
     #[inline]
     pub const fn set_executable(&mut self, flag: bool) {
         let flag = if flag { 1 } else { 0 };
-        self.inner |= flag << 3;
+        self.inner = (self.inner & !(1 << 3)) | flag << 3;
     }
     #[inline]
     pub const fn set_user(&mut self, flag: bool) {
         let flag = if flag { 1 } else { 0 };
-        self.inner |= flag << 4;
+        self.inner = (self.inner & !(1 << 4)) | flag << 4;
     }
     #[inline]
     pub const fn set_global(&mut self, flag: bool) {
         let flag = if flag { 1 } else { 0 };
-        self.inner |= flag << 5;
+        self.inner = (self.inner & !(1 << 5)) | flag << 5;
     }
     #[inline]
     pub const fn set_accessed(&mut self, flag: bool) {
         let flag = if flag { 1 } else { 0 };
-        self.inner |= flag << 6;
+        self.inner = (self.inner & !(1 << 6)) | flag << 6;
     }
     #[inline]
     pub const fn set_dirty(&mut self, flag: bool) {
         let flag = if flag { 1 } else { 0 };
-        self.inner |= flag << 7;
+        self.inner = (self.inner & !(1 << 7)) | flag << 7;
     }
     #[inline]
     pub const fn set_softflags(&mut self, flag: (bool, bool)) {
         let flag = match flag {
             (true, true) => 0b11,
-            (true, false) => 0b10,
-            (false, true) => 0b01,
+            (true, false) => 0b01,
+            (false, true) => 0b10,
             (false, false) => 0b000,
         };
-        self.inner |= flag << 8;
+        self.inner = (self.inner & !(0b11 << 8)) | flag << 8;
     }
     /// clears the RWE bits, leaving valid bit alone
     #[inline]
@@ -189,6 +189,24 @@ impl EntryFlags {
     pub const READ_WRITE: EntryFlags = EntryFlags::READ.with_writable(true);
     pub const USER_READ_WRITE: EntryFlags = EntryFlags::READ_WRITE.with_user(true);
     pub const READ_EXECUTE: EntryFlags = EntryFlags::READ.with_executable(true);
+    /// checks the first software flag, which marks a page shared copy-on-write
+    /// by [`PageTable::clone_cow`]
+    ///
+    /// [`PageTable::clone_cow`]: crate::mmu::page_table::PageTable::clone_cow
+    #[inline]
+    pub const fn is_copy_on_write(&self) -> bool {
+        self.read_softflags().0
+    }
+    #[inline]
+    pub const fn set_copy_on_write(&mut self, flag: bool) {
+        let (_, other) = self.read_softflags();
+        self.set_softflags((flag, other));
+    }
+    #[inline]
+    pub const fn with_copy_on_write(mut self, flag: bool) -> EntryFlags {
+        self.set_copy_on_write(flag);
+        self
+    }
 }
 
 impl Debug for EntryFlags {
@@ -1,19 +1,16 @@
-use crate::cpu::registers::satp::Satp;
+use crate::cpu::registers::satp::{self, Satp};
 
 use self::page_table::{PAGE_ADDR_MAGNITIDE, PAGE_SIZE};
 
 pub use entry::EntryFlags;
+pub use physical_address::PhysAddr;
+pub use virtual_address::VirtAddr;
 
 pub mod entry;
 pub mod page_table;
 pub mod physical_address;
 pub mod virtual_address;
 
-extern "C" {
-    fn asm_get_satp() -> usize;
-    fn asm_set_satp(_: usize);
-}
-
 /// The different types of page tables possible in Riscv
 /// for both 32 bit and 64bit systems
 #[derive(PartialEq, Eq, Copy, Clone)]
@@ -25,6 +22,7 @@ pub enum TableTypes {
     // 64-bit only
     Sv39 = 8,
     Sv48 = 9,
+    Sv57 = 10,
 }
 #[repr(align(4096))]
 pub struct Page(pub [u8; PAGE_SIZE]);
@@ -55,15 +53,22 @@ impl PageSize {
 /// sets the satp register to the given address.
 /// does not turn on address translation
 pub fn set_translation_table(mode: TableTypes, address: usize) -> bool {
-    let mode = mode as u8;
-    let desired = Satp::from(address, mode);
+    let desired = Satp::for_table(mode, address);
+    unsafe { satp::write(desired) };
+    satp::read() == desired
+}
 
-    let found = unsafe {
-        asm_set_satp(desired.raw());
-        asm_get_satp()
-    };
+/// Flushes this hart's cached translations for the page
+/// containing the given virtual address, in every address space.
+#[inline]
+pub fn flush_tlb_page(address: VirtAddr) {
+    unsafe { riscv_asm!("sfence.vma {}, zero", in(reg) address.as_usize()) };
+}
 
-    found == desired.raw()
+/// Flushes all of this hart's cached translations.
+#[inline]
+pub fn flush_tlb() {
+    unsafe { riscv_asm!("sfence.vma zero, zero") };
 }
 
 /// Produces a page-aligned address by adding one
@@ -1,14 +1,23 @@
 use core::cmp::Ordering;
 
-use self::{descriptor::BitGroup, forty_eight::Sv48, thirty_nine::Sv39, thirty_two::Sv32};
-use super::{align_power, entry::PTEntry, EntryFlags, PageSize};
+use self::{
+    descriptor::BitGroup,
+    forty_eight::Sv48,
+    thirty_nine::Sv39,
+    thirty_two::Sv32,
+    walk::{Leaves, Regions},
+};
+use super::{align_power, entry::PTEntry, EntryFlags, PageSize, PhysAddr, VirtAddr};
 
+pub mod cow;
 pub mod descriptor;
 pub mod display;
 pub mod forty_eight;
+pub mod protect;
 pub mod thirty_nine;
 pub mod thirty_two;
 // pub mod untyped;
+pub mod walk;
 
 /// page size per riscv Sv39 spec is 4096 bytes
 /// which needs 12 bits to address each byte inside
@@ -42,6 +51,18 @@ pub trait PageTableKind {
             .sum::<usize>()
             + 12
     }
+    /// number of bits in the physical address
+    fn physical_address_size(&self) -> usize {
+        self.physical_segments()
+            .iter()
+            .map(|(n, _)| n)
+            .sum::<usize>()
+            + 12
+    }
+    /// number of bytes mapped by a leaf entry at the given level
+    fn page_size_at(&self, level: usize) -> usize {
+        1 << self.virtual_segments()[level].1
+    }
 }
 
 pub enum AnyPageTable {
@@ -99,8 +120,8 @@ where
     /// todo: currently no safety checks in place
     pub fn identity_map(
         &self,
-        start: usize,
-        end: usize,
+        start: PhysAddr,
+        end: PhysAddr,
         flags: EntryFlags,
         zalloc: &mut dyn FnMut(usize) -> Option<*mut u8>,
     ) {
@@ -112,7 +133,85 @@ where
         //     end,
         //     self as *const _ as usize
         // );
-        internal_map_range(self, start, end, flags, zalloc)
+        internal_map_range(self, start.as_usize(), end.as_usize(), flags, zalloc)
+    }
+
+    /// Map length bytes of physical memory starting at phys, to the virtual
+    /// addresses starting at virt, with the given permissions. Nothing is
+    /// mapped when length is 0.
+    ///
+    /// Panics if the virtual address is not canonical or the physical address
+    /// cannot be stored in this kind of page table.
+    pub fn map(
+        &self,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        length: usize,
+        flags: EntryFlags,
+        zalloc: &mut dyn FnMut(usize) -> Option<*mut u8>,
+    ) {
+        assert!(
+            virt.is_canonical_in(&self.kind),
+            "attempted to map non-canonical virtual address {}",
+            virt
+        );
+        assert!(
+            phys.is_valid_in(&self.kind),
+            "attempted to map physical address {} out of range",
+            phys
+        );
+        if length == 0 {
+            return;
+        }
+        let start = virt.align_down(PAGE_SIZE);
+        let physical_start = phys.align_down(PAGE_SIZE);
+        let end = virt
+            .checked_add(length)
+            .and_then(|end| end.checked_align_up(PAGE_SIZE))
+            .expect("attempted to map past the end of the address space");
+        let page_count = (end.as_usize() - start.as_usize()) / PAGE_SIZE;
+        for i in 0..page_count {
+            let newpages = map_root(
+                self,
+                start.as_usize() + (i << 12),
+                physical_start.as_usize() + (i << 12),
+                flags,
+                PageSize::Page,
+                zalloc,
+            );
+            for page in newpages.iter() {
+                if *page != 0 {
+                    internal_map_range(self, *page, *page, EntryFlags::READ_WRITE, zalloc);
+                }
+            }
+        }
+    }
+
+    /// Find the physical address the virtual address is mapped to, if any.
+    pub fn translate(&self, virt: VirtAddr) -> Option<PhysAddr> {
+        if !virt.is_canonical_in(&self.kind) {
+            return None;
+        }
+        let page = virt.align_down(PAGE_SIZE);
+        let leaf = self.leaves(page, page.checked_add(1)?).next()?;
+        let offset = virt.checked_offset_from(leaf.virtual_address())?;
+        leaf.physical_address().checked_add(offset)
+    }
+
+    /// Iterate over the mapped leaf entries covering any part of the
+    /// virtual addresses from start up to (but not including) end.
+    ///
+    /// The yielded entries can be used to read and clear the accessed and dirty
+    /// bits, e.g. to estimate working sets or pick pages to reclaim.
+    pub fn leaves(&self, start: VirtAddr, end: VirtAddr) -> Leaves<'_, K> {
+        Leaves::new(self, start, end)
+    }
+
+    /// Iterate over the regions mapped between the virtual addresses start and end,
+    /// each region gathering the consecutive leaf entries which map contiguous
+    /// physical memory with the same page size and permissions.
+    pub fn regions(&self, start: VirtAddr, end: VirtAddr) -> Regions<'_, K> {
+        Regions::new(self.leaves(start, end))
     }
 }
 
@@ -316,3 +415,103 @@ fn extract_bits(address: usize, segment: &(usize, usize)) -> usize {
     let mask = (1 << bit_width) - 1;
     (address >> offset) & mask
 }
+
+/// Page tables in host memory, for the tests of this module and those below
+#[cfg(test)]
+mod test_tables {
+    use std::alloc::{alloc_zeroed, Layout};
+
+    use super::{map_root, thirty_nine::Sv39, PageTable, PAGE_SIZE};
+    use crate::mmu::{EntryFlags, PageSize};
+
+    /// zalloc for tests, leaking zeroed pages from the host's heap
+    pub fn zalloc(pages: usize) -> Option<*mut u8> {
+        let layout = Layout::from_size_align(pages * PAGE_SIZE, PAGE_SIZE).unwrap();
+        let memory = unsafe { alloc_zeroed(layout) };
+        (!memory.is_null()).then_some(memory)
+    }
+
+    /// Zeroed memory aligned to its size, which must be a power of two,
+    /// to stand in for the frames mapped by a page or superpage
+    pub fn memory(size: usize) -> *mut u8 {
+        let layout = Layout::from_size_align(size, size).unwrap();
+        let memory = unsafe { alloc_zeroed(layout) };
+        assert!(!memory.is_null());
+        memory
+    }
+
+    pub fn empty() -> PageTable<Sv39> {
+        PageTable::in_place(zalloc(1).unwrap(), Sv39)
+    }
+
+    /// Maps a single page or superpage, without identity mapping the
+    /// tables allocated for it as [`PageTable::map`] does
+    pub fn map_page(
+        table: &PageTable<Sv39>,
+        virt: usize,
+        phys: usize,
+        flags: EntryFlags,
+        size: PageSize,
+    ) {
+        map_root(table, virt, phys, flags, size, &mut zalloc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_tables::{empty, map_page, zalloc};
+    use crate::mmu::{EntryFlags, PageSize, PhysAddr, VirtAddr};
+
+    const PHYS: usize = 0x8000_0000;
+
+    #[test]
+    fn map_nothing() {
+        let table = empty();
+        let start = VirtAddr::new(0x1000);
+        table.map(start, PhysAddr::new(PHYS), 0, EntryFlags::READ, &mut zalloc);
+        assert_eq!(table.translate(start), None);
+        assert_eq!(
+            table
+                .leaves(VirtAddr::new(0), VirtAddr::new(usize::MAX))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn map_covers_partial_pages() {
+        let table = empty();
+        let start = VirtAddr::new(0x1800);
+        table.map(
+            start,
+            PhysAddr::new(PHYS + 0x800),
+            0x1000,
+            EntryFlags::READ,
+            &mut zalloc,
+        );
+        for (virt, phys) in [
+            (0x1000, PHYS),
+            (0x1800, PHYS + 0x800),
+            (0x27ff, PHYS + 0x17ff),
+        ] {
+            assert_eq!(
+                table.translate(VirtAddr::new(virt)),
+                Some(PhysAddr::new(phys))
+            );
+        }
+        assert_eq!(table.translate(VirtAddr::new(0x3000)), None);
+    }
+
+    #[test]
+    fn translate_the_last_address() {
+        let table = empty();
+        let top = usize::MAX - 0xfff;
+        map_page(&table, top, PHYS, EntryFlags::READ, PageSize::Page);
+        assert_eq!(
+            table.translate(VirtAddr::new(usize::MAX)),
+            Some(PhysAddr::new(PHYS + 0xfff))
+        );
+        // not canonical in Sv39
+        assert_eq!(table.translate(VirtAddr::new(1 << 40)), None);
+    }
+}
@@ -0,0 +1,343 @@
+//! Copy-on-write duplication of address spaces.
+
+use crate::mmu::{entry::PTEntry, flush_tlb, flush_tlb_page, EntryFlags, VirtAddr};
+
+use super::{protect::split_to, PageTable, PageTableKind, PAGE_SIZE};
+
+impl<K> PageTable<K>
+where
+    K: PageTableKind + core::fmt::Debug + Copy,
+{
+    /// Produce a new page table mapping the same memory as this one, for a cheap fork.
+    ///
+    /// Leaf frames are shared rather than copied: every writable leaf loses its
+    /// W bit in both tables and is marked with [`EntryFlags::set_copy_on_write`],
+    /// so the first write from either side faults and can be handed to
+    /// [`PageTable::resolve_cow_fault`]. Global mappings are the same in every
+    /// address space and are shared as they are. Only the branch tables are
+    /// allocated, all at once with zalloc before this table is changed, and
+    /// they are expected to be reachable at their physical address. Returns
+    /// None, leaving this table as it was, if there is no memory for them.
+    ///
+    /// This hart's translation cache is flushed since the existing mappings lost
+    /// write access, other harts using this table must flush their own.
+    pub fn clone_cow(
+        &self,
+        zalloc: &mut dyn FnMut(usize) -> Option<*mut u8>,
+    ) -> Option<PageTable<K>> {
+        let level = self.kind.depth() - 1;
+        let count = count_tables(self, level);
+        let pages = zalloc(count)?;
+        let mut next_page = pages;
+        let end = pages.wrapping_add(count * PAGE_SIZE);
+        let table = clone_table(self, level, &mut next_page, end);
+        flush_tlb();
+        Some(PageTable::in_place(table, self.kind))
+    }
+
+    /// Complete a write to a copy-on-write page by giving this address space
+    /// its own copy of the frame, returning false if the address is not a
+    /// copy-on-write page and the fault must be handled some other way, or if
+    /// there was no memory for the copy.
+    ///
+    /// Shared superpages are first split so only the faulting page is copied.
+    /// The frame is always copied, as frames do not yet carry a count of how
+    /// many address spaces share them.
+    pub fn resolve_cow_fault(
+        &self,
+        address: VirtAddr,
+        zalloc: &mut dyn FnMut(usize) -> Option<*mut u8>,
+    ) -> bool {
+        let page = address.align_down(PAGE_SIZE);
+        let Some(end) = page.checked_add(1) else {
+            return false;
+        };
+        match self.leaves(page, end).next() {
+            Some(leaf) if leaf.flags().is_copy_on_write() => (),
+            _ => return false,
+        }
+        // split on both sides, so the page gets a leaf of its own
+        // even when it is the first or last page of a superpage
+        if !split_to(self, page, zalloc) {
+            return false;
+        }
+        if let Some(next) = page.checked_add(PAGE_SIZE) {
+            if !split_to(self, next, zalloc) {
+                return false;
+            }
+        }
+        let Some(leaf) = self.leaves(page, end).next() else {
+            return false;
+        };
+        assert_eq!(
+            leaf.size(),
+            PAGE_SIZE,
+            "copy-on-write page at {} was not split",
+            page
+        );
+        let entry = leaf.entry;
+        let old_value = entry.load();
+        let flags = EntryFlags::from_u16((old_value & ((1 << 10) - 1)) as u16);
+        if !flags.is_copy_on_write() {
+            // another hart resolved this fault first
+            return true;
+        }
+        let source = entry.read_address() as *const u8;
+        let Some(frame) = zalloc(1) else {
+            return false;
+        };
+        unsafe { core::ptr::copy_nonoverlapping(source, frame, PAGE_SIZE) };
+        let flags = flags.with_writable(true).with_copy_on_write(false);
+        if !entry.write(old_value, frame as u64, flags) {
+            panic!("failed to write to page table: concurrent access?");
+        }
+        flush_tlb_page(page);
+        true
+    }
+}
+
+/// The number of tables [`clone_table`] needs to copy the table at the
+/// given level, counting itself and every table below it.
+fn count_tables<K>(table: &PageTable<K>, level: usize) -> usize
+where
+    K: PageTableKind + core::fmt::Debug + Copy,
+{
+    let mut count = 1;
+    if level == 0 {
+        return count;
+    }
+    for index in 0..table.kind.size() / table.kind.entry_size() {
+        let entry = table.entry(index);
+        if entry.read_flags().is_valid() && entry.read_flags().is_branch() {
+            let next_table = PageTable::in_place(entry.read_address() as *const u8, table.kind);
+            count += count_tables(&next_table, level - 1);
+        }
+    }
+    count
+}
+
+/// Copy the table at the given level, and every table below it, into the
+/// zeroed pages from next_page up to end, turning writable leaves in both
+/// copies into copy-on-write leaves.
+fn clone_table<K>(
+    table: &PageTable<K>,
+    level: usize,
+    next_page: &mut *mut u8,
+    end: *mut u8,
+) -> *mut u8
+where
+    K: PageTableKind + core::fmt::Debug + Copy,
+{
+    if *next_page == end {
+        panic!("page table grew while it was cloned: concurrent access?");
+    }
+    let new_page = *next_page;
+    *next_page = new_page.wrapping_add(PAGE_SIZE);
+    let new_table = PageTable::in_place(new_page, table.kind);
+    for index in 0..table.kind.size() / table.kind.entry_size() {
+        let entry = table.entry(index);
+        let mut old_value = entry.load();
+        let mut flags = entry.read_flags();
+        if !flags.is_valid() {
+            continue;
+        }
+        let address = if flags.is_branch() && level > 0 {
+            let next_table = PageTable::in_place(entry.read_address() as *const u8, table.kind);
+            clone_table(&next_table, level - 1, next_page, end) as u64
+        } else {
+            let address = entry.read_address();
+            while flags.is_writable() && !flags.is_global() {
+                let shared = flags.with_writable(false).with_copy_on_write(true);
+                if entry.write(old_value, address, shared) {
+                    flags = shared;
+                } else {
+                    old_value = entry.load();
+                    flags = entry.read_flags();
+                }
+            }
+            address
+        };
+        if !new_table.entry(index).write(0, address, flags) {
+            panic!("failed to write to page table: concurrent access?");
+        }
+    }
+    new_page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        test_tables::{empty, map_page, memory, zalloc},
+        thirty_nine::Sv39,
+    };
+    use super::{PageTable, PAGE_SIZE};
+    use crate::mmu::{EntryFlags, PageSize, VirtAddr};
+
+    const MEGAPAGE: usize = 1 << 21;
+
+    /// flags, physical address and size of the leaf mapping the address
+    fn leaf_at(table: &PageTable<Sv39>, address: usize) -> (EntryFlags, usize, usize) {
+        let start = VirtAddr::new(address);
+        let leaf = table
+            .leaves(start, start.checked_add(1).unwrap())
+            .next()
+            .expect("address is not mapped");
+        (
+            leaf.flags(),
+            leaf.physical_address().as_usize(),
+            leaf.size(),
+        )
+    }
+
+    #[test]
+    fn clone_shares_frames_copy_on_write() {
+        let table = empty();
+        let writable = memory(PAGE_SIZE) as usize;
+        let read_only = memory(PAGE_SIZE) as usize;
+        let global = memory(PAGE_SIZE) as usize;
+        let global_flags = EntryFlags::READ_WRITE.with_global(true);
+        map_page(
+            &table,
+            0x1000,
+            writable,
+            EntryFlags::READ_WRITE,
+            PageSize::Page,
+        );
+        map_page(&table, 0x2000, read_only, EntryFlags::READ, PageSize::Page);
+        map_page(&table, 0x4000_0000, global, global_flags, PageSize::Page);
+
+        let clone = table.clone_cow(&mut zalloc).unwrap();
+        assert_ne!(clone.table, table.table);
+        for table in [&table, &clone] {
+            let (flags, address, _) = leaf_at(table, 0x1000);
+            assert_eq!(address, writable);
+            assert!(!flags.is_writable() && flags.is_copy_on_write());
+            let (flags, address, _) = leaf_at(table, 0x2000);
+            assert_eq!(address, read_only);
+            assert_eq!(flags.as_u16(), EntryFlags::READ.as_u16());
+            let (flags, address, _) = leaf_at(table, 0x4000_0000);
+            assert_eq!(address, global);
+            assert_eq!(flags.as_u16(), global_flags.as_u16());
+        }
+    }
+
+    #[test]
+    fn clone_without_memory_leaves_the_table() {
+        let table = empty();
+        map_page(
+            &table,
+            0x1000,
+            memory(PAGE_SIZE) as usize,
+            EntryFlags::READ_WRITE,
+            PageSize::Page,
+        );
+        map_page(
+            &table,
+            0x4000_0000,
+            memory(PAGE_SIZE) as usize,
+            EntryFlags::READ_WRITE,
+            PageSize::Page,
+        );
+        // a root, plus two tables under it for each of the two pages
+        let mut requested = None;
+        let clone = table.clone_cow(&mut |pages| {
+            requested = Some(pages);
+            None
+        });
+        assert!(clone.is_none());
+        assert_eq!(requested, Some(5));
+        for address in [0x1000, 0x4000_0000] {
+            let (flags, _, _) = leaf_at(&table, address);
+            assert!(flags.is_writable() && !flags.is_copy_on_write());
+        }
+    }
+
+    #[test]
+    fn fault_copies_the_page() {
+        let table = empty();
+        let frame = memory(PAGE_SIZE);
+        unsafe { frame.write_bytes(0x5a, PAGE_SIZE) };
+        map_page(
+            &table,
+            0x1000,
+            frame as usize,
+            EntryFlags::READ_WRITE,
+            PageSize::Page,
+        );
+        let clone = table.clone_cow(&mut zalloc).unwrap();
+
+        assert!(clone.resolve_cow_fault(VirtAddr::new(0x1234), &mut zalloc));
+        let (flags, copy, size) = leaf_at(&clone, 0x1000);
+        assert_eq!(size, PAGE_SIZE);
+        assert_ne!(copy, frame as usize);
+        assert!(flags.is_writable() && !flags.is_copy_on_write());
+        let copy = unsafe { core::slice::from_raw_parts(copy as *const u8, PAGE_SIZE) };
+        assert!(copy.iter().all(|byte| *byte == 0x5a));
+        // the original still shares the frame, until it faults too
+        let (flags, address, _) = leaf_at(&table, 0x1000);
+        assert_eq!(address, frame as usize);
+        assert!(flags.is_copy_on_write());
+    }
+
+    #[test]
+    fn fault_elsewhere_is_not_resolved() {
+        let table = empty();
+        map_page(
+            &table,
+            0x1000,
+            memory(PAGE_SIZE) as usize,
+            EntryFlags::READ_WRITE,
+            PageSize::Page,
+        );
+        assert!(!table.resolve_cow_fault(VirtAddr::new(0x1000), &mut zalloc));
+        assert!(!table.resolve_cow_fault(VirtAddr::new(0x2000), &mut zalloc));
+    }
+
+    #[test]
+    fn fault_without_memory_is_not_resolved() {
+        let table = empty();
+        map_page(
+            &table,
+            0x1000,
+            memory(PAGE_SIZE) as usize,
+            EntryFlags::READ_WRITE,
+            PageSize::Page,
+        );
+        let clone = table.clone_cow(&mut zalloc).unwrap();
+        assert!(!clone.resolve_cow_fault(VirtAddr::new(0x1000), &mut |_| None));
+        assert!(leaf_at(&clone, 0x1000).0.is_copy_on_write());
+    }
+
+    /// faults on the first, a middle and the last page of a shared megapage
+    #[test]
+    fn fault_splits_superpages() {
+        for page in [0, 0x2000, MEGAPAGE - PAGE_SIZE] {
+            let table = empty();
+            let frames = memory(MEGAPAGE) as usize;
+            map_page(
+                &table,
+                MEGAPAGE,
+                frames,
+                EntryFlags::READ_WRITE,
+                PageSize::Megapage,
+            );
+            let clone = table.clone_cow(&mut zalloc).unwrap();
+
+            assert!(clone.resolve_cow_fault(VirtAddr::new(MEGAPAGE + page), &mut zalloc));
+            let (flags, copy, size) = leaf_at(&clone, MEGAPAGE + page);
+            assert_eq!(size, PAGE_SIZE);
+            assert_ne!(copy, frames + page);
+            assert!(flags.is_writable() && !flags.is_copy_on_write());
+            // the rest of the megapage is still shared, as 4K pages
+            for other in [0, page + PAGE_SIZE, MEGAPAGE - PAGE_SIZE] {
+                if other == page || other >= MEGAPAGE {
+                    continue;
+                }
+                let (flags, address, size) = leaf_at(&clone, MEGAPAGE + other);
+                assert_eq!((address, size), (frames + other, PAGE_SIZE));
+                assert!(flags.is_copy_on_write());
+            }
+            assert_eq!(leaf_at(&table, MEGAPAGE + page).2, MEGAPAGE);
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Changing the permissions of existing mappings, or removing them.
+
+use crate::mmu::{entry::PTEntry, flush_tlb, flush_tlb_page, EntryFlags, VirtAddr};
+
+use super::{extract_bits, PageTable, PageTableKind, PAGE_SIZE};
+
+/// above this many pages, the whole translation cache is flushed rather than each page
+const FLUSH_ALL_THRESHOLD: usize = 64;
+
+impl<K> PageTable<K>
+where
+    K: PageTableKind + core::fmt::Debug + Copy,
+{
+    /// Change the permissions of the pages mapped in the virtual address
+    /// range from start to start + length, similar to mprotect.
+    ///
+    /// The R, W, X, U and G bits are taken from flags, while the accessed,
+    /// dirty and software bits of each entry are left alone. Pages shared
+    /// copy-on-write stay read-only, so that a write still faults and copies
+    /// them rather than changing the frame of every table sharing it. Superpages which
+    /// are only partly covered by the range are split into smaller pages first,
+    /// allocating new page tables with zalloc. Addresses in the range which are
+    /// not mapped are skipped. The translation cache of this hart is flushed
+    /// for the affected addresses before returning.
+    pub fn protect(
+        &self,
+        start: VirtAddr,
+        length: usize,
+        flags: EntryFlags,
+        zalloc: &mut dyn FnMut(usize) -> Option<*mut u8>,
+    ) {
+        assert!(
+            !flags.is_branch() && !flags.is_invalid(),
+            "attempted to protect pages with non-leaf permissions {:?}",
+            flags
+        );
+        if length == 0 {
+            return;
+        }
+        let end = start
+            .checked_add(length)
+            .and_then(|end| end.checked_align_up(PAGE_SIZE))
+            .expect("attempted to protect past the end of the address space");
+        let start = start.align_down(PAGE_SIZE);
+        if !(split_to(self, start, zalloc) && split_to(self, end, zalloc)) {
+            panic!("out of memory for the page tables of split superpages");
+        }
+
+        let mut changed = 0;
+        for leaf in self.leaves(start, end) {
+            let entry = leaf.entry;
+            loop {
+                let old_value = entry.load();
+                let old = EntryFlags::from_u16((old_value & ((1 << 10) - 1)) as u16);
+                let new = flags
+                    .with_valid(true)
+                    .with_writable(flags.is_writable() && !old.is_copy_on_write())
+                    .with_accessed(old.is_accessed())
+                    .with_dirty(old.is_dirty())
+                    .with_softflags(old.read_softflags());
+                if entry.write(old_value, entry.read_address(), new) {
+                    break;
+                }
+            }
+            changed += leaf.size() >> 12;
+            if changed <= FLUSH_ALL_THRESHOLD {
+                flush_tlb_page(leaf.virtual_address());
+            }
+        }
+        if changed > FLUSH_ALL_THRESHOLD {
+            flush_tlb();
+        }
+    }
+
+    /// Remove the mappings of the pages in the virtual address range from
+    /// start to start + length, so that any access to them faults.
+    ///
+    /// Superpages which are only partly covered by the range are split first,
+    /// as in [`PageTable::protect`]. The frames which were mapped are not freed,
+    /// and page tables left empty are kept. Addresses in the range which are
+    /// not mapped are skipped. The translation cache of this hart is flushed
+    /// for the affected addresses before returning.
+    pub fn unmap(
+        &self,
+        start: VirtAddr,
+        length: usize,
+        zalloc: &mut dyn FnMut(usize) -> Option<*mut u8>,
+    ) {
+        if length == 0 {
+            return;
+        }
+        let end = start
+            .checked_add(length)
+            .and_then(|end| end.checked_align_up(PAGE_SIZE))
+            .expect("attempted to unmap past the end of the address space");
+        let start = start.align_down(PAGE_SIZE);
+        if !(split_to(self, start, zalloc) && split_to(self, end, zalloc)) {
+            panic!("out of memory for the page tables of split superpages");
+        }
+
+        let mut changed = 0;
+        for leaf in self.leaves(start, end) {
+            let entry = leaf.entry;
+            while !entry.invalidate(entry.load()) {}
+            changed += leaf.size() >> 12;
+            if changed <= FLUSH_ALL_THRESHOLD {
+                flush_tlb_page(leaf.virtual_address());
+            }
+        }
+        if changed > FLUSH_ALL_THRESHOLD {
+            flush_tlb();
+        }
+    }
+}
+
+/// Split any superpage which maps the given address but does not start at it,
+/// so that a leaf entry begins exactly at the address. Returns false if a new
+/// table could not be allocated, leaving the superpage as it was.
+pub(super) fn split_to<K>(
+    root: &PageTable<K>,
+    address: VirtAddr,
+    zalloc: &mut dyn FnMut(usize) -> Option<*mut u8>,
+) -> bool
+where
+    K: PageTableKind + core::fmt::Debug + Copy,
+{
+    let address = address.as_usize();
+    let mut table = root.table;
+    let mut level = root.kind.depth() - 1;
+    loop {
+        let vpn = extract_bits(address, &root.kind.virtual_segments()[level]);
+        let current = PageTable {
+            table,
+            kind: root.kind,
+        };
+        let entry = current.entry(vpn);
+        let old_value = entry.load();
+        let flags = entry.read_flags();
+        if !flags.is_valid() {
+            return true;
+        }
+        if flags.is_branch() {
+            if level == 0 {
+                return true;
+            }
+            table = entry.read_address() as *const K::Entry;
+            level -= 1;
+            continue;
+        }
+        let size = root.kind.page_size_at(level);
+        if level == 0 || address & (size - 1) == 0 {
+            return true;
+        }
+        // fill out a new table describing the same memory with smaller pages
+        let Some(new_page) = zalloc(1) else {
+            return false;
+        };
+        let next_table = PageTable::in_place(new_page, root.kind);
+        let base = entry.read_address();
+        let child_size = root.kind.page_size_at(level - 1) as u64;
+        for index in 0..root.kind.size() / root.kind.entry_size() {
+            let child_address = base + index as u64 * child_size;
+            if !next_table.entry(index).write(0, child_address, flags) {
+                panic!("failed to write to page table: concurrent access?");
+            }
+        }
+        if !entry.write(
+            old_value,
+            new_page as u64,
+            EntryFlags::new().with_valid(true),
+        ) {
+            panic!("failed to write to page table: concurrent access?");
+        }
+        flush_tlb_page(VirtAddr::new(address & !(size - 1)));
+        table = next_table.table;
+        level -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_tables::{empty, map_page, memory, zalloc};
+    use super::split_to;
+    use crate::mmu::{EntryFlags, PageSize, PhysAddr, VirtAddr, PAGE_SIZE};
+
+    const MEGAPAGE: usize = 0x20_0000;
+
+    /// flags, physical address and size of the leaf mapping an address
+    fn leaf_at(
+        table: &super::PageTable<super::super::thirty_nine::Sv39>,
+        address: usize,
+    ) -> Option<(EntryFlags, usize, usize)> {
+        table
+            .leaves(VirtAddr::new(address), VirtAddr::new(address + 1))
+            .next()
+            .map(|leaf| {
+                (
+                    leaf.flags(),
+                    leaf.physical_address().as_usize(),
+                    leaf.size(),
+                )
+            })
+    }
+
+    #[test]
+    fn protect_partial_range() {
+        let table = empty();
+        let frames = memory(4 * PAGE_SIZE) as usize;
+        for page in 0..4 {
+            map_page(
+                &table,
+                0x1000 + page * PAGE_SIZE,
+                frames + page * PAGE_SIZE,
+                EntryFlags::READ_WRITE.with_accessed(true),
+                PageSize::Page,
+            );
+        }
+        // from the middle of the second page to the middle of the third
+        table.protect(
+            VirtAddr::new(0x2800),
+            PAGE_SIZE,
+            EntryFlags::READ,
+            &mut zalloc,
+        );
+        for (page, writable) in [(0, true), (1, false), (2, false), (3, true)] {
+            let (flags, address, _) = leaf_at(&table, 0x1000 + page * PAGE_SIZE).unwrap();
+            assert_eq!(address, frames + page * PAGE_SIZE);
+            assert_eq!(flags.is_writable(), writable);
+            assert!(flags.is_readable() && flags.is_accessed());
+        }
+    }
+
+    #[test]
+    fn protect_splits_superpages() {
+        let table = empty();
+        let frames = memory(MEGAPAGE) as usize;
+        map_page(
+            &table,
+            MEGAPAGE,
+            frames,
+            EntryFlags::READ_WRITE,
+            PageSize::Megapage,
+        );
+        table.protect(
+            VirtAddr::new(MEGAPAGE + 0x3000),
+            2 * PAGE_SIZE,
+            EntryFlags::READ,
+            &mut zalloc,
+        );
+        for (offset, writable) in [
+            (0, true),
+            (0x2000, true),
+            (0x3000, false),
+            (0x4000, false),
+            (0x5000, true),
+            (MEGAPAGE - PAGE_SIZE, true),
+        ] {
+            let (flags, address, size) = leaf_at(&table, MEGAPAGE + offset).unwrap();
+            assert_eq!((address, size), (frames + offset, PAGE_SIZE));
+            assert_eq!(flags.is_writable(), writable);
+        }
+    }
+
+    #[test]
+    fn protect_keeps_copy_on_write_read_only() {
+        let table = empty();
+        map_page(
+            &table,
+            0x1000,
+            memory(PAGE_SIZE) as usize,
+            EntryFlags::READ_WRITE,
+            PageSize::Page,
+        );
+        let clone = table.clone_cow(&mut zalloc).unwrap();
+        clone.protect(
+            VirtAddr::new(0x1000),
+            PAGE_SIZE,
+            EntryFlags::USER_READ_WRITE,
+            &mut zalloc,
+        );
+        let (flags, _, _) = leaf_at(&clone, 0x1000).unwrap();
+        assert!(flags.is_user() && flags.is_readable());
+        assert!(!flags.is_writable() && flags.is_copy_on_write());
+    }
+
+    #[test]
+    fn unmap_then_translate() {
+        let table = empty();
+        let frames = memory(MEGAPAGE) as usize;
+        map_page(
+            &table,
+            MEGAPAGE,
+            frames,
+            EntryFlags::READ_WRITE,
+            PageSize::Megapage,
+        );
+        table.unmap(VirtAddr::new(MEGAPAGE + 0x1000), 0x1800, &mut zalloc);
+        let translate = |offset: usize| {
+            table
+                .translate(VirtAddr::new(MEGAPAGE + offset))
+                .map(PhysAddr::as_usize)
+        };
+        assert_eq!(translate(0x0fff), Some(frames + 0x0fff));
+        assert_eq!(translate(0x1000), None);
+        assert_eq!(translate(0x2fff), None);
+        assert_eq!(translate(0x3000), Some(frames + 0x3000));
+        assert_eq!(translate(MEGAPAGE - 1), Some(frames + MEGAPAGE - 1));
+    }
+
+    #[test]
+    fn split_to_only_splits_when_needed() {
+        let table = empty();
+        let frames = memory(MEGAPAGE) as usize;
+        map_page(
+            &table,
+            MEGAPAGE,
+            frames,
+            EntryFlags::READ_WRITE,
+            PageSize::Megapage,
+        );
+        // on the boundary, or not mapped at all, nothing is split
+        assert!(split_to(&table, VirtAddr::new(MEGAPAGE), &mut |_| None));
+        assert!(split_to(&table, VirtAddr::new(0x1000), &mut |_| None));
+        assert_eq!(leaf_at(&table, MEGAPAGE).unwrap().2, MEGAPAGE);
+        // without memory the superpage is left as it was
+        assert!(!split_to(
+            &table,
+            VirtAddr::new(MEGAPAGE + 0x1000),
+            &mut |_| None
+        ));
+        assert_eq!(leaf_at(&table, MEGAPAGE).unwrap().2, MEGAPAGE);
+
+        assert!(split_to(
+            &table,
+            VirtAddr::new(MEGAPAGE + 0x1000),
+            &mut zalloc
+        ));
+        let leaves: std::vec::Vec<_> = table
+            .leaves(VirtAddr::new(MEGAPAGE), VirtAddr::new(2 * MEGAPAGE))
+            .map(|leaf| {
+                (
+                    leaf.physical_address().as_usize(),
+                    leaf.size(),
+                    leaf.flags().as_u16(),
+                )
+            })
+            .collect();
+        assert_eq!(leaves.len(), MEGAPAGE / PAGE_SIZE);
+        for (index, (address, size, flags)) in leaves.into_iter().enumerate() {
+            assert_eq!((address, size), (frames + index * PAGE_SIZE, PAGE_SIZE));
+            assert_eq!(flags, EntryFlags::READ_WRITE.with_valid(true).as_u16());
+        }
+    }
+}
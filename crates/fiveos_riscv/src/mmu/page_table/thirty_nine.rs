@@ -87,10 +87,10 @@ impl PTEntry for Entry {
         let mut address = 0;
         for level in 0..LEVELS {
             let (bit_width, offset) = PPN_SEGMENTS[level];
-            let mask = ((1 << bit_width) - 1) << offset;
-            address = (entry & mask) >> offset;
+            let (_, address_offset) = PA_SEGMENTS[level];
+            let mask = (1 << bit_width) - 1;
+            address |= ((entry >> offset) & mask) << address_offset;
         }
-        let address = address << 12;
         // let mut uart = unsafe {Uart0::new()};
         // println!(uart, "extracted address {:x}", address);
         address
@@ -109,12 +109,12 @@ impl PTEntry for Entry {
     }
 
     fn write(&self, old_value: u64, address: u64, flags: EntryFlags) -> bool {
-        let address = address >> 12;
         let mut bits = 0;
         for level in 0..LEVELS {
             let (bit_width, offset) = PPN_SEGMENTS[level];
-            let mask = ((1 << bit_width) - 1) << offset;
-            bits = (address << offset) & mask;
+            let (_, address_offset) = PA_SEGMENTS[level];
+            let mask = (1 << bit_width) - 1;
+            bits |= ((address >> address_offset) & mask) << offset;
         }
         let new_value = flags.as_u16() as u64 | bits as u64;
 
@@ -124,6 +124,13 @@ impl PTEntry for Entry {
     }
 
     fn invalidate(&self, old_value: u64) -> bool {
-        todo!()
+        self.0
+            .compare_exchange(
+                old_value,
+                old_value & !1,
+                Ordering::Release,
+                Ordering::Relaxed,
+            )
+            .is_ok()
     }
 }
@@ -0,0 +1,558 @@
+//! Traversal of the leaf entries of a page table, used to inspect
+//! and harvest the accessed/dirty state of existing mappings.
+
+use core::marker::PhantomData;
+
+use crate::mmu::{entry::PTEntry, EntryFlags, PhysAddr, VirtAddr};
+
+use super::{extract_bits, PageTable, PageTableKind};
+
+/// the deepest page table supported by the walker
+const MAX_DEPTH: usize = 4;
+
+/// Iterator over the valid leaf entries of a page table
+/// whose pages overlap a range of virtual addresses.
+///
+/// Created by [`PageTable::leaves`].
+pub struct Leaves<'a, K>
+where
+    K: PageTableKind,
+{
+    kind: K,
+    /// table being read at each level
+    tables: [*const K::Entry; MAX_DEPTH],
+    /// index of the next entry to read at each level
+    indices: [usize; MAX_DEPTH],
+    /// level currently being read
+    level: usize,
+    /// first virtual address of interest, without sign extension
+    start: usize,
+    /// one past the last virtual address of interest, without sign extension
+    end: usize,
+    done: bool,
+    _root: PhantomData<&'a PageTable<K>>,
+}
+
+impl<'a, K> Leaves<'a, K>
+where
+    K: PageTableKind + core::fmt::Debug + Copy,
+{
+    pub(super) fn new(root: &'a PageTable<K>, start: VirtAddr, end: VirtAddr) -> Leaves<'a, K> {
+        let kind = root.kind;
+        let depth = kind.depth();
+        assert!(depth <= MAX_DEPTH, "page table too deep to walk");
+        let bits = kind.virtual_address_size();
+        let done = end <= start;
+        let start = truncate(start.as_usize(), bits);
+        let end = if done {
+            start
+        } else {
+            truncate(end.as_usize() - 1, bits) + 1
+        };
+        let level = depth - 1;
+        let mut tables = [core::ptr::null(); MAX_DEPTH];
+        let mut indices = [0; MAX_DEPTH];
+        tables[level] = root.table;
+        indices[level] = extract_bits(start, &kind.virtual_segments()[level]);
+        Leaves {
+            kind,
+            tables,
+            indices,
+            level,
+            start,
+            end,
+            done,
+            _root: PhantomData,
+        }
+    }
+
+    /// the virtual address of the entry at the current position, without sign extension
+    fn current_address(&self) -> usize {
+        let segments = self.kind.virtual_segments();
+        (self.level..self.kind.depth())
+            .map(|level| self.indices[level] << segments[level].1)
+            .sum()
+    }
+}
+
+impl<'a, K> Iterator for Leaves<'a, K>
+where
+    K: PageTableKind + core::fmt::Debug + Copy,
+{
+    type Item = Leaf<'a, K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry_count = self.kind.size() / self.kind.entry_size();
+        while !self.done {
+            let level = self.level;
+            if self.indices[level] >= entry_count {
+                // finished this table, continue with the parent's next entry
+                if level == self.kind.depth() - 1 {
+                    self.done = true;
+                } else {
+                    self.level += 1;
+                    self.indices[self.level] += 1;
+                }
+                continue;
+            }
+            let address = self.current_address();
+            if address >= self.end {
+                self.done = true;
+                continue;
+            }
+            let size = self.kind.page_size_at(level);
+            // safety: index is within the table, and the table was read out
+            // of a valid branch entry which points at an entire page table
+            let entry = unsafe { &*self.tables[level].add(self.indices[level]) };
+            let flags = entry.read_flags();
+            if !flags.is_valid() || address + size <= self.start {
+                self.indices[level] += 1;
+                continue;
+            }
+            if flags.is_branch() {
+                if level == 0 {
+                    // malformed table, branches are not allowed at the last level
+                    self.indices[level] += 1;
+                    continue;
+                }
+                let child = level - 1;
+                self.tables[child] = entry.read_address() as *const K::Entry;
+                self.indices[child] = if address < self.start {
+                    extract_bits(self.start, &self.kind.virtual_segments()[child])
+                } else {
+                    0
+                };
+                self.level = child;
+                continue;
+            }
+            self.indices[level] += 1;
+            return Some(Leaf {
+                entry,
+                virtual_address: VirtAddr::new(sign_extend(
+                    address,
+                    self.kind.virtual_address_size(),
+                )),
+                level,
+                size,
+                _kind: PhantomData,
+            });
+        }
+        None
+    }
+}
+
+/// A valid leaf entry found while walking a page table.
+pub struct Leaf<'a, K>
+where
+    K: PageTableKind,
+{
+    pub(super) entry: &'a K::Entry,
+    virtual_address: VirtAddr,
+    level: usize,
+    size: usize,
+    _kind: PhantomData<K>,
+}
+
+impl<'a, K> Leaf<'a, K>
+where
+    K: PageTableKind,
+{
+    /// the first virtual address mapped by this entry
+    pub fn virtual_address(&self) -> VirtAddr {
+        self.virtual_address
+    }
+    /// the first physical address mapped by this entry
+    pub fn physical_address(&self) -> PhysAddr {
+        PhysAddr::new(self.entry.read_address() as usize)
+    }
+    /// the last virtual address mapped by this entry
+    pub fn last_virtual_address(&self) -> VirtAddr {
+        VirtAddr::new(self.virtual_address.as_usize() + (self.size - 1))
+    }
+    /// the number of bytes mapped by this entry
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    /// the page table level this entry was found at, 0 being a regular 4096 byte page
+    pub fn level(&self) -> usize {
+        self.level
+    }
+    pub fn flags(&self) -> EntryFlags {
+        self.entry.read_flags()
+    }
+    pub fn is_accessed(&self) -> bool {
+        self.flags().is_accessed()
+    }
+    pub fn is_dirty(&self) -> bool {
+        self.flags().is_dirty()
+    }
+    /// Clears the accessed bit, returning its previous value.
+    pub fn clear_accessed(&self) -> bool {
+        self.clear(true, false).0
+    }
+    /// Clears the dirty bit, returning its previous value.
+    pub fn clear_dirty(&self) -> bool {
+        self.clear(false, true).1
+    }
+    /// Clears both the accessed and dirty bits, returning their previous values.
+    pub fn clear_accessed_dirty(&self) -> (bool, bool) {
+        self.clear(true, true)
+    }
+
+    /// Atomically clear the requested bits, retrying if the entry changed
+    /// underneath us (e.g. the hardware set the accessed bit while we worked).
+    ///
+    /// Note that the processor may have the old entry cached, the caller is
+    /// responsible for issuing an `sfence.vma` for this address once done.
+    fn clear(&self, accessed: bool, dirty: bool) -> (bool, bool) {
+        loop {
+            let old_value = self.entry.load();
+            let mut flags = EntryFlags::from_u16((old_value & ((1 << 10) - 1)) as u16);
+            let previous = (flags.is_accessed(), flags.is_dirty());
+            if (!accessed || !previous.0) && (!dirty || !previous.1) {
+                return previous;
+            }
+            if accessed {
+                flags.set_accessed(false);
+            }
+            if dirty {
+                flags.set_dirty(false);
+            }
+            let address = self.entry.read_address();
+            if self.entry.write(old_value, address, flags) {
+                return previous;
+            }
+        }
+    }
+}
+
+/// drops the bits above the virtual address size
+fn truncate(address: usize, bits: usize) -> usize {
+    if bits >= usize::BITS as usize {
+        address
+    } else {
+        address & ((1 << bits) - 1)
+    }
+}
+
+/// copies the top bit of the virtual address into the unused high bits
+fn sign_extend(address: usize, bits: usize) -> usize {
+    if bits >= usize::BITS as usize {
+        address
+    } else {
+        let unused = usize::BITS as usize - bits;
+        (((address << unused) as isize) >> unused) as usize
+    }
+}
+
+/// A run of consecutive leaf entries with the same page size and
+/// permissions, mapping contiguous virtual memory onto contiguous
+/// physical memory.
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    /// the first virtual address in the region
+    pub virtual_start: VirtAddr,
+    /// the last virtual address in the region, inclusive so a region
+    /// reaching the top of the address space has an end
+    pub virtual_last: VirtAddr,
+    /// the physical address mapped at the start of the region
+    pub physical_start: PhysAddr,
+    /// the flags of the first entry, the regions's entries only differ in accessed/dirty bits
+    pub flags: EntryFlags,
+    /// the number of bytes mapped by each entry
+    pub page_size: usize,
+}
+
+impl Region {
+    /// the number of bytes in the region
+    pub fn size(&self) -> usize {
+        self.virtual_last.as_usize() - self.virtual_start.as_usize() + 1
+    }
+    /// checks if the leaf continues this region
+    fn continued_by<K: PageTableKind>(&self, leaf: &Leaf<'_, K>) -> bool {
+        let ignored = EntryFlags::new()
+            .with_accessed(true)
+            .with_dirty(true)
+            .as_u16();
+        leaf.size() == self.page_size
+            && Some(leaf.virtual_address()) == self.virtual_last.checked_add(1)
+            && Some(leaf.physical_address()) == self.physical_start.checked_add(self.size())
+            && leaf.flags().as_u16() & !ignored == self.flags.as_u16() & !ignored
+    }
+}
+
+impl core::fmt::Display for Region {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let flags = self.flags;
+        write!(
+            f,
+            "{}-{} -> {} {:?}{}{} ",
+            self.virtual_start,
+            self.virtual_last,
+            self.physical_start,
+            flags,
+            if flags.is_user() { 'U' } else { '-' },
+            if flags.is_global() { 'G' } else { '-' },
+        )?;
+        match self.page_size {
+            size if size >= 1 << 30 => write!(f, "{}G", size >> 30)?,
+            size if size >= 1 << 20 => write!(f, "{}M", size >> 20)?,
+            size => write!(f, "{}K", size >> 10)?,
+        }
+        write!(f, " x{}", self.size() / self.page_size)
+    }
+}
+
+/// Iterator over the mapped regions of a page table.
+///
+/// Created by [`PageTable::regions`].
+pub struct Regions<'a, K>
+where
+    K: PageTableKind,
+{
+    leaves: Leaves<'a, K>,
+    pending: Option<Region>,
+}
+
+impl<'a, K> Regions<'a, K>
+where
+    K: PageTableKind + core::fmt::Debug + Copy,
+{
+    pub(super) fn new(leaves: Leaves<'a, K>) -> Regions<'a, K> {
+        Regions {
+            leaves,
+            pending: None,
+        }
+    }
+}
+
+impl<'a, K> Iterator for Regions<'a, K>
+where
+    K: PageTableKind + core::fmt::Debug + Copy,
+{
+    type Item = Region;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for leaf in self.leaves.by_ref() {
+            match self.pending.as_mut() {
+                Some(region) if region.continued_by(&leaf) => {
+                    region.virtual_last = leaf.last_virtual_address();
+                }
+                _ => {
+                    let region = Region {
+                        virtual_start: leaf.virtual_address(),
+                        virtual_last: leaf.last_virtual_address(),
+                        physical_start: leaf.physical_address(),
+                        flags: leaf.flags(),
+                        page_size: leaf.size(),
+                    };
+                    if let Some(finished) = self.pending.replace(region) {
+                        return Some(finished);
+                    }
+                }
+            }
+        }
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{format, vec::Vec};
+
+    use super::super::{
+        test_tables::{empty, map_page},
+        thirty_nine::Sv39,
+        PageTable,
+    };
+    use super::{Leaf, Region};
+    use crate::mmu::{EntryFlags, PageSize, VirtAddr};
+
+    const PHYS: usize = 0x8000_0000;
+
+    fn regions(table: &PageTable<Sv39>) -> Vec<Region> {
+        table
+            .regions(VirtAddr::new(0), VirtAddr::new(usize::MAX))
+            .collect()
+    }
+
+    fn span(region: &Region) -> (usize, usize, usize) {
+        (
+            region.virtual_start.as_usize(),
+            region.virtual_last.as_usize(),
+            region.physical_start.as_usize(),
+        )
+    }
+
+    #[test]
+    fn contiguous_pages_coalesce() {
+        let table = empty();
+        let accessed = EntryFlags::READ_WRITE.with_accessed(true).with_dirty(true);
+        map_page(&table, 0x1000, PHYS, EntryFlags::READ_WRITE, PageSize::Page);
+        map_page(&table, 0x2000, PHYS + 0x1000, accessed, PageSize::Page);
+        map_page(
+            &table,
+            0x3000,
+            PHYS + 0x2000,
+            EntryFlags::READ_WRITE,
+            PageSize::Page,
+        );
+        let regions = regions(&table);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(span(&regions[0]), (0x1000, 0x3fff, PHYS));
+        assert_eq!(regions[0].size(), 0x3000);
+        assert_eq!(regions[0].page_size, 0x1000);
+    }
+
+    #[test]
+    fn regions_split_on_any_difference() {
+        let table = empty();
+        let read_write = EntryFlags::READ_WRITE;
+        map_page(&table, 0x1000, PHYS, read_write, PageSize::Page);
+        // physical gap
+        map_page(&table, 0x2000, PHYS + 0x3000, read_write, PageSize::Page);
+        // different permissions
+        map_page(
+            &table,
+            0x3000,
+            PHYS + 0x4000,
+            EntryFlags::READ,
+            PageSize::Page,
+        );
+        // virtual gap
+        map_page(
+            &table,
+            0x5000,
+            PHYS + 0x5000,
+            EntryFlags::READ,
+            PageSize::Page,
+        );
+        // different page size, though contiguous
+        map_page(
+            &table,
+            0x1ff000,
+            PHYS + 0x1ff000,
+            read_write,
+            PageSize::Page,
+        );
+        map_page(
+            &table,
+            0x200000,
+            PHYS + 0x200000,
+            read_write,
+            PageSize::Megapage,
+        );
+        let spans: Vec<_> = regions(&table).iter().map(span).collect();
+        assert_eq!(
+            spans,
+            [
+                (0x1000, 0x1fff, PHYS),
+                (0x2000, 0x2fff, PHYS + 0x3000),
+                (0x3000, 0x3fff, PHYS + 0x4000),
+                (0x5000, 0x5fff, PHYS + 0x5000),
+                (0x1ff000, 0x1fffff, PHYS + 0x1ff000),
+                (0x200000, 0x3fffff, PHYS + 0x200000),
+            ]
+        );
+    }
+
+    #[test]
+    fn region_at_the_top_of_the_address_space() {
+        let table = empty();
+        let top = usize::MAX - 0xfff;
+        map_page(&table, top - 0x1000, PHYS, EntryFlags::READ, PageSize::Page);
+        map_page(&table, top, PHYS + 0x1000, EntryFlags::READ, PageSize::Page);
+        let regions = regions(&table);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(span(&regions[0]), (top - 0x1000, usize::MAX, PHYS));
+        assert_eq!(regions[0].size(), 0x2000);
+    }
+
+    #[test]
+    fn display() {
+        let table = empty();
+        let kernel = EntryFlags::READ_EXECUTE.with_global(true);
+        let user = EntryFlags::USER_READ_WRITE;
+        map_page(&table, 0x1000, PHYS, kernel, PageSize::Page);
+        map_page(&table, 0x2000, PHYS + 0x1000, kernel, PageSize::Page);
+        map_page(&table, 0x4000_0000, PHYS, user, PageSize::GigaPage);
+        let lines: Vec<_> = regions(&table).iter().map(|r| format!("{}", r)).collect();
+        assert_eq!(
+            lines,
+            [
+                "0x1000-0x2fff -> 0x80000000 R-E-G 4K x2",
+                "0x40000000-0x7fffffff -> 0x80000000 RW-U- 1G x1",
+            ]
+        );
+    }
+
+    /// a page and a megapage mapped with A and D, with G and a software bit to be left alone
+    fn accessed_dirty_pages(table: &PageTable<Sv39>) -> EntryFlags {
+        let flags = EntryFlags::USER_READ_WRITE
+            .with_global(true)
+            .with_copy_on_write(true)
+            .with_accessed(true)
+            .with_dirty(true);
+        map_page(table, 0x1000, PHYS + 0x1000, flags, PageSize::Page);
+        map_page(
+            table,
+            0x20_0000,
+            PHYS + 0x20_0000,
+            flags,
+            PageSize::Megapage,
+        );
+        flags.with_valid(true)
+    }
+
+    fn leaves(table: &PageTable<Sv39>) -> Vec<Leaf<'_, Sv39>> {
+        table
+            .leaves(VirtAddr::new(0), VirtAddr::new(usize::MAX))
+            .collect()
+    }
+
+    /// every leaf has the expected flags, and still maps its frame
+    fn assert_leaves(table: &PageTable<Sv39>, flags: EntryFlags) {
+        let leaves = leaves(table);
+        assert_eq!(leaves.len(), 2);
+        for leaf in leaves {
+            assert_eq!(leaf.flags().as_u16(), flags.as_u16());
+            assert_eq!(
+                leaf.physical_address().as_usize(),
+                PHYS + leaf.virtual_address().as_usize()
+            );
+        }
+    }
+
+    #[test]
+    fn clear_accessed() {
+        let table = empty();
+        let flags = accessed_dirty_pages(&table);
+        for leaf in leaves(&table) {
+            assert!(leaf.clear_accessed());
+            assert!(!leaf.clear_accessed());
+        }
+        assert_leaves(&table, flags.with_accessed(false));
+    }
+
+    #[test]
+    fn clear_dirty() {
+        let table = empty();
+        let flags = accessed_dirty_pages(&table);
+        for leaf in leaves(&table) {
+            assert!(leaf.clear_dirty());
+            assert!(!leaf.clear_dirty());
+        }
+        assert_leaves(&table, flags.with_dirty(false));
+    }
+
+    #[test]
+    fn clear_accessed_dirty() {
+        let table = empty();
+        let flags = accessed_dirty_pages(&table);
+        for leaf in leaves(&table) {
+            assert_eq!(leaf.clear_accessed_dirty(), (true, true));
+            assert_eq!(leaf.clear_accessed_dirty(), (false, false));
+        }
+        assert_leaves(&table, flags.with_accessed(false).with_dirty(false));
+    }
+}
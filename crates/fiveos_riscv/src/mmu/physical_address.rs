@@ -1,4 +1,154 @@
-trait PhysicalAddress {
-    fn as_u64(&self) -> u64;
-    fn as_usize(&self) -> usize;
+use core::fmt::{Debug, Display};
+
+use super::page_table::PageTableKind;
+
+/// An address in physical memory, as found in page table entries.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(transparent)]
+pub struct PhysAddr(usize);
+
+impl PhysAddr {
+    #[inline]
+    pub const fn new(address: usize) -> PhysAddr {
+        PhysAddr(address)
+    }
+    /// Produces an address only if it fits in the given kind of page table's entries
+    #[inline]
+    pub fn new_checked<K: PageTableKind>(address: usize, kind: &K) -> Option<PhysAddr> {
+        let address = PhysAddr(address);
+        address.is_valid_in(kind).then_some(address)
+    }
+    #[inline]
+    pub const fn as_usize(self) -> usize {
+        self.0
+    }
+    #[inline]
+    pub const fn as_u64(self) -> u64 {
+        self.0 as u64
+    }
+    /// Checks that the address can be stored in the given kind of page table
+    #[inline]
+    pub fn is_valid_in<K: PageTableKind>(self, kind: &K) -> bool {
+        let bits = kind.physical_address_size();
+        bits >= usize::BITS as usize || self.0 >> bits == 0
+    }
+    /// The segment of the physical page number stored at the given level
+    #[inline]
+    pub fn ppn<K: PageTableKind>(self, kind: &K, level: usize) -> usize {
+        let (bits, offset) = kind.physical_segments()[level];
+        (self.0 >> offset) & ((1 << bits) - 1)
+    }
+    /// The whole physical page number, e.g. as stored in satp
+    #[inline]
+    pub const fn page_number(self) -> usize {
+        self.0 >> super::page_table::PAGE_ADDR_MAGNITIDE
+    }
+    #[inline]
+    pub const fn page_offset(self) -> usize {
+        self.0 & super::page_table::PAGE_ADDR_MASK
+    }
+    /// rounds the address down to a multiple of alignment, which must be a power of two
+    #[inline]
+    pub const fn align_down(self, alignment: usize) -> PhysAddr {
+        PhysAddr(self.0 & !(alignment - 1))
+    }
+    /// rounds the address up to a multiple of alignment, which must be a power of two
+    #[inline]
+    pub const fn align_up(self, alignment: usize) -> PhysAddr {
+        PhysAddr(super::align_to(self.0, alignment))
+    }
+    /// rounds the address up to a multiple of alignment, which must be a power
+    /// of two, or None if that is past the end of the address space
+    #[inline]
+    pub const fn checked_align_up(self, alignment: usize) -> Option<PhysAddr> {
+        match self.0.checked_add(alignment - 1) {
+            Some(address) => Some(PhysAddr(address & !(alignment - 1))),
+            None => None,
+        }
+    }
+    #[inline]
+    pub const fn is_aligned(self, alignment: usize) -> bool {
+        self.0 & (alignment - 1) == 0
+    }
+    #[inline]
+    pub const fn checked_add(self, bytes: usize) -> Option<PhysAddr> {
+        match self.0.checked_add(bytes) {
+            Some(address) => Some(PhysAddr(address)),
+            None => None,
+        }
+    }
+    #[inline]
+    pub const fn checked_sub(self, bytes: usize) -> Option<PhysAddr> {
+        match self.0.checked_sub(bytes) {
+            Some(address) => Some(PhysAddr(address)),
+            None => None,
+        }
+    }
+    /// The number of bytes from the other address up to this one
+    #[inline]
+    pub const fn checked_offset_from(self, other: PhysAddr) -> Option<usize> {
+        self.0.checked_sub(other.0)
+    }
+}
+
+impl From<PhysAddr> for usize {
+    fn from(address: PhysAddr) -> usize {
+        address.0
+    }
+}
+
+impl Debug for PhysAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "PhysAddr(0x{:x})", self.0)
+    }
+}
+
+impl Display for PhysAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "0x{:x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PhysAddr;
+    use crate::mmu::page_table::{thirty_nine::Sv39, thirty_two::Sv32};
+
+    #[test]
+    fn valid_in_kind() {
+        // Sv39 stores 56 bit physical addresses, Sv32 34 bits
+        assert!(PhysAddr::new((1 << 56) - 1).is_valid_in(&Sv39));
+        assert!(!PhysAddr::new(1 << 56).is_valid_in(&Sv39));
+        assert_eq!(PhysAddr::new_checked(1 << 34, &Sv32), None);
+        assert_eq!(
+            PhysAddr::new_checked((1 << 34) - 1, &Sv32),
+            Some(PhysAddr::new((1 << 34) - 1))
+        );
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let top = PhysAddr::new(usize::MAX);
+        assert_eq!(top.checked_add(1), None);
+        assert_eq!(PhysAddr::new(0).checked_sub(1), None);
+        assert_eq!(
+            PhysAddr::new(0x8000_0000).checked_add(0x1000),
+            Some(PhysAddr::new(0x8000_1000))
+        );
+        assert_eq!(
+            PhysAddr::new(0x2000).checked_offset_from(PhysAddr::new(0x1000)),
+            Some(0x1000)
+        );
+        assert_eq!(
+            PhysAddr::new(0x1000).checked_offset_from(PhysAddr::new(0x2000)),
+            None
+        );
+        assert_eq!(
+            PhysAddr::new(0x1001).checked_align_up(0x1000),
+            Some(PhysAddr::new(0x2000))
+        );
+        assert_eq!(PhysAddr::new(usize::MAX).checked_align_up(0x1000), None);
+        assert_eq!(PhysAddr::new(0x8000_1234).page_number(), 0x80001);
+        assert_eq!(PhysAddr::new(0x8000_1234).page_offset(), 0x234);
+    }
 }
@@ -1,4 +1,208 @@
-pub trait VirtualAddress {
-    fn extract_page_index(&self, level: usize) -> u64;
-    fn as_u64(&self) -> u64;
+use core::fmt::{Debug, Display};
+
+use super::{page_table::PageTableKind, TableTypes};
+
+/// An address in a virtual address space, as translated by a page table.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[repr(transparent)]
+pub struct VirtAddr(usize);
+
+impl VirtAddr {
+    #[inline]
+    pub const fn new(address: usize) -> VirtAddr {
+        VirtAddr(address)
+    }
+    /// Produces an address only if it is canonical for the given kind of page table
+    #[inline]
+    pub fn new_canonical<K: PageTableKind>(address: usize, kind: &K) -> Option<VirtAddr> {
+        let address = VirtAddr(address);
+        address.is_canonical_in(kind).then_some(address)
+    }
+    #[inline]
+    pub const fn as_usize(self) -> usize {
+        self.0
+    }
+    /// Checks that the unused high bits of the address all match the highest
+    /// used bit, as required of addresses translated in this mode.
+    ///
+    /// Every address is canonical with translation off, or in Sv32.
+    #[inline]
+    pub const fn is_canonical(self, mode: TableTypes) -> bool {
+        match mode {
+            TableTypes::None | TableTypes::Sv32 => true,
+            TableTypes::Sv39 => self.is_sign_extended(39),
+            TableTypes::Sv48 => self.is_sign_extended(48),
+            TableTypes::Sv57 => self.is_sign_extended(57),
+        }
+    }
+    /// Checks that the address is canonical for the given kind of page table
+    #[inline]
+    pub fn is_canonical_in<K: PageTableKind>(self, kind: &K) -> bool {
+        self.is_sign_extended(kind.virtual_address_size())
+    }
+    const fn is_sign_extended(self, bits: usize) -> bool {
+        if bits >= usize::BITS as usize {
+            return true;
+        }
+        let high = (self.0 as isize) >> (bits - 1);
+        high == 0 || high == -1
+    }
+    /// The virtual page number used to index the page table at the given level
+    #[inline]
+    pub fn vpn<K: PageTableKind>(self, kind: &K, level: usize) -> usize {
+        let (bits, offset) = kind.virtual_segments()[level];
+        (self.0 >> offset) & ((1 << bits) - 1)
+    }
+    /// The low bits of the address, which are not translated
+    #[inline]
+    pub const fn page_offset(self) -> usize {
+        self.0 & super::page_table::PAGE_ADDR_MASK
+    }
+    /// rounds the address down to a multiple of alignment, which must be a power of two
+    #[inline]
+    pub const fn align_down(self, alignment: usize) -> VirtAddr {
+        VirtAddr(self.0 & !(alignment - 1))
+    }
+    /// rounds the address up to a multiple of alignment, which must be a power of two
+    #[inline]
+    pub const fn align_up(self, alignment: usize) -> VirtAddr {
+        VirtAddr(super::align_to(self.0, alignment))
+    }
+    /// rounds the address up to a multiple of alignment, which must be a power
+    /// of two, or None if that is past the end of the address space
+    #[inline]
+    pub const fn checked_align_up(self, alignment: usize) -> Option<VirtAddr> {
+        match self.0.checked_add(alignment - 1) {
+            Some(address) => Some(VirtAddr(address & !(alignment - 1))),
+            None => None,
+        }
+    }
+    #[inline]
+    pub const fn is_aligned(self, alignment: usize) -> bool {
+        self.0 & (alignment - 1) == 0
+    }
+    #[inline]
+    pub const fn checked_add(self, bytes: usize) -> Option<VirtAddr> {
+        match self.0.checked_add(bytes) {
+            Some(address) => Some(VirtAddr(address)),
+            None => None,
+        }
+    }
+    #[inline]
+    pub const fn checked_sub(self, bytes: usize) -> Option<VirtAddr> {
+        match self.0.checked_sub(bytes) {
+            Some(address) => Some(VirtAddr(address)),
+            None => None,
+        }
+    }
+    /// The number of bytes from the other address up to this one
+    #[inline]
+    pub const fn checked_offset_from(self, other: VirtAddr) -> Option<usize> {
+        self.0.checked_sub(other.0)
+    }
+}
+
+impl From<VirtAddr> for usize {
+    fn from(address: VirtAddr) -> usize {
+        address.0
+    }
+}
+
+impl Debug for VirtAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "VirtAddr(0x{:x})", self.0)
+    }
+}
+
+impl Display for VirtAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "0x{:x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtAddr;
+    use crate::mmu::{
+        page_table::{forty_eight::Sv48, thirty_nine::Sv39},
+        TableTypes,
+    };
+
+    /// the lowest and highest addresses of the upper and lower halves,
+    /// and the first non-canonical address above the lower half
+    fn halves(bits: u32) -> (usize, usize, usize, usize) {
+        let half = 1 << (bits - 1);
+        (0, half - 1, half.wrapping_neg(), half)
+    }
+
+    #[test]
+    fn canonical() {
+        for (mode, bits) in [
+            (TableTypes::Sv39, 39),
+            (TableTypes::Sv48, 48),
+            (TableTypes::Sv57, 57),
+        ] {
+            let (low, low_top, high, gap) = halves(bits);
+            for address in [low, low_top, high, usize::MAX] {
+                assert!(VirtAddr::new(address).is_canonical(mode), "{:x}", address);
+            }
+            for address in [gap, high - 1, 1 << (usize::BITS - 1)] {
+                assert!(!VirtAddr::new(address).is_canonical(mode), "{:x}", address);
+            }
+        }
+        for mode in [TableTypes::None, TableTypes::Sv32] {
+            assert!(VirtAddr::new(1 << 40).is_canonical(mode));
+        }
+    }
+
+    #[test]
+    fn canonical_in_kind() {
+        let (_, low_top, high, gap) = halves(39);
+        assert!(VirtAddr::new(low_top).is_canonical_in(&Sv39));
+        assert!(VirtAddr::new(high).is_canonical_in(&Sv39));
+        assert!(!VirtAddr::new(gap).is_canonical_in(&Sv39));
+        assert_eq!(VirtAddr::new_canonical(gap, &Sv39), None);
+        assert_eq!(
+            VirtAddr::new_canonical(gap, &Sv48),
+            Some(VirtAddr::new(gap))
+        );
+        let (_, _, _, gap) = halves(48);
+        assert_eq!(VirtAddr::new_canonical(gap, &Sv48), None);
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let top = VirtAddr::new(usize::MAX);
+        assert_eq!(top.checked_add(1), None);
+        assert_eq!(top.checked_add(0), Some(top));
+        assert_eq!(VirtAddr::new(0).checked_sub(1), None);
+        assert_eq!(
+            VirtAddr::new(0x1000).checked_sub(1),
+            Some(VirtAddr::new(0xfff))
+        );
+        assert_eq!(
+            VirtAddr::new(0x1000).checked_offset_from(VirtAddr::new(0x800)),
+            Some(0x800)
+        );
+        assert_eq!(
+            VirtAddr::new(0x800).checked_offset_from(VirtAddr::new(0x1000)),
+            None
+        );
+        assert_eq!(
+            VirtAddr::new(0x1001).checked_align_up(0x1000),
+            Some(VirtAddr::new(0x2000))
+        );
+        assert_eq!(
+            VirtAddr::new(0x1000).checked_align_up(0x1000),
+            Some(VirtAddr::new(0x1000))
+        );
+        assert_eq!(
+            VirtAddr::new(usize::MAX - 0xffe).checked_align_up(0x1000),
+            None
+        );
+        assert_eq!(
+            VirtAddr::new(0x1fff).align_down(0x1000),
+            VirtAddr::new(0x1000)
+        );
+    }
 }
@@ -0,0 +1,135 @@
+//! Calls from supervisor mode to the Supervisor Binary Interface, v2.0,
+//! implemented by machine mode firmware such as OpenSBI or five_os's own.
+//!
+//! Each extension is a module holding its ID, the IDs of its functions
+//! and a wrapper for each function. Calls put the extension ID in a7, the
+//! function ID in a6 and arguments from a0, and return an error code in a0
+//! and a value in a1.
+
+pub mod base;
+pub mod dbcn;
+pub mod hsm;
+pub mod ipi;
+pub mod rfence;
+pub mod srst;
+pub mod time;
+
+/// Errors an SBI call can return, in place of its value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SbiError {
+    Failed,
+    NotSupported,
+    InvalidParam,
+    Denied,
+    InvalidAddress,
+    AlreadyAvailable,
+    AlreadyStarted,
+    AlreadyStopped,
+    NoSharedMemory,
+    InvalidState,
+    BadRange,
+    Timeout,
+    Io,
+    /// an error code not defined by the spec
+    Unknown(isize),
+}
+
+impl SbiError {
+    pub const fn from_code(code: isize) -> SbiError {
+        use SbiError::*;
+        match code {
+            -1 => Failed,
+            -2 => NotSupported,
+            -3 => InvalidParam,
+            -4 => Denied,
+            -5 => InvalidAddress,
+            -6 => AlreadyAvailable,
+            -7 => AlreadyStarted,
+            -8 => AlreadyStopped,
+            -9 => NoSharedMemory,
+            -10 => InvalidState,
+            -11 => BadRange,
+            -12 => Timeout,
+            -13 => Io,
+            _ => Unknown(code),
+        }
+    }
+    pub const fn code(self) -> isize {
+        use SbiError::*;
+        match self {
+            Failed => -1,
+            NotSupported => -2,
+            InvalidParam => -3,
+            Denied => -4,
+            InvalidAddress => -5,
+            AlreadyAvailable => -6,
+            AlreadyStarted => -7,
+            AlreadyStopped => -8,
+            NoSharedMemory => -9,
+            InvalidState => -10,
+            BadRange => -11,
+            Timeout => -12,
+            Io => -13,
+            Unknown(code) => code,
+        }
+    }
+}
+
+pub type SbiResult<T> = Result<T, SbiError>;
+
+/// The harts a call applies to: those with a set bit in mask, where bit 0
+/// is hart base, or every hart if base is [`HartMask::ALL_BASE`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HartMask {
+    pub mask: usize,
+    pub base: usize,
+}
+
+impl HartMask {
+    /// the base which selects every hart, ignoring the mask
+    pub const ALL_BASE: usize = usize::MAX;
+    pub const ALL: HartMask = HartMask {
+        mask: 0,
+        base: HartMask::ALL_BASE,
+    };
+    pub const fn single(hart: usize) -> HartMask {
+        HartMask {
+            mask: 1,
+            base: hart,
+        }
+    }
+    pub const fn is_all(&self) -> bool {
+        self.base == HartMask::ALL_BASE
+    }
+    pub const fn contains(&self, hart: usize) -> bool {
+        self.is_all()
+            || (hart >= self.base
+                && hart - self.base < usize::BITS as usize
+                && self.mask & (1 << (hart - self.base)) != 0)
+    }
+}
+
+/// Makes an SBI call with up to 6 arguments.
+///
+/// ## Safety
+/// The call may do anything firmware can, the wrappers in each extension's
+/// module say what the caller must ensure for theirs.
+#[inline]
+pub unsafe fn call(extension: usize, function: usize, args: [usize; 6]) -> SbiResult<usize> {
+    let (error, value): (isize, usize);
+    riscv_asm!(
+        "ecall",
+        inlateout("a0") args[0] => error,
+        inlateout("a1") args[1] => value,
+        in("a2") args[2],
+        in("a3") args[3],
+        in("a4") args[4],
+        in("a5") args[5],
+        in("a6") function,
+        in("a7") extension,
+    );
+    match error {
+        0 => Ok(value),
+        error => Err(SbiError::from_code(error)),
+    }
+}
@@ -0,0 +1,86 @@
+//! The base extension, which every SBI implementation provides
+
+use super::call;
+
+pub const EXTENSION: usize = 0x10;
+pub const GET_SPEC_VERSION: usize = 0;
+pub const GET_IMPL_ID: usize = 1;
+pub const GET_IMPL_VERSION: usize = 2;
+pub const PROBE_EXTENSION: usize = 3;
+pub const GET_MVENDORID: usize = 4;
+pub const GET_MARCHID: usize = 5;
+pub const GET_MIMPID: usize = 6;
+
+/// Version of the SBI spec, as returned by [`spec_version`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpecVersion {
+    pub major: usize,
+    pub minor: usize,
+}
+
+impl SpecVersion {
+    pub const fn from_bits(bits: usize) -> SpecVersion {
+        SpecVersion {
+            major: (bits >> 24) & 0x7f,
+            minor: bits & 0xff_ffff,
+        }
+    }
+    pub const fn bits(self) -> usize {
+        (self.major & 0x7f) << 24 | (self.minor & 0xff_ffff)
+    }
+}
+
+/// Names of the registered SBI implementation IDs
+pub const fn implementation_name(id: usize) -> Option<&'static str> {
+    match id {
+        0 => Some("Berkeley Boot Loader"),
+        1 => Some("OpenSBI"),
+        2 => Some("Xvisor"),
+        3 => Some("KVM"),
+        4 => Some("RustSBI"),
+        5 => Some("Diosix"),
+        6 => Some("Coffer"),
+        7 => Some("Xen Project"),
+        8 => Some("PolarFire Hart Software Services"),
+        9 => Some("coreboot"),
+        10 => Some("oreboot"),
+        11 => Some("bhyve"),
+        _ => None,
+    }
+}
+
+fn base_call(function: usize, argument: usize) -> usize {
+    // safety: the base functions only report on the implementation, and
+    // can't fail other than for an unknown extension in probe_extension
+    unsafe { call(EXTENSION, function, [argument, 0, 0, 0, 0, 0]) }.unwrap_or(0)
+}
+
+pub fn spec_version() -> SpecVersion {
+    SpecVersion::from_bits(base_call(GET_SPEC_VERSION, 0))
+}
+
+pub fn implementation_id() -> usize {
+    base_call(GET_IMPL_ID, 0)
+}
+
+pub fn implementation_version() -> usize {
+    base_call(GET_IMPL_VERSION, 0)
+}
+
+/// Checks if the implementation provides an extension
+pub fn probe_extension(extension: usize) -> bool {
+    base_call(PROBE_EXTENSION, extension) != 0
+}
+
+/// The hart's mvendorid, which supervisor mode can't read itself
+pub fn mvendorid() -> usize {
+    base_call(GET_MVENDORID, 0)
+}
+
+pub fn marchid() -> usize {
+    base_call(GET_MARCHID, 0)
+}
+
+pub fn mimpid() -> usize {
+    base_call(GET_MIMPID, 0)
+}
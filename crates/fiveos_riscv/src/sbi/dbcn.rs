@@ -0,0 +1,36 @@
+//! The debug console extension, "DBCN", for early or last-resort output.
+//!
+//! Buffers are passed by physical address, the low bits in one argument and
+//! the high bits, which are always zero here, in the next.
+
+use super::{call, SbiResult};
+
+pub const EXTENSION: usize = 0x4442_434e;
+pub const CONSOLE_WRITE: usize = 0;
+pub const CONSOLE_READ: usize = 1;
+pub const CONSOLE_WRITE_BYTE: usize = 2;
+
+/// Writes up to length bytes from the physical address, returning how many were written
+///
+/// ## Safety
+/// The bytes must be readable at that physical address.
+pub unsafe fn console_write(physical_address: usize, length: usize) -> SbiResult<usize> {
+    let args = [length, physical_address, 0, 0, 0, 0];
+    call(EXTENSION, CONSOLE_WRITE, args)
+}
+
+/// Reads up to length bytes into the physical address, returning how many were read
+///
+/// ## Safety
+/// The bytes must be writable at that physical address, and not otherwise in use.
+pub unsafe fn console_read(physical_address: usize, length: usize) -> SbiResult<usize> {
+    let args = [length, physical_address, 0, 0, 0, 0];
+    call(EXTENSION, CONSOLE_READ, args)
+}
+
+/// Writes a byte, waiting until it can be
+pub fn console_write_byte(byte: u8) -> SbiResult<()> {
+    let args = [byte as usize, 0, 0, 0, 0, 0];
+    // safety: only writes to the console
+    unsafe { call(EXTENSION, CONSOLE_WRITE_BYTE, args) }.map(|_| ())
+}
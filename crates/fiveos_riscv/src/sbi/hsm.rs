@@ -0,0 +1,82 @@
+//! The hart state management extension, "HSM", which starts and stops harts
+
+use super::{call, SbiError, SbiResult};
+
+pub const EXTENSION: usize = 0x0048_534d;
+pub const HART_START: usize = 0;
+pub const HART_STOP: usize = 1;
+pub const HART_GET_STATUS: usize = 2;
+pub const HART_SUSPEND: usize = 3;
+
+/// Suspend type which keeps the hart's state, resuming after the call
+pub const SUSPEND_RETENTIVE: usize = 0;
+/// Suspend type which loses the hart's state, resuming at a given address
+pub const SUSPEND_NON_RETENTIVE: usize = 0x8000_0000;
+
+/// State of a hart, as reported by [`hart_get_status`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HartState {
+    Started,
+    Stopped,
+    StartPending,
+    StopPending,
+    Suspended,
+    SuspendPending,
+    ResumePending,
+}
+
+impl HartState {
+    pub const fn from_code(code: usize) -> Option<HartState> {
+        use HartState::*;
+        match code {
+            0 => Some(Started),
+            1 => Some(Stopped),
+            2 => Some(StartPending),
+            3 => Some(StopPending),
+            4 => Some(Suspended),
+            5 => Some(SuspendPending),
+            6 => Some(ResumePending),
+            _ => None,
+        }
+    }
+    pub const fn code(self) -> usize {
+        self as usize
+    }
+}
+
+/// Starts a stopped hart in supervisor mode at the physical address start,
+/// with translation off, its hart id in a0 and opaque in a1.
+///
+/// ## Safety
+/// start must be code which can run on the hart from those registers alone.
+pub unsafe fn hart_start(hart: usize, start: usize, opaque: usize) -> SbiResult<()> {
+    call(EXTENSION, HART_START, [hart, start, opaque, 0, 0, 0]).map(|_| ())
+}
+
+/// Stops this hart, returning only if it can't be stopped
+///
+/// ## Safety
+/// Anything the hart was doing is abandoned.
+pub unsafe fn hart_stop() -> SbiError {
+    match call(EXTENSION, HART_STOP, [0; 6]) {
+        Ok(_) => SbiError::Failed,
+        Err(error) => error,
+    }
+}
+
+pub fn hart_get_status(hart: usize) -> SbiResult<HartState> {
+    // safety: only reports on the hart
+    let code = unsafe { call(EXTENSION, HART_GET_STATUS, [hart, 0, 0, 0, 0, 0]) }?;
+    HartState::from_code(code).ok_or(SbiError::Failed)
+}
+
+/// Suspends this hart until an interrupt, or a platform specific event.
+/// A retentive suspend returns, a non-retentive one resumes at the physical
+/// address resume as hart_start would.
+///
+/// ## Safety
+/// For non-retentive suspends, as [`hart_start`].
+pub unsafe fn hart_suspend(suspend_type: usize, resume: usize, opaque: usize) -> SbiResult<()> {
+    let args = [suspend_type, resume, opaque, 0, 0, 0];
+    call(EXTENSION, HART_SUSPEND, args).map(|_| ())
+}
@@ -0,0 +1,12 @@
+//! The inter-processor interrupt extension, "sPI"
+
+use super::{call, HartMask, SbiResult};
+
+pub const EXTENSION: usize = 0x0073_5049;
+pub const SEND_IPI: usize = 0;
+
+/// Raises a supervisor software interrupt on each of the harts
+pub fn send_ipi(harts: HartMask) -> SbiResult<()> {
+    // safety: the interrupt is only a signal, handled like any other
+    unsafe { call(EXTENSION, SEND_IPI, [harts.mask, harts.base, 0, 0, 0, 0]) }.map(|_| ())
+}
@@ -0,0 +1,36 @@
+//! The remote fence extension, "RFNC", which runs fences on other harts
+
+use super::{call, HartMask, SbiResult};
+
+pub const EXTENSION: usize = 0x5246_4e43;
+pub const REMOTE_FENCE_I: usize = 0;
+pub const REMOTE_SFENCE_VMA: usize = 1;
+pub const REMOTE_SFENCE_VMA_ASID: usize = 2;
+
+/// Runs fence.i on the harts, after this hart has written instructions
+pub fn remote_fence_i(harts: HartMask) -> SbiResult<()> {
+    let args = [harts.mask, harts.base, 0, 0, 0, 0];
+    // safety: fences only make earlier writes visible
+    unsafe { call(EXTENSION, REMOTE_FENCE_I, args) }.map(|_| ())
+}
+
+/// Runs sfence.vma on the harts for the virtual addresses from start,
+/// after this hart has changed their mapping. A size of usize::MAX
+/// flushes every address.
+pub fn remote_sfence_vma(harts: HartMask, start: usize, size: usize) -> SbiResult<()> {
+    let args = [harts.mask, harts.base, start, size, 0, 0];
+    // safety: see remote_fence_i
+    unsafe { call(EXTENSION, REMOTE_SFENCE_VMA, args) }.map(|_| ())
+}
+
+/// As remote_sfence_vma, only for the address space of asid
+pub fn remote_sfence_vma_asid(
+    harts: HartMask,
+    start: usize,
+    size: usize,
+    asid: usize,
+) -> SbiResult<()> {
+    let args = [harts.mask, harts.base, start, size, asid, 0];
+    // safety: see remote_fence_i
+    unsafe { call(EXTENSION, REMOTE_SFENCE_VMA_ASID, args) }.map(|_| ())
+}
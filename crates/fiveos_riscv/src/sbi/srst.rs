@@ -0,0 +1,55 @@
+//! The system reset extension, "SRST"
+
+use super::{call, SbiError};
+
+pub const EXTENSION: usize = 0x5352_5354;
+pub const SYSTEM_RESET: usize = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetType {
+    Shutdown = 0,
+    ColdReboot = 1,
+    WarmReboot = 2,
+}
+
+impl ResetType {
+    pub const fn from_code(code: usize) -> Option<ResetType> {
+        match code {
+            0 => Some(ResetType::Shutdown),
+            1 => Some(ResetType::ColdReboot),
+            2 => Some(ResetType::WarmReboot),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetReason {
+    NoReason = 0,
+    SystemFailure = 1,
+}
+
+impl ResetReason {
+    pub const fn from_code(code: usize) -> Option<ResetReason> {
+        match code {
+            0 => Some(ResetReason::NoReason),
+            1 => Some(ResetReason::SystemFailure),
+            _ => None,
+        }
+    }
+}
+
+/// Shuts down or reboots the system, returning only if that failed
+///
+/// ## Safety
+/// Everything running is abandoned.
+pub unsafe fn system_reset(reset: ResetType, reason: ResetReason) -> SbiError {
+    match call(
+        EXTENSION,
+        SYSTEM_RESET,
+        [reset as usize, reason as usize, 0, 0, 0, 0],
+    ) {
+        Ok(_) => SbiError::Failed,
+        Err(error) => error,
+    }
+}
@@ -0,0 +1,14 @@
+//! The timer extension, "TIME"
+
+use super::{call, SbiResult};
+
+pub const EXTENSION: usize = 0x5449_4d45;
+pub const SET_TIMER: usize = 0;
+
+/// Asks for a supervisor timer interrupt once the time csr reaches
+/// stime_value, clearing any which is pending. A value in the past
+/// raises it at once, and u64::MAX effectively cancels it.
+pub fn set_timer(stime_value: u64) -> SbiResult<()> {
+    // safety: only changes when this hart's timer interrupt is raised
+    unsafe { call(EXTENSION, SET_TIMER, [stime_value as usize, 0, 0, 0, 0, 0]) }.map(|_| ())
+}
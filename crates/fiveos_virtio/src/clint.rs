@@ -3,3 +3,27 @@
 pub const CLINT_BASE_ADDRESS: usize = 0x0200_0000;
 pub const CLINT_SIZE: usize = 0x1_0000;
 pub const CLINT_END_ADDRESS: usize = CLINT_BASE_ADDRESS + CLINT_SIZE;
+/// machine software interrupt pending, one u32 per hart
+const MSIP: usize = CLINT_BASE_ADDRESS;
+/// timer compare, one u64 per hart
+const MTIMECMP: usize = CLINT_BASE_ADDRESS + 0x4000;
+/// the time, shared by all harts
+const MTIME: usize = CLINT_BASE_ADDRESS + 0xbff8;
+
+/// The current time, as in the time csr
+pub fn mtime() -> u64 {
+    unsafe { (MTIME as *const u64).read_volatile() }
+}
+
+/// Raise a machine timer interrupt on the hart once mtime reaches value.
+/// The interrupt stays pending until the compare is moved past mtime.
+pub fn set_timer_compare(hart: usize, value: u64) {
+    let register = MTIMECMP as *mut u64;
+    unsafe { register.add(hart).write_volatile(value) };
+}
+
+/// Raise or clear a machine software interrupt on the hart
+pub fn set_software_pending(hart: usize, pending: bool) {
+    let register = MSIP as *mut u32;
+    unsafe { register.add(hart).write_volatile(pending as u32) };
+}
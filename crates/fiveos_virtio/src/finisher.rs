@@ -0,0 +1,31 @@
+//! VIRTIO test finisher, which QEMU's virt machine provides to power off or reset
+
+pub const FINISHER_BASE_ADDRESS: usize = 0x10_0000;
+pub const FINISHER_SIZE: usize = 0x1000;
+pub const FINISHER_END_ADDRESS: usize = FINISHER_BASE_ADDRESS + FINISHER_SIZE;
+/// exits QEMU with a failure, the exit code goes in the upper 16 bits
+const FINISHER_FAIL: u32 = 0x3333;
+/// exits QEMU successfully
+const FINISHER_PASS: u32 = 0x5555;
+/// resets the machine
+const FINISHER_RESET: u32 = 0x7777;
+
+fn finish(value: u32) {
+    let register = FINISHER_BASE_ADDRESS as *mut u32;
+    unsafe { register.write_volatile(value) };
+}
+
+/// Powers off, which returns only if there is no finisher
+pub fn power_off() {
+    finish(FINISHER_PASS);
+}
+
+/// Powers off reporting a failure, QEMU exits with the code
+pub fn fail(code: u16) {
+    finish(FINISHER_FAIL | (code as u32) << 16);
+}
+
+/// Resets the machine, which returns only if there is no finisher
+pub fn reset() {
+    finish(FINISHER_RESET);
+}
@@ -5,6 +5,7 @@ use core::marker::PhantomData;
 use uart::{Uart, Uart0};
 
 pub mod clint;
+pub mod finisher;
 pub mod plic;
 pub mod rtc;
 pub mod uart;
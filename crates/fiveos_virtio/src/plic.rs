@@ -1,13 +1,22 @@
 use core::num::NonZeroU32;
 
 pub const PLIC_BASE_ADDRESS: usize = 0x0c00_0000; // 0x20_0000
-pub const PLIC_SIZE: usize = 0x2000; //0x20_0000 + (4 * 0x1000); // note: size is 0x1000 * num_cpus, going with 4 here due to my config
+/// priorities, pending bits, and the enable bits of the first 32 contexts
+pub const PLIC_SIZE: usize = 0x3000;
 pub const PLIC_END_ADDRESS: usize = PLIC_BASE_ADDRESS + PLIC_SIZE;
-const ENABLE: usize = 0x0c00_2000;
+/// threshold and claim/complete registers, 0x1000 for each of the 2 contexts per hart
+pub const PLIC_CONTEXT_BASE_ADDRESS: usize = 0x0c20_0000;
+pub const PLIC_CONTEXT_SIZE: usize = 0x8000;
+pub const PLIC_CONTEXT_END_ADDRESS: usize = PLIC_CONTEXT_BASE_ADDRESS + PLIC_CONTEXT_SIZE;
+/// The context interrupts are routed to. Each hart has a machine mode
+/// context followed by a supervisor mode one, the kernel uses hart 0's
+/// supervisor context as it runs in supervisor mode.
+const CONTEXT: usize = 1;
+const ENABLE: usize = 0x0c00_2000 + 0x80 * CONTEXT;
 const PRIORITY: usize = 0x0c00_0000;
 const PENDING: usize = 0x0c00_1000;
-const THRESHOLD: usize = 0x0c20_0000;
-const CLAIM_COMPLETE: usize = 0x0c20_0004;
+const THRESHOLD: usize = PLIC_CONTEXT_BASE_ADDRESS + 0x1000 * CONTEXT;
+const CLAIM_COMPLETE: usize = THRESHOLD + 4;
 
 /// ZST representing access to to the PLIC
 /// todo: hide this behind some kind of
@@ -3,6 +3,8 @@
 pub const UART_BASE_ADDRESS: usize = 0x1000_0000;
 pub const UART_SIZE: usize = 0x100;
 pub const UART_END_ADDRESS: usize = UART_BASE_ADDRESS + UART_SIZE;
+/// The PLIC source the UART raises when it has received data
+pub const UART_INTERRUPT: u8 = 10;
 
 use core::{
     fmt::{Error, Write},